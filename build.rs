@@ -0,0 +1,20 @@
+// Hands the git commit the binaries are built from to the code as
+// CHAT_GIT_HASH, shown by :version. Builds outside a git checkout (or
+// without git installed) leave it unset.
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let output = Command::new("git").args(["rev-parse", "--short", "HEAD"]).output();
+    if let Ok(output) = output && output.status.success() {
+        let hash = String::from_utf8_lossy(&output.stdout);
+        println!("cargo:rustc-env=CHAT_GIT_HASH={}", hash.trim());
+    }
+    // rebuild when a commit is made or checked out
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
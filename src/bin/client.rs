@@ -1,103 +1,524 @@
 
-// Simple synchronous TCP client for the chat server. The client uses a
-// small thread to concurrently read from the server while the main thread
-// reads user input and sends messages. Fixed-size framing (MSG_SIZE) is used
-// to match the server's framing policy.
-use std::io::{self, ErrorKind, Read, Write};
-use std::net::TcpStream;
+// Interactive terminal client for the chat server, a thin layer over
+// shared::client::ChatClient. The client uses one thread to print what the
+// server sends and one to write to it while the main thread reads user
+// input. With `--tls` the connection is wrapped in TLS (see
+// shared::transport); with `--reconnect` a lost connection is re-established
+// instead of exiting.
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, IsTerminal, Write};
 use std::env;
-use std::sync::mpsc::{self, TryRecvError};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use chatproject::shared::client::ChatClient;
+use chatproject::shared::commands::{completions, help_text, is_command};
+use chatproject::shared::error::StartupError;
+use chatproject::shared::protocol::{parse, Command, Hello};
+use chatproject::shared::transfer::{chunk_file, safe_filename, Assembler, FileChunk};
+use chatproject::shared::transport::client_config;
+use rustls::ClientConfig;
 
-// Server address used when connecting. This can be changed to a machine
-// reachable on the local network when testing with other hosts.
-const LOCAL: &str = "127.0.0.1:9090";
-//const LOCAL: &str = "172.20.10.3:9090";
+// Server address used when neither `--server <addr>` nor CHAT_SERVER is
+// given, e.g. to reach a machine on the local network when testing with
+// other hosts.
+const DEFAULT_SERVER: &str = "127.0.0.1:9090";
 
-// Message framing size in bytes. Must match the server's MSG_SIZE.
-const MSG_SIZE: usize = 500;
+// The `--server` flag wins over CHAT_SERVER (`var`), which wins over
+// DEFAULT_SERVER.
+fn server_addr(flag: Option<String>, var: Option<String>) -> String {
+    flag.or_else(|| var.filter(|a| !a.is_empty())).unwrap_or_else(|| DEFAULT_SERVER.to_string())
+}
 
-fn main() {
-    // Connect to the server and mark the socket as non-blocking. Non-blocking
-    // reads paired with a short sleep keep the client responsive without
-    // dedicating a blocking read loop to the main thread.
-    let mut client = TcpStream::connect(LOCAL).expect("Stream failed to connect");
-    client.set_nonblocking(true).expect("failed to initiate non-blocking");
+// In reconnect mode the client waits RECONNECT_DELAY before its first
+// retry and doubles the wait after every failure, up to MAX_RECONNECT_DELAY.
+// It gives up after `--max-retries` failed attempts in a row.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_RETRIES: u32 = 10;
 
-    // Channel used to send user-entered messages from the main thread to the
-    // network writer in the reader thread. This keeps all network writes in
-    // a single place to avoid concurrent writes to the same TcpStream.
-    let (tx, rx) = mpsc::channel::<String>();
-
-    // Reader thread: reads fixed-size frames from the server and prints
-    // received messages to stdout. It also receives outgoing messages from
-    // the main thread through `rx` and writes them to the server.
-    thread::spawn(move || loop {
-        // Read from server
-        let mut buff = vec![0; MSG_SIZE];
-        match client.read_exact(&mut buff) {
-            Ok(_) => {
-                // Trim trailing zeros and convert to UTF-8 for printing.
-                let msg = buff.into_iter().take_while(|&x| x != 0).collect::<Vec<_>>();
-                match String::from_utf8(msg) {
-                    Ok(s) => println!("{}", s),
-                    Err(e) => println!("message recv (invalid utf8): {:?}", e.into_bytes()),
-                }
-            },
-            // No data available yet on non-blocking socket; continue the loop.
-            Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
-            // Read error indicates the server closed the connection.
-            Err(_) => {
+// Lines typed while disconnected are kept and sent after reconnecting, up to
+// this many; beyond that the oldest are dropped.
+const MAX_BUFFERED: usize = 100;
+
+// How long `:quit` waits for the writer thread to tell the server before the
+// client exits anyway.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// `:send` queues one chunk per FILE_CHUNK_DELAY, which keeps a transfer
+// below the server's default rate limit of 5 messages per second.
+const FILE_CHUNK_DELAY: Duration = Duration::from_millis(250);
+// Received files are saved here unless CHAT_DOWNLOADS names another
+// directory.
+const DEFAULT_DOWNLOADS: &str = "downloads";
+
+// The trust settings for `--tls`. The server's certificate is checked
+// against the PEM file named by CLIENT_TLS_CA (for a self-signed server, its
+// own certificate).
+fn tls_config() -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+    let ca = env::var("CLIENT_TLS_CA").map_err(|_| StartupError::boxed("--tls needs CLIENT_TLS_CA set to the server's certificate (PEM)"))?;
+    client_config(Path::new(&ca)).map_err(|e| StartupError::boxed(format!("could not load {}: {}", ca, e)))
+}
+
+// How to reach the server and what to do when the connection is lost.
+struct Options {
+    server: String,
+    tls: Option<Arc<ClientConfig>>,
+    color: bool,
+    // Some(max attempts) in reconnect mode
+    reconnect: Option<u32>,
+    // read-only: announced in the hello, and only :quit is sent
+    lurk: bool,
+}
+
+// What the writer thread reacts to.
+enum Input {
+    // A line to send to the server.
+    Line(String),
+    // The reader of the given connection saw it end.
+    Lost(u64),
+    // The user quits: send `:quit` to the server, then answer on the channel.
+    Quit(Sender<()>),
+}
+
+// Connects (with TLS if asked to) and starts a printer thread for the new
+// connection.
+fn open(opts: &Options, generation: u64, input: &Sender<Input>, me: &Arc<Mutex<String>>) -> io::Result<Arc<ChatClient>> {
+    let hello = if opts.lurk { Hello::lurker() } else { Hello::ours() };
+    let client = Arc::new(match &opts.tls {
+        Some(config) => ChatClient::connect_tls_as(&opts.server, config.clone(), hello)?,
+        None => ChatClient::connect_as(&opts.server, hello)?,
+    });
+    spawn_printer(client.clone(), generation, input.clone(), me.clone(), opts.color);
+    Ok(client)
+}
+
+// Server replies that look like "word: text" but are not chat.
+const SYSTEM_PREFIXES: [&str; 17] = ["away", "ban", "delete", "edit", "hangman", "kick", "lurk", "muted", "name_available", "name_taken", "op", "poll", "protocol", "topic", "unban", "usage", "version"];
+
+// Who a line from the server is from, judged by its shape since the text
+// protocol carries no type.
+#[derive(Debug, PartialEq)]
+enum LineKind {
+    // chat, emotes and whispers sent by this user
+    Own,
+    // chat, emotes and whispers from someone else
+    Other,
+    // announcements, replies and game boards
+    System,
+}
+
+fn classify(line: &str, me: &str) -> LineKind {
+    // chat and emotes may start with a "[HH:MM:SS] " timestamp
+    let body = match line.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+        Some((ts, rest)) if ts.len() == 8 && ts.chars().all(|c| c.is_ascii_digit() || c == ':') => rest,
+        _ => line,
+    };
+    if body.starts_with("you whispered to ") {
+        return LineKind::Own;
+    }
+    let author = match body.strip_prefix("* ") {
+        Some(emote) => emote.split(' ').next(),
+        None => body.split_once(": ").map(|(name, _)| name.strip_suffix(" whispers").unwrap_or(name)),
+    };
+    match author {
+        // names never contain whitespace, so "poll closed: ..." is not chat
+        Some(name) if !name.is_empty() && !name.contains(char::is_whitespace) && !SYSTEM_PREFIXES.contains(&name) => {
+            if name == me { LineKind::Own } else { LineKind::Other }
+        }
+        _ => LineKind::System,
+    }
+}
+
+// Wraps the line in the ANSI color for its kind: green for your own lines,
+// cyan for other people and yellow for the server.
+fn colorize(line: &str, me: &str) -> String {
+    paint(line, classify(line, me))
+}
+
+fn paint(line: &str, kind: LineKind) -> String {
+    let color = match kind {
+        LineKind::Own => "32",
+        LineKind::Other => "36",
+        LineKind::System => "33",
+    };
+    format!("\x1b[{}m{}\x1b[0m", color, line)
+}
+
+// The local echo of a line the user sent, or None if the server sends it
+// back itself. Chat is only relayed to the others; commands (including
+// :flip, whose result the whole room sees) are answered by the server, and
+// their replies are all the user needs to see. `parse` is what
+// the server routes by, so both agree on which lines are chat.
+fn local_echo(msg: &str) -> Option<String> {
+    match parse(msg) {
+        Command::Chat(text) if !text.trim().is_empty() => Some(format!("you: {}", text)),
+        _ => None,
+    }
+}
+
+// Colors are used on terminals unless NO_COLOR is set (see no-color.org);
+// `--color` and `--no-color` override both.
+fn color_enabled(flags: &[String]) -> bool {
+    if flags.iter().any(|f| f == "--no-color") { return false; }
+    if flags.iter().any(|f| f == "--color") { return true; }
+    env::var("NO_COLOR").map_or(true, |v| v.is_empty()) && io::stdout().is_terminal()
+}
+
+fn set_name(me: &Mutex<String>, name: &str) {
+    if let Ok(mut me) = me.lock() {
+        *me = name.trim().to_string();
+    }
+}
+
+// Milliseconds since the Unix epoch, the token of a `:ping`.
+fn unix_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
+
+// The round trip in milliseconds if `msg` answers one of our `:ping`s. A
+// clock that went backwards gives 0 rather than a negative time.
+fn round_trip(msg: &str) -> Option<u128> {
+    let sent: u128 = msg.strip_prefix(":pong ")?.parse().ok()?;
+    Some(unix_millis().saturating_sub(sent))
+}
+
+// Printer thread body: waits for lines from the server and prints them to
+// stdout (ChatClient answers heartbeat pings itself); file chunks are put
+// together and saved instead of printed. When the connection ends the
+// writer thread is told which connection it was, so a late notice about an
+// old connection cannot end a newer one.
+fn spawn_printer(client: Arc<ChatClient>, generation: u64, input: Sender<Input>, me: Arc<Mutex<String>>, color: bool) {
+    thread::spawn(move || {
+        let mut files = Assembler::new();
+        while let Some(msg) = client.recv() {
+            if let Some(chunk) = FileChunk::parse(&msg) {
+                receive_file(&mut files, &chunk);
+            } else if let Some(rtt) = round_trip(&msg) {
+                println!("pong: {} ms", rtt);
+            } else if msg == ":clear" {
+                // erase the screen and move the cursor home (ANSI)
+                print!("\x1b[2J\x1b[H");
+                let _ = io::stdout().flush();
+            } else if color {
+                let me = me.lock().map(|me| me.clone()).unwrap_or_default();
+                println!("{}", colorize(&msg, &me));
+            } else {
+                println!("{}", msg);
+            }
+        }
+        let _ = input.send(Input::Lost(generation));
+    });
+}
+
+// Writer thread body: sends what the user types. When the connection is
+// lost it exits, or in reconnect mode connects again, registers the name
+// again and then sends what was typed in the meantime.
+fn write_server(opts: Options, mut client: Option<Arc<ChatClient>>, input: Receiver<Input>, input_tx: Sender<Input>, me: Arc<Mutex<String>>) {
+    let mut lost = client.is_none();
+    let mut generation = 0;
+    let mut pending: VecDeque<String> = VecDeque::new();
+    loop {
+        if lost {
+            let Some(max_retries) = opts.reconnect else {
                 println!("connection with server was severed");
                 std::process::exit(0);
+            };
+            generation += 1;
+            client = Some(reconnect(&opts, max_retries, generation, &input, &input_tx, &me, &mut pending));
+            lost = false;
+            let name = me.lock().map(|me| me.clone()).unwrap_or_default();
+            if !name.is_empty() {
+                pending.push_front(format!(":name {}", name));
             }
         }
-
-        // Check for outbound messages from the main thread and send them.
-        match rx.try_recv() {
-            Ok(msg) => {
-                let mut buff = msg.clone().into_bytes();
-                buff.resize(MSG_SIZE, 0);
-                if let Err(_) = client.write_all(&buff) {
-                    println!("connection with server was severed");
-                    std::process::exit(0);
+        // anything typed while disconnected goes out first
+        while let Some(msg) = pending.pop_front() {
+            if client.as_ref().is_none_or(|client| client.send(&msg).is_err()) {
+                buffer(&mut pending, msg);
+                lost = true;
+                break;
+            }
+        }
+        if lost { continue; }
+        match input.recv() {
+            Ok(Input::Line(msg)) => pending.push_back(msg),
+            Ok(Input::Lost(old)) => lost = old == generation,
+            Ok(Input::Quit(done)) => {
+                if let Some(client) = &client {
+                    let _ = client.send(":quit");
                 }
-            }, 
-            Err(TryRecvError::Empty) => (),
-            Err(TryRecvError::Disconnected) => break
+                let _ = done.send(());
+                return;
+            }
+            Err(_) => return,
         }
+    }
+}
 
-        // Yield a small amount of time to avoid busy-waiting.
-        thread::sleep(Duration::from_millis(100));
+// Keeps a line typed while disconnected, dropping the oldest one when the
+// buffer is full.
+fn buffer(pending: &mut VecDeque<String>, msg: String) {
+    if pending.len() == MAX_BUFFERED {
+        pending.pop_front();
+        println!("too many messages while disconnected, dropping the oldest");
+    }
+    pending.push_back(msg);
+}
+
+// Retries `open` with exponential backoff until it works, exiting after
+// `max_retries` failures. Lines typed while waiting are buffered.
+fn reconnect(opts: &Options, max_retries: u32, generation: u64, input: &Receiver<Input>, input_tx: &Sender<Input>, me: &Arc<Mutex<String>>, pending: &mut VecDeque<String>) -> Arc<ChatClient> {
+    println!("connection with server was severed");
+    let mut delay = RECONNECT_DELAY;
+    for attempt in 1..=max_retries {
+        println!("reconnecting in {:.1}s (attempt {}/{})", delay.as_secs_f64(), attempt, max_retries);
+        let deadline = Instant::now() + delay;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match input.recv_timeout(left) {
+                Ok(Input::Line(msg)) => buffer(pending, msg),
+                Ok(Input::Lost(_)) => {}
+                // no server to tell; dropping the sender lets main exit
+                Ok(Input::Quit(_)) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => std::process::exit(0),
+            }
+        }
+        match open(opts, generation, input_tx, me) {
+            Ok(client) => {
+                println!("reconnected to {}", opts.server);
+                return client;
+            }
+            Err(e) => println!("could not connect to {}: {}", opts.server, e),
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+    println!("giving up after {} attempts", max_retries);
+    std::process::exit(0);
+}
+
+// `:send <name> <path>`: reads the file and hands its chunks to the writer
+// thread from a background thread, so typing goes on during the transfer.
+fn send_file(args: &str, input: &Sender<Input>) {
+    let Some((name, path)) = args.split_once(' ') else {
+        println!("usage: :send <name> <path>");
+        return;
+    };
+    let path = path.trim();
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("send: could not read {}: {}", path, e);
+            return;
+        }
+    };
+    let lines = match chunk_file(name, path, &data) {
+        Ok(lines) => lines,
+        Err(e) => {
+            println!("send: {}", e);
+            return;
+        }
+    };
+    println!("sending {} to {} ({} bytes)", path, name, data.len());
+    let input = input.clone();
+    thread::spawn(move || {
+        for line in lines {
+            if input.send(Input::Line(line)).is_err() { break; }
+            thread::sleep(FILE_CHUNK_DELAY);
+        }
     });
+}
+
+// Adds a received chunk and saves the file once it is complete.
+fn receive_file(files: &mut Assembler, chunk: &FileChunk) {
+    if chunk.index == 0 {
+        println!("receiving {} from {} ({} bytes)", chunk.filename, chunk.peer, chunk.size);
+    }
+    match files.add(chunk) {
+        Ok(Some(data)) => match save_download(chunk.filename, &data) {
+            Ok(path) => println!("received {} from {}, saved as {}", chunk.filename, chunk.peer, path.display()),
+            Err(e) => println!("could not save {} from {}: {}", chunk.filename, chunk.peer, e),
+        },
+        Ok(None) => {}
+        Err(e) => println!("file from {} dropped: {}", chunk.peer, e),
+    }
+}
+
+// Writes a received file to the download directory. An existing file is
+// never overwritten; the new one gets a "1-", "2-", ... prefix instead.
+fn save_download(name: &str, data: &[u8]) -> io::Result<PathBuf> {
+    let dir = PathBuf::from(env::var("CHAT_DOWNLOADS").ok().filter(|d| !d.is_empty()).unwrap_or_else(|| DEFAULT_DOWNLOADS.to_string()));
+    fs::create_dir_all(&dir)?;
+    let name = safe_filename(name);
+    let mut n = 0;
+    loop {
+        let path = if n == 0 { dir.join(&name) } else { dir.join(format!("{}-{}", n, name)) };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(data)?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Handles `/help [prefix]` and `:commands [prefix]` without asking the
+// server. Returns false for anything else.
+fn local_help(msg: &str) -> bool {
+    let Some(prefix) = msg.strip_prefix("/help").or_else(|| msg.strip_prefix(":commands")) else {
+        return false;
+    };
+    if !prefix.is_empty() && !prefix.starts_with(' ') {
+        return false;
+    }
+    let prefix = prefix.trim();
+    let prefix = if prefix.is_empty() || prefix.starts_with(':') { prefix.to_string() } else { format!(":{}", prefix) };
+    println!("{}", help_text(&prefix));
+    true
+}
+
+// A hint for a line that starts like a command but isn't one, e.g. ":ha"
+// for ":hang". The line is still sent; the server treats it as chat.
+fn command_hint(msg: &str) -> Option<String> {
+    let word = msg.split_whitespace().next()?;
+    if word.len() < 2 || !word.starts_with(':') || is_command(word) {
+        return None;
+    }
+    let names = completions(word);
+    if names.is_empty() {
+        return None;
+    }
+    Some(format!("hint: {} is not a command, did you mean {}? (/help lists them)", word, names.join(", ")))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Flags (`--server <addr>`, `--tls`, `--color`, `--no-color`,
+    // `--no-echo`, `--reconnect`, `--max-retries <n>`, `--lurk`) may appear
+    // anywhere on the command line; everything else is the optional name.
+    let (mut flags, mut args, mut server, mut max_retries) = (Vec::new(), Vec::new(), None, DEFAULT_MAX_RETRIES);
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        if arg == "--server" {
+            server = argv.next();
+        } else if arg == "--max-retries" {
+            max_retries = argv.next().and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_MAX_RETRIES);
+        } else if let Some(addr) = arg.strip_prefix("--server=") {
+            server = Some(addr.to_string());
+        } else if arg.starts_with("--") {
+            flags.push(arg);
+        } else {
+            args.push(arg);
+        }
+    }
+    let server = server_addr(server, env::var("CHAT_SERVER").ok());
+    let tls = flags.iter().any(|f| f == "--tls");
+    let color = color_enabled(&flags);
+    let reconnect = flags.iter().any(|f| f == "--reconnect").then_some(max_retries);
+    let lurk = flags.iter().any(|f| f == "--lurk");
+    // print what we send as "you: <msg>" (toggled with /echo)
+    let mut echo = !flags.iter().any(|f| f == "--no-echo");
+    // the name we last asked for, to tell our own lines apart
+    let me = Arc::new(Mutex::new(String::new()));
+
+    let tls = if tls { Some(tls_config()?) } else { None };
+    let opts = Options { server, tls, color, reconnect, lurk };
+
+    // Channel used to send user-entered messages from the main thread to the
+    // writer thread. This keeps all network writes in a single place to
+    // avoid concurrent writes to the same TcpStream.
+    let (tx, rx) = mpsc::channel::<Input>();
+
+    // Connect to the server. Both directions use blocking I/O on their own
+    // thread, so nothing has to poll or sleep.
+    let client = match open(&opts, 0, &tx, &me) {
+        Ok(client) => Some(client),
+        Err(e) if opts.reconnect.is_some() => {
+            println!("could not connect to {}: {}", opts.server, e);
+            None
+        }
+        Err(e) => return Err(StartupError::boxed(format!("could not connect to {}: {}", opts.server, e))),
+    };
+
+    // Writer thread: sleeps on the channel until the main thread has a
+    // message, then sends it to the server.
+    let (writer_tx, writer_me) = (tx.clone(), me.clone());
+    thread::spawn(move || write_server(opts, client, rx, writer_tx, writer_me));
 
     // If a name was supplied on the command line, send a registration message
     // to the server using the :name command. The code accepts either
     // `client <name>` or `client :name <name>` for convenience.
-    let mut args = env::args().skip(1);
+    let mut args = args.into_iter();
     if let Some(first) = args.next() {
-        if first == ":name" {
-            if let Some(name) = args.next() {
-                let _ = tx.send(format!(":name {}", name));
-            }
-        } else {
-            // treat first arg as the name directly
-            let _ = tx.send(format!(":name {}", first));
+        // treat first arg as the name directly unless it is `:name`
+        let name = if first == ":name" { args.next() } else { Some(first) };
+        if let Some(name) = name {
+            set_name(&me, &name);
+            let _ = tx.send(Input::Line(format!(":name {}", name)));
         }
     }
 
     // Main input loop: read user input and forward it to the reader/writer
-    // thread via the channel. Sending :quit will break the loop and exit.
-    println!("Write a Message:");
+    // thread via the channel. :quit tells the server, then ends the loop and
+    // exits. The end of the input (e.g. `client --lurk </dev/null`) counts
+    // as :quit.
+    println!("Write a Message (/help lists the commands):");
     loop {
         let mut buff = String::new();
-        io::stdin().read_line(&mut buff).expect("reading from stdin failed");
+        if io::stdin().read_line(&mut buff)? == 0 {
+            buff = String::from(":quit");
+        }
         let msg = buff.trim().to_string();
-        if msg == ":quit" || tx.send(msg).is_err() {break}
+        if local_help(&msg) {
+            continue;
+        }
+        // lurking: nothing but :quit goes to the server
+        if lurk && parse(&msg) != Command::Quit {
+            if !msg.is_empty() {
+                println!("lurking: not sent, only :quit works in lurk mode");
+            }
+            continue;
+        }
+        if let Command::SendFile(args) = parse(&msg) {
+            send_file(args, &tx);
+            continue;
+        }
+        // :ping carries the time it was sent; the printer times the answer
+        if let Command::Ping(_) = parse(&msg) {
+            let _ = tx.send(Input::Line(format!(":ping {}", unix_millis())));
+            continue;
+        }
+        if msg == "/echo" {
+            echo = !echo;
+            println!("local echo {}", if echo { "on" } else { "off" });
+            continue;
+        }
+        if let Some(hint) = command_hint(&msg) {
+            println!("{}", hint);
+        }
+        if let Some(line) = local_echo(&msg).filter(|_| echo) {
+            println!("{}", if color { paint(&line, LineKind::Own) } else { line });
+        }
+        if let Command::Name(name) = parse(&msg) {
+            set_name(&me, name);
+        }
+        // the server is told, so the room hears right away that we left
+        if parse(&msg) == Command::Quit {
+            let (done, sent) = mpsc::channel();
+            if tx.send(Input::Quit(done)).is_ok() {
+                let _ = sent.recv_timeout(QUIT_TIMEOUT);
+            }
+            break;
+        }
+        if tx.send(Input::Line(msg)).is_err() {break}
     }
     println!("bye bye!");
-
+    Ok(())
 }
 
 /*  
@@ -106,4 +527,77 @@ In the server run `cargo run`.
 Then do the same in the client. And this time you should see a message, `write a message`. 
 Type something and then you should see that in the server. 
 If you type ':quit' then the program will quit. 
- */
\ No newline at end of file
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_classified_by_who_they_are_from() {
+        assert_eq!(classify("kai: hello", "kai"), LineKind::Own);
+        assert_eq!(classify("[12:34:56] kai: hello", "kai"), LineKind::Own);
+        assert_eq!(classify("* kai waves", "kai"), LineKind::Own);
+        assert_eq!(classify("you whispered to bob: psst", "kai"), LineKind::Own);
+        assert_eq!(classify("bob: hello kai", "kai"), LineKind::Other);
+        assert_eq!(classify("[12:34:56] * bob waves", "kai"), LineKind::Other);
+        assert_eq!(classify("bob whispers: psst", "kai"), LineKind::Other);
+        assert_eq!(classify("bob joined the chat", "kai"), LineKind::System);
+        assert_eq!(classify("hangman: guess a letter", "kai"), LineKind::System);
+        assert_eq!(classify("poll closed: Lunch?", "kai"), LineKind::System);
+        assert_eq!(classify("connected:\nkai\nbob", "kai"), LineKind::System);
+        assert_eq!(classify("[not a time] kai: hi", "kai"), LineKind::System);
+    }
+
+    #[test]
+    fn each_kind_gets_its_own_color() {
+        assert_eq!(colorize("kai: hi", "kai"), "\x1b[32mkai: hi\x1b[0m");
+        assert_eq!(colorize("bob: hi", "kai"), "\x1b[36mbob: hi\x1b[0m");
+        assert_eq!(colorize("bob left the chat", "kai"), "\x1b[33mbob left the chat\x1b[0m");
+    }
+
+    #[test]
+    fn color_flags_override_the_environment() {
+        assert!(!color_enabled(&[String::from("--color"), String::from("--no-color")]));
+        assert!(color_enabled(&[String::from("--color")]));
+    }
+
+    #[test]
+    fn the_server_flag_wins_over_the_environment() {
+        let some = |addr: &str| Some(addr.to_string());
+        assert_eq!(server_addr(some("10.0.0.1:9090"), some("10.0.0.2:9090")), "10.0.0.1:9090");
+        assert_eq!(server_addr(None, some("10.0.0.2:9090")), "10.0.0.2:9090");
+        assert_eq!(server_addr(None, some("")), DEFAULT_SERVER);
+        assert_eq!(server_addr(None, None), DEFAULT_SERVER);
+    }
+
+    #[test]
+    fn only_near_misses_get_a_command_hint() {
+        assert_eq!(command_hint(":qui now").unwrap(), "hint: :qui is not a command, did you mean :quit? (/help lists them)");
+        assert_eq!(command_hint(":quit"), None);
+        assert_eq!(command_hint(":zzz"), None);
+        assert_eq!(command_hint("hello :qui"), None);
+    }
+
+    #[test]
+    fn only_chat_is_echoed_locally() {
+        assert_eq!(local_echo("hello there").unwrap(), "you: hello there");
+        assert_eq!(local_echo("  "), None);
+        assert_eq!(local_echo(":flip"), None);
+        assert_eq!(local_echo(":me waves"), None);
+        assert_eq!(local_echo(":w bob psst"), None);
+        assert_eq!(local_echo(":name kai"), None);
+    }
+
+    #[test]
+    fn the_round_trip_is_timed_from_the_echoed_token() {
+        let sent = unix_millis();
+        let rtt = round_trip(&format!(":pong {}", sent)).unwrap();
+        assert!(rtt < 1000, "{}", rtt);
+        // a token from the future (a clock that went back) is no negative time
+        assert_eq!(round_trip(&format!(":pong {}", sent + 60_000)), Some(0));
+        assert_eq!(round_trip(":pong"), None);
+        assert_eq!(round_trip(":pong soon"), None);
+        assert_eq!(round_trip("bob: :pong 1"), None);
+    }
+}
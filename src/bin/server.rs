@@ -1,381 +1,4429 @@
-use std::io::{ErrorKind, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
 use rand::Rng;
-use std::sync::mpsc;
-use std::collections::HashSet;
+use serde::Deserialize;
+use log::{debug, error, info, trace, warn};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use chatproject::shared::commands::help_text;
+use chatproject::shared::error::StartupError;
+use chatproject::shared::framing::{encode_frame, read_frame_limited, read_line_limited, write_frame};
 use chatproject::shared::hangman::*;
+use chatproject::shared::protocol::{parse, validate_name, version_text, ClientMessage, Command, Hello, ServerMessage, CAPABILITIES, JSON_HANDSHAKE, OPT_IN_CAPABILITIES, TYPING_INTERVAL};
+use chatproject::shared::transfer::{FileChunk, MAX_FILE_SIZE};
+use chatproject::shared::transport::{server_config, TlsTransport, Transport};
+#[cfg(feature = "websocket")]
+use chatproject::shared::transport::WsTransport;
+use chatproject::shared::words::{random_word_by_difficulty, Difficulty};
 
 // The server implements a small thread-per-connection TCP chat server. Each
 // client reader runs in its own thread and forwards framed messages to the
-// main loop via an mpsc channel. The main loop owns the writable handles and
-// the `clients` list so that broadcasts and state changes are performed
-// centrally without additional locking.
+// main loop via an mpsc channel. The main loop owns the `clients` list so
+// that broadcasts and state changes are performed centrally without
+// additional locking. Writes never happen on the main loop: every client has
+// a bounded outbound queue drained by its own writer thread, so one slow
+// client cannot hold up everyone else.
 
 // Default bind address. Can be overridden with the SERVER_ADDR env var.
 // The server binds a TcpListener to this address at startup.
 const DEFAULT_LOCAL: &str = "127.0.0.1:9090";
 
-// Message framing size in bytes. All network reads and writes use this fixed
-// buffer length. Messages are padded with zeros when shorter. 
-const MSG_SIZE: usize = 500;
+// Config file read at startup if it exists. SERVER_CONFIG names another
+// file, which then has to exist.
+const DEFAULT_CONFIG: &str = "server.toml";
 
-// Pause briefly to avoid busy-waiting in loops that poll sockets or channels.
-// A small sleep keeps CPU usage low while still providing responsive
-// behaviour for this example server.
-fn sleep() {
-    thread::sleep(::std::time::Duration::from_millis(100));
+// Settings from the config file. Each one stands in for an env var (see
+// FileConfig::value), so env vars override the file and the file overrides
+// the built-in defaults. Unknown keys are rejected to catch typos.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    // one address or a list, like the comma-separated SERVER_ADDR
+    addr: Option<Addrs>,
+    max_msg: Option<usize>,
+    max_clients: Option<usize>,
+    rate: Option<f64>,
+    burst: Option<f64>,
+    // seconds
+    idle_timeout: Option<u64>,
+    op_password: Option<String>,
+    log_file: Option<String>,
+    // command aliases, see Aliases
+    aliases: Option<HashMap<String, String>>,
 }
 
-
-// Simple utility to return a 50/50 result for the :flip command. .
-fn flip_coin() -> &'static str {
-    let mut rng = rand::thread_rng();
-    if rng.gen_bool(0.5) { "heads" } else { "tails" }
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Addrs {
+    One(String),
+    Many(Vec<String>),
 }
 
-// Wraps below helper function, but accepts Strings
-fn send_to_all_text(clients: &mut Vec<(TcpStream, String, String)>, msg: &str) {
-    let mut buf = msg.as_bytes().to_vec();
-    buf.resize(MSG_SIZE, 0);
-    send_to_all(clients, &buf);
+impl FileConfig {
+    // The file's value for the env var `var`, in the env var's format.
+    fn value(&self, var: &str) -> Option<String> {
+        match var {
+            "SERVER_ADDR" => self.addr.as_ref().map(|addrs| match addrs {
+                Addrs::One(addr) => addr.clone(),
+                Addrs::Many(addrs) => addrs.join(","),
+            }),
+            "SERVER_MAX_MSG" => self.max_msg.map(|n| n.to_string()),
+            "SERVER_MAX_CLIENTS" => self.max_clients.map(|n| n.to_string()),
+            "SERVER_RATE" => self.rate.map(|n| n.to_string()),
+            "SERVER_BURST" => self.burst.map(|n| n.to_string()),
+            "SERVER_IDLE_TIMEOUT" => self.idle_timeout.map(|n| n.to_string()),
+            "SERVER_OP_PASSWORD" => self.op_password.clone(),
+            "SERVER_LOG_FILE" => self.log_file.clone(),
+            _ => None,
+        }
+    }
 }
 
-// Wraps below helper function, but accepts Strings
-fn send_to_client_text(
-    clients: &mut Vec<(TcpStream, String, String)>,
-    recipient: &str,
-    msg: &str,
-) {
-    let mut buf = msg.as_bytes().to_vec();
-    buf.resize(MSG_SIZE, 0);
-    send_to_client(clients, recipient, &buf);
+static CONFIG: OnceLock<FileConfig> = OnceLock::new();
+
+// Reads and parses the config file. A server with a broken config refuses
+// to start rather than running with settings nobody asked for.
+fn load_config() -> Result<FileConfig, Box<dyn Error>> {
+    let (path, required) = match env::var("SERVER_CONFIG") {
+        Ok(path) if !path.is_empty() => (path, true),
+        _ => (DEFAULT_CONFIG.to_string(), false),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound && !required => return Ok(FileConfig::default()),
+        Err(e) => return Err(StartupError::boxed(format!("could not read config {}: {}", path, e))),
+    };
+    let config: FileConfig = toml::from_str(&text).map_err(|e| StartupError::boxed(format!("invalid config {}: {}", path, e)))?;
+    if let Some(aliases) = &config.aliases {
+        check_aliases(aliases).map_err(|e| StartupError::boxed(format!("invalid config {}: {}", path, e)))?;
+    }
+    info!("using config {}", path);
+    Ok(config)
 }
 
+// Command aliases from the config file's [aliases] table, e.g. "/f" =
+// ":flip". The router replaces the first word of a message with its alias
+// (again, if that is an alias too) before working out the command, so
+// `/f` flips a coin and "/w" = ":w" makes `/w kai hi` a whisper.
+#[derive(Default)]
+struct Aliases {
+    map: HashMap<String, String>,
+}
 
-// Helper: send buffer to all clients, removing any that fail
-fn send_to_all(clients: &mut Vec<(TcpStream, String, String)>, buf: &[u8]) {
-    let mut remove_idx: Vec<usize> = Vec::new();
-    for (i, (client, _addr, _disp)) in clients.iter_mut().enumerate() {
-        if client.write_all(buf).is_err() { remove_idx.push(i); }
+impl Aliases {
+    fn expand<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut line = Cow::Borrowed(line);
+        // check_aliases made sure this ends
+        while let Some(target) = self.map.get(first_word(&line)) {
+            let rest = &line[first_word(&line).len()..];
+            line = Cow::Owned(format!("{}{}", target, rest));
+        }
+        line
     }
-    for i in remove_idx.into_iter().rev() { clients.remove(i); }
 }
 
-// Helper: send buffer to all clients except the sender (by addr); remove failed clients
-fn send_to_others(clients: &mut Vec<(TcpStream, String, String)>, sender: &str, buf: &[u8]) {
-    let mut remove_idx: Vec<usize> = Vec::new();
-    for (i, (client, addr, _disp)) in clients.iter_mut().enumerate() {
-        if addr == sender { continue; }
-        if client.write_all(buf).is_err() { remove_idx.push(i); }
-    }
-    for i in remove_idx.into_iter().rev() { clients.remove(i); }
+fn first_word(line: &str) -> &str {
+    line.split(' ').next().unwrap_or("")
 }
 
-// Helper: send buffer only to a single client (by addr). Does not remove other clients on failure.
-fn send_to_client(clients: &mut Vec<(TcpStream, String, String)>, recipient: &str, buf: &[u8]) {
-    for (client, addr, _disp) in clients.iter_mut() {
-        if addr == recipient {
-            let _ = client.write_all(buf);
-            break;
+// Checks an alias table before the server starts with it: every alias is a
+// single word that is not a command already, stands for something, and does
+// not lead back to itself through other aliases.
+fn check_aliases(aliases: &HashMap<String, String>) -> Result<(), String> {
+    for (name, target) in aliases {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("alias {:?} must be a single word", name));
+        }
+        if parse(name) != Command::Chat(name) {
+            return Err(format!("alias {} would hide the command of the same name", name));
+        }
+        if target.trim().is_empty() {
+            return Err(format!("alias {} stands for nothing", name));
+        }
+        let mut chain = vec![name.as_str()];
+        let mut next = first_word(target);
+        while let Some(target) = aliases.get(next) {
+            let looped = chain.contains(&next);
+            chain.push(next);
+            if looped {
+                return Err(format!("aliases form a cycle: {}", chain.join(" -> ")));
+            }
+            next = first_word(target);
         }
     }
+    Ok(())
 }
 
-fn main() {
-    let mut hangman_state: Option<GameState> = None;
+// The env var `var` if set, else the config file's value for it.
+fn setting(var: &str) -> Option<String> {
+    env::var(var).ok().or_else(|| CONFIG.get().and_then(|config| config.value(var)))
+}
 
-    // Allow overriding the listening address via SERVER_ADDR environment variable.
-    let local = env::var("SERVER_ADDR").unwrap_or_else(|_| DEFAULT_LOCAL.to_string());
-    println!("Binding server to {}", local);
-    let server = TcpListener::bind(&local).expect("Listener failed to bind");
-    server.set_nonblocking(true).expect("failed to initialize non-blocking");
+// Current server time as HH:MM:SS (UTC). Computed on the server so every
+// client sees the same clock regardless of its own settings.
+fn timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let day_secs = secs % 86_400;
+    format!("{:02}:{:02}:{:02}", day_secs / 3600, (day_secs / 60) % 60, day_secs % 60)
+}
 
-    // clients: Vec of (stream, peer_addr_string, display_name)
-    let mut clients: Vec<(TcpStream, String, String)> = vec![];
-    // track clients who recently received a name_taken so we can confirm when they later pick a unique name
-    let mut name_rejected: HashSet<String> = HashSet::new();
+// How long ago `since` was, as "1h 2m 3s" (leading zero units are left out).
+fn format_uptime(since: Instant) -> String {
+    format_duration(since.elapsed())
+}
+
+fn format_duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (h, m, s) = (secs / 3600, (secs / 60) % 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+// Current server date and time as YYYY-MM-DD HH:MM:SS (UTC), for the chat
+// log where lines may span several days.
+fn log_timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {}", year, month, day, timestamp())
+}
+
+// Feeds the chat log thread started by start_chat_log. None when logging is
+// off (SERVER_LOG_FILE unset) or after stop_chat_log.
+static CHAT_LOG: Mutex<Option<Sender<String>>> = Mutex::new(None);
+
+// Opens SERVER_LOG_FILE for appending and starts the thread that writes to
+// it, so a slow disk never holds up the main loop.
+fn start_chat_log() -> Option<thread::JoinHandle<()>> {
+    let path = setting("SERVER_LOG_FILE").filter(|p| !p.is_empty())?;
+    let file = match fs::OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("could not open chat log {}: {}", path, e);
+            return None;
+        }
+    };
     let (tx, rx) = mpsc::channel::<String>();
-    loop {
-        if let Ok((mut socket, addr)) = server.accept() {
-            println!("Client {} connected", addr);
+    *CHAT_LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+    info!("logging chat to {}", path);
+    Some(thread::spawn(move || {
+        let mut out = BufWriter::new(file);
+        for line in rx {
+            if writeln!(out, "{}", line).and_then(|_| out.flush()).is_err() {
+                warn!("could not write to chat log {}", path);
+                return;
+            }
+        }
+    }))
+}
 
-            // Clone the transmitter for the new client thread. The client
-            // thread will send framed messages into the shared channel so the
-            // central loop can perform routing and broadcasting.
-            let tx = tx.clone();
-            // store (stream, addr, display_name) - display_name defaults to addr
-            clients.push((socket.try_clone().expect("failed to clone client"), addr.to_string(), addr.to_string()));
-
-            // Start a dedicated reader thread for this client. The thread
-            // performs blocking reads of fixed-size frames and forwards
-            // messages to the main loop via the channel. The main loop keeps
-            // writable handles and performs broadcasts to avoid concurrent
-            // writes to the same TcpStream.
-            thread::spawn(move || loop {
-                let mut buff = vec![0; MSG_SIZE];
-
-                match socket.read_exact(&mut buff) {
-                    Ok(_) => {
-                        let msg = buff.into_iter().take_while(|&x| x != 0).collect::<Vec<_>>();
-                        let msg = String::from_utf8(msg).expect("Invalid utf8 message");
-
-                        // Command handling: keep :flip and :list server-side; other messages forwarded
-                        match msg.as_str() {
-                            ":flip" => {
-                                let result = flip_coin();
-                                println!("{} requested flip -> {}", addr, result);
-                                // send framed message so main thread can map addr -> name
-                                let framed = format!("[{}]::flipped: {}", addr, result);
-                                tx.send(framed).expect("failed to send flip result to rx");
-                            }
-                            ":list" => {
-                                // request the main loop to send the (multi-line) user list
-                                let framed = format!("[{}]::{}", addr, msg);
-                                tx.send(framed).expect("failed to send list request to rx");
-                            }
-                            ":help" => {
-                                let help_msg = "Available commands:\n:name <name> - set/change your display name (must be unique)\n:list - list connected users\n:flip - flip a coin (result sent to all)\n:hang start <word> - start a hangman game\n:hang end - end the current hangman game\n:hang guess <letter> - send a hangman guess, must be one letter\n:quit - disconnect from server".to_string();
-                                let mut buf = help_msg.into_bytes();
-                                buf.resize(MSG_SIZE, 0);
-                                // Send help only to the requesting client (do not forward to main loop)
-                                socket.write_all(&buf).expect("failed to send help message to client");
-                            }
-                            _ => {
-                                // Prefix with sender addr so main thread can identify sender
-                                let framed = format!("[{}]::{}", addr, msg);
-                                tx.send(framed).expect("failed to send msg to rx");
-                            }
-                        }
-                    },
-                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
-                    Err(_) => {
-                        println!("closing connection with: {}", addr);
-                        break;
-                    }
-                }
+// Appends one broadcast message to the chat log, if logging is on.
+fn log_chat(room: Option<&str>, msg: &str) {
+    if let Some(tx) = CHAT_LOG.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        let _ = tx.send(format!("[{}] [{}] {}", log_timestamp(), room.unwrap_or("*"), msg));
+    }
+}
 
-                sleep();
-            });
+// Closes the log channel and waits until every pending line is written.
+fn stop_chat_log(handle: Option<thread::JoinHandle<()>>) {
+    CHAT_LOG.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(handle) = handle {
+        let _ = handle.join();
+    }
+}
+
+// Timestamps are on by default. SERVER_TIMESTAMPS=off (or 0/false) disables
+// them, and the --timestamps / --no-timestamps flags override the env var.
+fn timestamps_enabled() -> bool {
+    let mut enabled = env::var("SERVER_TIMESTAMPS")
+        .map(|v| !matches!(v.to_lowercase().as_str(), "0" | "off" | "false" | "no"))
+        .unwrap_or(true);
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--timestamps" => enabled = true,
+            "--no-timestamps" => enabled = false,
+            _ => (),
         }
+    }
+    enabled
+}
 
-        if let Ok(recv_msg) = rx.try_recv() {
-            // Messages arrive framed as "[<addr>]::<content>" from per-client threads.
-            if recv_msg.starts_with('[') {
-                if let Some(pos) = recv_msg.find("]::") {
-                    let sender = &recv_msg[1..pos];
-                    let content = &recv_msg[pos + 3..];
+// Per-client message rate, from SERVER_RATE (messages per second) and
+// SERVER_BURST, falling back to RATE_PER_SEC and RATE_BURST.
+fn rate_limit() -> RateLimit {
+    let var = |name: &str, default: f64| {
+        setting(name).and_then(|v| v.parse().ok()).filter(|v: &f64| *v > 0.0).unwrap_or(default)
+    };
+    RateLimit { per_sec: var("SERVER_RATE", RATE_PER_SEC), burst: var("SERVER_BURST", RATE_BURST) }
+}
 
-                    if content.starts_with(":name ") {
-                        try_client_name_assignment(&mut clients, &mut name_rejected, sender, content);
-                        continue;
-                    } else if content.starts_with(":hang") {
-                        handle_hangman_command(&mut clients, sender, content, &mut hangman_state);
-                        continue;
-                    }
+// History length per room, from SERVER_HISTORY or HISTORY_LEN.
+fn history_len() -> usize {
+    env::var("SERVER_HISTORY").ok().and_then(|v| v.parse().ok()).unwrap_or(HISTORY_LEN)
+}
 
-                    // Handle a private :list request. The requesting client
-                    // asks for the current list of display names. Build a
-                    // multi-line response and send it only to that client.
-                    if content == ":list" {
-                        // build a multi-line list of display names (one per line)
-                        let mut resp = String::from("connected:\n");
-                        for (_, _, disp) in &clients {
-                            resp.push_str(&format!("{}\n", disp));
-                        }
-                        let mut buf = resp.into_bytes();
-                        buf.resize(MSG_SIZE, 0);
-                        // write only to the requesting client (don't move the clients vec)
-                        send_to_client(&mut clients, sender, &buf);
-                        continue;
-                    }
-                    
-
-                    // Normal message: find display name for sender (fallback to sender addr)
-                    let sender_name = clients.iter().find(|(_, addr, _)| addr == sender).map(|(_, _, disp)| disp.clone()).unwrap_or_else(|| sender.to_string());
-                    let to_send_str = format!("{}: {}", sender_name, content);
-
-                    // server log using the sender name
-                    println!("{}", to_send_str);
-
-                    let mut buff = to_send_str.into_bytes();
-                    buff.resize(MSG_SIZE, 0);
-                    // If this is a coin-flip result (content starts with "flipped:"), send to everyone including sender.
-                    // Otherwise, avoid sending the message back to the originating client to prevent duplicate echo.
-                    if content.starts_with("flipped:") {
-                        // broadcast to all; remove clients that fail
-                        send_to_all(&mut clients, &buff);
-                    } else {
-                        // send to others only; keep sender always
-                        send_to_others(&mut clients, sender, &buff);
-                    }
-                }
+// The message of the day: the contents of SERVER_MOTD_FILE, or else the
+// single line in SERVER_MOTD. None if neither is set or both are empty.
+fn load_motd() -> Option<String> {
+    let motd = match env::var("SERVER_MOTD_FILE").ok().filter(|p| !p.is_empty()) {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("could not read MOTD file {}: {}", path, e);
+                return None;
+            }
+        },
+        None => env::var("SERVER_MOTD").ok()?,
+    };
+    let motd = motd.trim_end();
+    (!motd.is_empty()).then(|| format!("--- message of the day ---\n{}", motd))
+}
+
+// Time between pings, from SERVER_HEARTBEAT (seconds) or
+// HEARTBEAT_INTERVAL. Zero turns the heartbeat off.
+fn heartbeat_interval() -> Option<Duration> {
+    let secs = env::var("SERVER_HEARTBEAT").ok().and_then(|v| v.parse().ok()).unwrap_or(HEARTBEAT_INTERVAL.as_secs());
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+// Clients that send nothing for this long are disconnected, from
+// SERVER_IDLE_TIMEOUT (seconds). Off unless set.
+fn idle_timeout() -> Option<Duration> {
+    let secs: u64 = setting("SERVER_IDLE_TIMEOUT").and_then(|v| v.parse().ok())?;
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+// Message size limit, from SERVER_MAX_MSG or MAX_MSG_LEN.
+fn max_msg_len() -> usize {
+    setting("SERVER_MAX_MSG").and_then(|v| v.parse().ok()).unwrap_or(MAX_MSG_LEN)
+}
+
+// Most clients connected at once, from SERVER_MAX_CLIENTS. No limit unless
+// set.
+fn max_clients() -> Option<usize> {
+    setting("SERVER_MAX_CLIENTS").and_then(|v| v.parse().ok())
+}
+
+// TLS is used when both SERVER_TLS_CERT and SERVER_TLS_KEY point at PEM
+// files. A server that was asked for TLS but cannot load them refuses to
+// start rather than falling back to plaintext.
+fn tls_config() -> Result<Option<Arc<rustls::ServerConfig>>, Box<dyn Error>> {
+    let var = |name| env::var(name).ok().filter(|p: &String| !p.is_empty());
+    let (Some(cert), Some(key)) = (var("SERVER_TLS_CERT"), var("SERVER_TLS_KEY")) else {
+        return Ok(None);
+    };
+    let config = server_config(Path::new(&cert), Path::new(&key)).map_err(|e| StartupError::boxed(format!("could not load tls certificate: {}", e)))?;
+    Ok(Some(config))
+}
+
+fn save_dir() -> PathBuf {
+    env::var("SERVER_SAVE_DIR").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from(DEFAULT_SAVE_DIR))
+}
+
+// Snapshot names (and room names, for autosaves) become file names, so only
+// a small safe character set is accepted.
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Display name a client has until it picks one with :name. The connection id
+// keeps guest names unique.
+fn guest_name(id: u64) -> String {
+    format!("guest-{}", id)
+}
+
+// Ban list entries are either display names or address prefixes such as
+// "10.0.0." or "192.168.1.7:"; an entry is an address prefix if it only uses
+// digits, dots, colons and brackets.
+fn is_addr_prefix(entry: &str) -> bool {
+    entry.contains(['.', ':']) && entry.chars().all(|c| c.is_ascii_hexdigit() || matches!(c, '.' | ':' | '[' | ']'))
+}
+
+fn ban_matches(entry: &str, name: &str, addr: &str) -> bool {
+    entry == name || (is_addr_prefix(entry) && addr.starts_with(entry))
+}
+
+fn is_banned(bans: &HashSet<String>, name: &str, addr: &str) -> bool {
+    bans.iter().any(|entry| ban_matches(entry, name, addr))
+}
+
+// File the ban list is kept in across restarts (SERVER_BAN_FILE); bans are
+// not persisted when it is unset.
+fn ban_file() -> Option<PathBuf> {
+    env::var("SERVER_BAN_FILE").ok().filter(|p| !p.is_empty()).map(PathBuf::from)
+}
+
+// Reads the ban list, one entry per line. A missing file is an empty list;
+// any other read error stops the server, since starting without the bans
+// would lift them and the next :ban would overwrite the file.
+fn load_bans(path: Option<&Path>) -> Result<HashSet<String>, Box<dyn Error>> {
+    let Some(path) = path else { return Ok(HashSet::new()); };
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(StartupError::boxed(format!("could not read ban file {}: {}", path.display(), e))),
+    }
+}
+
+fn save_bans(bans: &HashSet<String>) {
+    let Some(path) = ban_file() else { return; };
+    let mut entries: Vec<&str> = bans.iter().map(String::as_str).collect();
+    entries.sort();
+    let text: String = entries.iter().map(|e| format!("{}\n", e)).collect();
+    if let Err(e) = fs::write(&path, text) {
+        warn!("could not save ban list to {}: {}", path.display(), e);
+    }
+}
+
+// Words masked in chat and emotes and refused in names, from the file named
+// by SERVER_FILTER_FILE (one word per line). Matching is by whole word and
+// ignores case; without a file nothing is filtered.
+#[derive(Default)]
+struct WordFilter {
+    words: HashSet<String>,
+}
+
+impl WordFilter {
+    fn load() -> WordFilter {
+        let Some(path) = env::var("SERVER_FILTER_FILE").ok().filter(|p| !p.is_empty()) else {
+            return WordFilter::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let words: HashSet<String> = text.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_lowercase).collect();
+                info!("filtering {} words from {}", words.len(), path);
+                WordFilter { words }
+            }
+            Err(e) => {
+                warn!("could not read word filter {}: {}", path, e);
+                WordFilter::default()
+            }
+        }
+    }
+
+    // `text` with every filtered word replaced by as many asterisks, e.g.
+    // "Darn it" -> "**** it" when "darn" is filtered.
+    fn mask(&self, text: &str) -> String {
+        if self.words.is_empty() {
+            return text.to_string();
+        }
+        let mut masked = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(char::is_alphanumeric) {
+            masked.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len());
+            let word = &rest[..end];
+            if self.words.contains(&word.to_lowercase()) {
+                masked.extend(word.chars().map(|_| '*'));
             } else {
-                // not framed: broadcast raw
-                let mut buff = recv_msg.into_bytes();
-                buff.resize(MSG_SIZE, 0);
-                send_to_all(&mut clients, &buff);
+                masked.push_str(word);
             }
+            rest = &rest[end..];
         }
+        masked.push_str(rest);
+        masked
+    }
 
-        sleep();
+    fn blocks(&self, text: &str) -> bool {
+        self.mask(text) != text
     }
 }
 
-fn handle_hangman_command(
-    clients: &mut Vec<(TcpStream, String, String)>,
-    sender: &str,
-    content: &str,
-    hangman_state: &mut Option<GameState>,
-) {
-    // get display name of sender
-    let sender_name = clients.iter().find(|(_, addr, _)| addr == sender).map(|(_, _, d)| d.clone()).unwrap_or_else(|| sender.to_string());
-
-    if let Some(rest) = content.strip_prefix(":hang start") {
-        if hangman_state.is_some() {
-            send_to_client_text(clients, sender, "hangman: game already active");
-            return;
+// Saves every running game to the save directory, one file per room.
+fn autosave_games(games: &HashMap<String, HangmanRound>) {
+    if games.is_empty() { return; }
+    let dir = save_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("could not create save directory {}: {}", dir.display(), e);
+        return;
+    }
+    for (room, round) in games {
+        let path = dir.join(format!("{}{}.json", AUTOSAVE_PREFIX, room));
+        match save_game(&round.game, &path) {
+            Ok(()) => info!("saved hangman game of {} to {}", room, path.display()),
+            Err(e) => warn!("could not save hangman game of {}: {}", room, e),
         }
+    }
+}
 
-        let secret = rest.trim();
-        if secret.is_empty() {
-            send_to_client_text(clients, sender, "usage: :hang start <word>");
-            return;
+// Loads the games written by autosave_games back into their rooms and removes
+// the autosave files, so the same round is not resumed twice.
+fn resume_games(games: &mut HashMap<String, HangmanRound>, dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return; };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(room) = path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(AUTOSAVE_PREFIX))
+            .and_then(|n| n.strip_suffix(".json"))
+            .map(String::from) else { continue; };
+        match load_game(&path) {
+            Ok(game) => {
+                info!("resumed hangman game in {}", room);
+                let mut round = HangmanRound::new(game, &room, None, &[]);
+                round.resumed_suggester = Some(round.game.suggester().to_string()).filter(|name| name != "the server");
+                games.insert(room.clone(), round);
+                let _ = fs::remove_file(&path);
+            }
+            Err(e) => warn!("could not resume {}: {}", path.display(), e),
         }
+    }
+}
 
-        *hangman_state = Some(create_hangman_match(
-            &sender,
-            secret,
-        ));
+// Simple utility to return a 50/50 result for the :flip command. .
+fn flip_coin() -> &'static str {
+    let mut rng = rand::thread_rng();
+    if rng.gen_bool(0.5) { "heads" } else { "tails" }
+}
 
-        let announce = format!(
-            "Hangman started by {}\n{}",
-            sender_name,
-            render_hangman_state(hangman_state.as_ref().unwrap())
-        );
+// Answers of the Magic 8-Ball (:8ball).
+const EIGHT_BALL_ANSWERS: [&str; 20] = [
+    "It is certain",
+    "It is decidedly so",
+    "Without a doubt",
+    "Yes definitely",
+    "You may rely on it",
+    "As I see it, yes",
+    "Most likely",
+    "Outlook good",
+    "Yes",
+    "Signs point to yes",
+    "Reply hazy, try again",
+    "Ask again later",
+    "Better not tell you now",
+    "Cannot predict now",
+    "Concentrate and ask again",
+    "Don't count on it",
+    "My reply is no",
+    "My sources say no",
+    "Outlook not so good",
+    "Very doubtful",
+];
 
-        send_to_all_text(clients, &announce);
-        return;
+fn shake_eight_ball() -> &'static str {
+    let mut rng = rand::thread_rng();
+    EIGHT_BALL_ANSWERS[rng.gen_range(0..EIGHT_BALL_ANSWERS.len())]
+}
+
+// Bounds for :roll, so a single roll cannot flood the room.
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+
+// Parses a dice spec like `2d6` (or `d6` for one die) into (count, sides).
+fn parse_dice(spec: &str) -> Result<(u32, u32), String> {
+    let spec = spec.to_ascii_lowercase();
+    let Some((count, sides)) = spec.split_once('d') else {
+        return Err(String::from("usage: :roll <NdM>, e.g. :roll 2d6"));
+    };
+    let count = if count.is_empty() { Ok(1) } else { count.parse::<u32>() };
+    let (Ok(count), Ok(sides)) = (count, sides.parse::<u32>()) else {
+        return Err(String::from("usage: :roll <NdM>, e.g. :roll 2d6"));
+    };
+    if !(1..=MAX_DICE).contains(&count) {
+        return Err(format!("invalid roll: between 1 and {} dice", MAX_DICE));
     }
+    if !(2..=MAX_SIDES).contains(&sides) {
+        return Err(format!("invalid roll: dice have between 2 and {} sides", MAX_SIDES));
+    }
+    Ok((count, sides))
+}
+
+// Rolls `count` dice with `sides` sides each.
+fn roll_dice(count: u32, sides: u32) -> Vec<u32> {
+    let mut rng = rand::thread_rng();
+    (0..count).map(|_| rng.gen_range(1..=sides)).collect()
+}
 
+// Default room every client is placed in on connect.
+const DEFAULT_ROOM: &str = "lobby";
 
-    // :hang end
-    if content.trim() == ":hang end" {
-        if hangman_state.is_none() {
-            send_to_client_text(clients, sender, "hangman: no active game");
-            return;
+// Directory for hangman snapshots (:hang save/load and the autosave on
+// Ctrl-C). Can be overridden with the SERVER_SAVE_DIR env var.
+const DEFAULT_SAVE_DIR: &str = "hangman_saves";
+
+// File name prefix of the per-room snapshots written on shutdown.
+const AUTOSAVE_PREFIX: &str = "autosave-";
+
+// Chat lines kept per room for new joiners and :history. Can be overridden
+// with the SERVER_HISTORY env var.
+const HISTORY_LEN: usize = 50;
+
+// Largest message (in bytes) the server accepts from a client. Can be
+// overridden with the SERVER_MAX_MSG env var.
+const MAX_MSG_LEN: usize = 4096;
+
+// Oversized messages a client may send before it is disconnected.
+const MAX_MSG_VIOLATIONS: u32 = 3;
+
+// Messages per second a client may send on average, and how many it may
+// send in a quick burst. Can be overridden with SERVER_RATE / SERVER_BURST.
+const RATE_PER_SEC: f64 = 5.0;
+const RATE_BURST: f64 = 10.0;
+
+// Outgoing messages a client may have queued. A client whose queue is full
+// does not keep up with the chat and is disconnected (slow consumer policy).
+const OUTBOX_CAPACITY: usize = 256;
+
+// How long a writer thread waits on a single socket write before it gives
+// up on the client.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The server pings every client this often. Anything a client sends counts
+// as an answer; a client that leaves MAX_MISSED_PINGS pings in a row
+// unanswered is considered gone (e.g. its network dropped without closing
+// the connection) and is removed.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_MISSED_PINGS: u32 = 3;
+
+// How often the main loop looks for idle clients when SERVER_IDLE_TIMEOUT
+// is set.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often the main loop checks timed hangman rounds, and so how late
+// after its deadline a round may end.
+const HANGMAN_CLOCK_INTERVAL: Duration = Duration::from_secs(1);
+
+// A client that sends the same chat line SPAM_REPEATS times within
+// SPAM_WINDOW is muted for SPAM_MUTE: its messages are dropped with a notice.
+// The main loop lifts mutes every MUTE_CHECK_INTERVAL.
+const SPAM_REPEATS: usize = 5;
+const SPAM_WINDOW: Duration = Duration::from_secs(10);
+const SPAM_MUTE: Duration = Duration::from_secs(30);
+const MUTE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Most options a :poll may offer.
+const MAX_POLL_OPTIONS: usize = 10;
+
+// Chat lines whose ids the server remembers, across all rooms; older lines
+// can no longer be edited or deleted.
+const RECENT_MESSAGES: usize = 500;
+
+// A running :poll. Every room can have one; votes are kept per connection
+// id, so each user has one vote and voting again replaces it.
+struct Poll {
+    creator: u64,
+    creator_name: String,
+    question: String,
+    options: Vec<String>,
+    votes: HashMap<u64, usize>,
+}
+
+impl Poll {
+    // Parses `"Question?" optA optB ...`.
+    fn parse(creator: u64, creator_name: String, args: &str) -> Result<Poll, String> {
+        let usage = || String::from("usage: :poll \"question\" <option> <option> ...");
+        let (question, options) = args.strip_prefix('"').and_then(|rest| rest.split_once('"')).ok_or_else(usage)?;
+        let options: Vec<String> = options.split_whitespace().map(String::from).collect();
+        if question.trim().is_empty() || options.len() < 2 {
+            return Err(usage());
         }
+        if options.len() > MAX_POLL_OPTIONS {
+            return Err(format!("a poll has at most {} options", MAX_POLL_OPTIONS));
+        }
+        Ok(Poll { creator, creator_name, question: question.trim().to_string(), options, votes: HashMap::new() })
+    }
 
-        hangman_state.take();
-        send_to_all_text(clients, "Hangman game ended");
-        return;
+    fn describe(&self) -> String {
+        let mut text = format!("poll by {}: {}", self.creator_name, self.question);
+        for (i, option) in self.options.iter().enumerate() {
+            text.push_str(&format!("\n  {}) {}", i + 1, option));
+        }
+        text.push_str(&format!("\nvote with :vote <1-{}>", self.options.len()));
+        text
     }
 
+    fn results(&self) -> String {
+        let mut text = format!("poll closed: {}", self.question);
+        for (i, option) in self.options.iter().enumerate() {
+            let count = self.votes.values().filter(|&&vote| vote == i).count();
+            text.push_str(&format!("\n  {}) {} - {} vote{}", i + 1, option, count, if count == 1 { "" } else { "s" }));
+        }
+        text
+    }
+}
 
-    if let Some(rest) = content.strip_prefix(":hang guess ") {
-        let Some(game) = hangman_state.as_mut() else {
-            send_to_client_text(clients, sender, "hangman: no active game");
-            return;
-        };
+// The most recent chat lines of every room, oldest first. Only chat, emotes
+// and coin flips are recorded, not system announcements.
+struct History {
+    capacity: usize,
+    rooms: HashMap<String, VecDeque<String>>,
+}
 
-        match check_letter(rest.trim(), game) {
-            Ok(true) => {
-                let msg = format!(
-                    "{} guessed '{}'\n{}",
-                    sender_name,
-                    rest.trim(),
-                    render_hangman_state(game)
-                );
-                if is_word_solved(&hangman_state.as_ref().unwrap()) {
-                   hangman_state.take(); 
-                }   
-                send_to_all_text(clients, &msg);
-            }
-            Ok(false) => {
-                let msg = format!(
-                    "{} guessed '{}' (wrong)\n{}",
-                    sender_name,
-                    rest.trim(),
-                    render_hangman_state(game)
-                );
-                send_to_all_text(clients, &msg);
-            }
-            Err(e) => {
-                send_to_client_text(clients, sender, &e);
-            }
+impl History {
+    fn new(capacity: usize) -> History {
+        History { capacity, rooms: HashMap::new() }
+    }
+
+    fn record(&mut self, room: &str, line: &str) {
+        if self.capacity == 0 { return; }
+        let lines = self.rooms.entry(room.to_string()).or_default();
+        if lines.len() == self.capacity {
+            lines.pop_front();
         }
-        return;
+        lines.push_back(line.to_string());
     }
 
+    // The last `n` lines of `room` behind a "--- history ---" marker, or None
+    // if nothing was said there yet.
+    fn replay(&self, room: &str, n: usize) -> Option<String> {
+        let lines = self.rooms.get(room).filter(|lines| !lines.is_empty() && n > 0)?;
+        let mut resp = String::from("--- history ---");
+        for line in lines.iter().skip(lines.len().saturating_sub(n)) {
+            resp.push('\n');
+            resp.push_str(line);
+        }
+        Some(resp)
+    }
 }
 
-// try_client_name_assignment centralizes the name-change flow. It follows a
-// small three-phase approach:
-//  1) read-only checks for name collisions and the previous name
-//  2) mutate the client's display_name if the name is available
-//  3) send appropriate messages (reject, confirmation or announce) after
-//     the mutation so there are no active borrows when writing to sockets
-// This ordering prevents borrow/ownership conflicts when updating the
-// `clients` Vec while also writing to streams owned by the same Vec.
-fn try_client_name_assignment(
-    clients: &mut Vec<(TcpStream, String, String)>, 
-    name_rejected: &mut HashSet<String>, 
-    sender: &str, 
-    content: &str,
-) {
-    let name = content[6..].to_string();
-    println!("Registering name '{}' for {}", name, sender);
+#[derive(Clone, Copy)]
+struct RateLimit {
+    per_sec: f64,
+    burst: f64,
+}
 
-    // ---- PHASE 1: READ ONLY ----
-    let name_taken = clients
-        .iter()
-        .any(|(_, addr, disp)| addr != sender && disp == &name);
+// Token bucket limiting how fast one client may send. Every message takes a
+// token; tokens refill at `per_sec` up to `burst`.
+struct RateLimiter {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+    last_notice: Option<Instant>,
+}
 
-    let previous_name = clients
-        .iter()
-        .find(|(_, addr, _)| addr == sender)
-        .map(|(_, _, disp)| disp.clone());
+impl RateLimiter {
+    fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter { limit, tokens: limit.burst, last_refill: Instant::now(), last_notice: None }
+    }
 
-    // ---- PHASE 2: MUTATE STATE ----
-    if !name_taken {
-        for (_stream, addr, disp) in clients.iter_mut() {
-            if addr == sender {
-                *disp = name.clone();
-                break;
-            }
+    // Takes a token if one is available.
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_sec).min(self.limit.burst);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
         }
+        self.tokens -= 1.0;
+        true
     }
 
-    // ---- PHASE 3: SEND MESSAGES (no borrows alive) ----
-    if name_taken {
-        let reject = format!(
-            "name_taken: {}\nchange the name with :name <new_name>",
-            name
-        );
-        let mut buf = reject.into_bytes();
-        buf.resize(MSG_SIZE, 0);
+    // True if the client should be told it is throttled: at most once per
+    // second, however many messages it keeps sending.
+    fn should_notify(&mut self) -> bool {
+        let now = Instant::now();
+        if self.last_notice.is_some_and(|t| now.duration_since(t) < Duration::from_secs(1)) {
+            return false;
+        }
+        self.last_notice = Some(now);
+        true
+    }
+}
 
-        send_to_client(clients, sender, &buf);
-        name_rejected.insert(sender.to_string());
-        return;
+// The main loop's handle on one connection: its id, the outbound queue, its
+// rate limiter, its away status, when it connected, whether it is an
+// operator and a flag shared with the reader thread. Setting the flag tells the reader to
+// stop, so a client removed by the main loop does not leave its reader
+// running. Clients are routed by id rather than by peer addr, which may be
+// reused by a quick reconnect.
+struct Connection {
+    id: u64,
+    // the client's place under SERVER_MAX_CLIENTS, given back on drop
+    _slot: ClientSlot,
+    outbox: SyncSender<ServerMessage>,
+    limiter: RateLimiter,
+    // Some(reason) while the user is away; the reason may be empty
+    away: Option<String>,
+    connected_at: Instant,
+    // set once the client authenticated with :op
+    operator: bool,
+    closed: Arc<AtomicBool>,
+    // protocol the client speaks, see MODE_TEXT
+    mode: Arc<AtomicU8>,
+    // pings sent since the client was last heard from
+    missed_pings: u32,
+    // when the client last sent a message; pongs do not count
+    last_active: Instant,
+    // what the client negotiated with a hello; None if it skipped the
+    // handshake
+    hello: Option<Hello>,
+    // when the client's last typing notification was passed on
+    typing_sent: Option<Instant>,
+    // hashes of the chat lines sent within SPAM_WINDOW, oldest first
+    recent_lines: VecDeque<(u64, Instant)>,
+    // set while the client is muted for repeating itself
+    muted_until: Option<Instant>,
+}
+
+impl Connection {
+    // A fresh handle: not away, not an operator, active as of now. `closed`
+    // and `mode` are shared with the connection's reader and writer threads.
+    fn new(id: u64, slot: ClientSlot, outbox: SyncSender<ServerMessage>, rate: RateLimit, closed: Arc<AtomicBool>, mode: Arc<AtomicU8>) -> Connection {
+        let now = Instant::now();
+        Connection { id, _slot: slot, outbox, limiter: RateLimiter::new(rate), away: None, connected_at: now, operator: false, closed, mode, missed_pings: 0, last_active: now, hello: None, typing_sent: None, recent_lines: VecDeque::new(), muted_until: None }
     }
 
-    if name_rejected.remove(sender) {
-        let confirm = format!("{} is unique and was appended to your client!", name);
-        let mut buf = confirm.into_bytes();
-        buf.resize(MSG_SIZE, 0);
-        send_to_client(clients, sender, &buf);
+    // Whether the client can handle `capability` (see protocol::CAPABILITIES).
+    // Clients that skipped the handshake are assumed to handle everything
+    // their mode allows, as before it existed, except the capabilities that
+    // have to be asked for.
+    fn supports(&self, capability: &str) -> bool {
+        match &self.hello {
+            Some(hello) => hello.has(capability),
+            None => !OPT_IN_CAPABILITIES.contains(&capability),
+        }
     }
 
-    let announce = match previous_name {
-        Some(prev) if prev != sender && prev != name =>
-            format!("{} changed their name to {}", prev, name),
-        _ => format!("{} joined", name),
-    };
+    // Remembers chat line `content` and reports whether it is the
+    // SPAM_REPEATS-th copy within SPAM_WINDOW.
+    fn repeats(&mut self, content: &str, now: Instant) -> bool {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+        while self.recent_lines.front().is_some_and(|(_, at)| now.duration_since(*at) > SPAM_WINDOW) {
+            self.recent_lines.pop_front();
+        }
+        self.recent_lines.push_back((hash, now));
+        self.recent_lines.iter().filter(|(line, _)| *line == hash).count() >= SPAM_REPEATS
+    }
+
+    // Queues a message for the writer thread. Fails if the queue is full
+    // (slow consumer) or the writer has stopped.
+    fn send(&self, msg: impl Into<ServerMessage>) -> bool {
+        self.outbox.try_send(msg.into()).is_ok()
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+// The send helpers only enqueue messages for the writer threads; clients
+// that fail are closed and removed, and the broadcasts return the removed
+// clients for Server::announce_dropped. send_to_client only closes the client,
+// see Server::drop_closed. Plain strings are sent as system
+// messages; the writer renders each message for the client's protocol.
+
+// Clients a send had to drop, as (room, display name).
+type Dropped = Vec<(String, String)>;
+
+// Helper: send a message to all clients (only those in `room` if given), removing any that fail
+#[must_use]
+fn send_to_all(clients: &mut Vec<(Connection, String, String, String)>, room: Option<&str>, msg: impl Into<ServerMessage>) -> Dropped {
+    let msg = msg.into();
+    log_chat(room, &msg.to_text());
+    trace!("sending to everyone in {}", room.unwrap_or("all rooms"));
+    let mut remove_idx: Vec<usize> = Vec::new();
+    for (i, (client, _addr, _disp, client_room)) in clients.iter_mut().enumerate() {
+        if room.is_some_and(|r| r != client_room) { continue; }
+        if !client.send(msg.clone()) { remove_idx.push(i); }
+    }
+    remove_failed(clients, remove_idx)
+}
+
+// Helper: close and remove the clients at `remove_idx` (ascending) after a
+// failed broadcast; returns them so the caller can announce them.
+#[must_use]
+fn remove_failed(clients: &mut Vec<(Connection, String, String, String)>, remove_idx: Vec<usize>) -> Dropped {
+    let mut dropped = Vec::new();
+    for i in remove_idx.into_iter().rev() {
+        let (client, _, name, room) = clients.remove(i);
+        client.close();
+        dropped.push((room, name));
+    }
+    dropped.reverse();
+    dropped
+}
+
+// Helper: send a message to all clients except the sender (by id), only those in `room` if given; remove failed clients
+#[must_use]
+fn send_to_others(clients: &mut Vec<(Connection, String, String, String)>, sender: u64, room: Option<&str>, msg: impl Into<ServerMessage>) -> Dropped {
+    let msg = msg.into();
+    log_chat(room, &msg.to_text());
+    trace!("sending to everyone but client {} in {}", sender, room.unwrap_or("all rooms"));
+    let mut remove_idx: Vec<usize> = Vec::new();
+    for (i, (client, _addr, _disp, client_room)) in clients.iter_mut().enumerate() {
+        if client.id == sender { continue; }
+        if room.is_some_and(|r| r != client_room) { continue; }
+        if !client.send(msg.clone()) { remove_idx.push(i); }
+    }
+    remove_failed(clients, remove_idx)
+}
+
+// Helper: send chat line `numbered` (the same line as `plain`, with its id)
+// to `room`. Clients that asked for "edit" get the numbered line, the sender
+// included, since such a client does not echo its own lines; the others get
+// the plain one, the sender only if `to_sender`.
+#[must_use]
+fn send_chat(clients: &mut Vec<(Connection, String, String, String)>, sender: u64, room: &str, plain: ServerMessage, numbered: ServerMessage, to_sender: bool) -> Dropped {
+    log_chat(Some(room), &plain.to_text());
+    trace!("sending chat from client {} to {}", sender, room);
+    let mut remove_idx: Vec<usize> = Vec::new();
+    for (i, (client, _addr, _disp, client_room)) in clients.iter_mut().enumerate() {
+        if room != client_room { continue; }
+        let sent = if client.supports("edit") {
+            client.send(numbered.clone())
+        } else if client.id != sender || to_sender {
+            client.send(plain.clone())
+        } else {
+            continue;
+        };
+        if !sent { remove_idx.push(i); }
+    }
+    remove_failed(clients, remove_idx)
+}
 
-    let mut buf = announce.into_bytes();
-    buf.resize(MSG_SIZE, 0);
-    send_to_others(clients, sender, &buf);
+// Helper: send an edit or delete frame to the clients in `room` that asked
+// for "edit"; the others keep the line as they first got it.
+#[must_use]
+fn send_to_editors(clients: &mut Vec<(Connection, String, String, String)>, room: &str, msg: ServerMessage) -> Dropped {
+    let mut remove_idx: Vec<usize> = Vec::new();
+    for (i, (client, _addr, _disp, client_room)) in clients.iter_mut().enumerate() {
+        if room != client_room || !client.supports("edit") { continue; }
+        if !client.send(msg.clone()) { remove_idx.push(i); }
+    }
+    remove_failed(clients, remove_idx)
 }
 
+// Helper: send a typing notification to the other clients in `room` that
+// asked for "typing". Not logged, unlike broadcasts.
+#[must_use]
+fn send_to_typists(clients: &mut Vec<(Connection, String, String, String)>, sender: u64, room: &str, msg: ServerMessage) -> Dropped {
+    let mut remove_idx: Vec<usize> = Vec::new();
+    for (i, (client, _addr, _disp, client_room)) in clients.iter_mut().enumerate() {
+        if client.id == sender || room != client_room || !client.supports("typing") { continue; }
+        if !client.send(msg.clone()) { remove_idx.push(i); }
+    }
+    remove_failed(clients, remove_idx)
+}
+
+// Helper: send a message only to a single client (by id); close it if that
+// fails. It stays in the list until Server::drop_closed removes and announces
+// it, since most callers cannot pass on who was dropped.
+// Returns whether the message was queued for the client: false if there is no
+// such client, it is already closed or its queue is full or closed.
+fn send_to_client(clients: &mut [(Connection, String, String, String)], recipient: u64, msg: impl Into<ServerMessage>) -> bool {
+    let Some((client, _, _, _)) = clients.iter().find(|(client, _, _, _)| client.id == recipient) else { return false; };
+    if !client.is_closed() && client.send(msg) {
+        return true;
+    }
+    trace!("dropping client {}: its queue failed", recipient);
+    client.close();
+    false
+}
+
+// Helper: drop a client that went away and tell the rest of its room
+// "<name> <how>" (e.g. "left"). The queue is dropped rather than closed, so
+// the writer still flushes whatever was queued for the client before shutting
+// the socket down, which also ends the reader thread.
+// Returns the clients the announcement had to drop.
+#[must_use]
+fn remove_client(clients: &mut Vec<(Connection, String, String, String)>, name_rejected: &mut HashSet<u64>, id: u64, how: &str) -> Dropped {
+    let Some(pos) = clients.iter().position(|(client, _, _, _)| client.id == id) else { return Vec::new(); };
+    let (client, _, name, room) = clients.remove(pos);
+    info!("{} disconnected after {}", name, format_uptime(client.connected_at));
+    name_rejected.remove(&id);
+    send_to_others(clients, id, Some(&room), format!("{} {}", name, how))
+}
+
+// Helper: list of display names, as sent for :list. Away users are
+// annotated with their reason.
+fn user_list(clients: &[(Connection, String, String, String)]) -> ServerMessage {
+    let names = clients
+        .iter()
+        .map(|(client, _, disp, _)| match client.away.as_deref() {
+            Some("") => format!("{} (away)", disp),
+            Some(reason) => format!("{} (away: {})", disp, reason),
+            None => disp.clone(),
+        })
+        .collect();
+    ServerMessage::UserList { names }
+}
+
+// Helper: room the client (by id) is currently in
+fn client_room(clients: &[(Connection, String, String, String)], id: u64) -> String {
+    clients.iter().find(|(c, _, _, _)| c.id == id).map(|(_, _, _, room)| room.clone()).unwrap_or_else(|| DEFAULT_ROOM.to_string())
+}
+
+// Everything the main loop reacts to arrives through one channel, so it can
+// block until there is work instead of polling.
+enum Event {
+    // A new client was accepted, with its writer thread.
+    Connected(Box<Connection>, SocketAddr, thread::JoinHandle<()>),
+    // A message from the client with the given connection id.
+    Message(u64, String),
+    // The client opened with a hello; this is what was agreed on.
+    Hello(u64, Hello),
+    // A private notice for a client, produced by its reader thread.
+    Notice(u64, String),
+    // The reader thread gave up on a client: send it a final notice and
+    // drop it.
+    Kick(u64, String),
+    // The client closed the connection or its socket failed.
+    Disconnected(u64),
+    // Time to ping every client, see HEARTBEAT_INTERVAL.
+    Heartbeat,
+    // Time to look for idle clients, see IDLE_CHECK_INTERVAL.
+    IdleCheck,
+    // Time to end timed hangman rounds that ran out, see HANGMAN_CLOCK_INTERVAL.
+    HangmanClock,
+    // Time to lift mutes that ran out, see MUTE_CHECK_INTERVAL.
+    MuteCheck,
+    // Ctrl-C was pressed.
+    Shutdown,
+}
+
+// Protocol a connection speaks, shared by its reader and writer threads.
+// Every connection starts in text mode; the reader switches it to JSON when
+// the client opens with the JSON handshake byte, and to line mode when the
+// first byte is anything other than the zero byte that starts a text frame
+// (i.e. someone typing into netcat or telnet).
+const MODE_TEXT: u8 = 0;
+const MODE_JSON: u8 = 1;
+const MODE_LINE: u8 = 2;
+
+// ANSI "erase screen, cursor home", sent as is to line-mode clients for :clear.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+// Writer thread body for one client: drains the outbound queue onto the
+// socket. The writing half of the transport arrives from the reader thread
+// once the connection is set up (after the TLS handshake, if any); messages
+// queue up until then. It stops when the main loop closes the client or
+// drops the queue, or when a write fails or times out, and then shuts the
+// socket down so the reader thread ends as well.
+fn write_client(tcp: TcpStream, socket: Receiver<Box<dyn Write + Send>>, outbox: Receiver<ServerMessage>, closed: Arc<AtomicBool>, mode: Arc<AtomicU8>) {
+    let _ = tcp.set_write_timeout(Some(WRITE_TIMEOUT));
+    let Ok(mut socket) = socket.recv() else {
+        let _ = tcp.shutdown(Shutdown::Both);
+        return;
+    };
+    for msg in outbox {
+        if closed.load(Ordering::SeqCst) { break; }
+        let frame = match mode.load(Ordering::SeqCst) {
+            MODE_JSON => encode_frame(&serde_json::to_string(&msg).unwrap_or_default()),
+            // a terminal clears itself when it sees the ANSI sequence
+            MODE_LINE if msg == ServerMessage::Clear => Ok(CLEAR_SCREEN.as_bytes().to_vec()),
+            MODE_LINE => Ok(format!("{}\n", msg.to_text().trim_end_matches('\n')).into_bytes()),
+            _ => encode_frame(&msg.to_text()),
+        };
+        // write_all rather than write_frame: a timed-out write must end the
+        // connection instead of being retried.
+        let Ok(frame) = frame else { continue; };
+        if socket.write_all(&frame).is_err() || socket.flush().is_err() { break; }
+    }
+    let _ = tcp.shutdown(Shutdown::Both);
+}
+
+// How the connections accepted on a listener are wrapped.
+#[derive(Clone)]
+enum Wrap {
+    Plain,
+    Tls(Arc<rustls::ServerConfig>),
+    #[cfg(feature = "websocket")]
+    WebSocket,
+}
+
+// Connection ids are shared by all listeners.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Connections holding a ClientSlot, across all listeners.
+static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+// What a client is told when SERVER_MAX_CLIENTS is reached.
+const SERVER_FULL: &str = "server full: too many clients are connected, try again later";
+
+// A connection's place under SERVER_MAX_CLIENTS. The accept thread takes it
+// before the client is handed to the main loop, and the Connection gives it
+// back when it is dropped, i.e. once the main loop has removed the client.
+// Counting in the accept thread keeps clients that the main loop has not
+// seen yet from slipping past the limit.
+struct ClientSlot;
+
+impl ClientSlot {
+    // None if `max` clients are already connected.
+    fn take(max: Option<usize>) -> Option<ClientSlot> {
+        CONNECTED
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| if max.is_some_and(|max| n >= max) { None } else { Some(n + 1) })
+            .ok()
+            .map(|_| ClientSlot)
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        CONNECTED.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Tells a client that the server is full and closes the connection. Runs on
+// a short-lived thread, since a TLS or WebSocket handshake may take a while;
+// the client never gets a reader thread or a place in the main loop. Closing
+// only the sending side first and reading what the client already sent (its
+// hello, say) keeps the notice from being lost to a connection reset.
+fn turn_away(socket: TcpStream, wrap: Wrap, addr: SocketAddr) {
+    let _ = socket.set_read_timeout(Some(WRITE_TIMEOUT));
+    let _ = socket.set_write_timeout(Some(WRITE_TIMEOUT));
+    let Ok(control) = socket.try_clone() else { return; };
+    if let Some(transport) = wrap_transport(socket, wrap, addr)
+        && let Ok((_, mut writer)) = transport.split()
+    {
+        let _ = write_frame(&mut writer, SERVER_FULL);
+    }
+    let _ = control.shutdown(Shutdown::Write);
+    let _ = io::copy(&mut (&control).take(MAX_MSG_LEN as u64), &mut io::sink());
+    let _ = control.shutdown(Shutdown::Both);
+}
+
+// Sets up the transport for a new client (the TLS handshake when the server
+// has a certificate) and hands its writing half to the writer thread. Runs
+// on the client's reader thread, so a slow handshake never holds up accept.
+fn open_transport(socket: TcpStream, wrap: Wrap, writer: SyncSender<Box<dyn Write + Send>>, addr: SocketAddr) -> Option<Box<dyn Read + Send>> {
+    let (reader, write_half) = wrap_transport(socket, wrap, addr)?.split().ok()?;
+    writer.send(write_half).ok()?;
+    Some(reader)
+}
+
+// The transport for a freshly accepted socket, after the TLS or WebSocket
+// handshake if the listener needs one.
+fn wrap_transport(socket: TcpStream, wrap: Wrap, addr: SocketAddr) -> Option<Box<dyn Transport>> {
+    let transport: Box<dyn Transport> = match wrap {
+        Wrap::Plain => Box::new(socket),
+        Wrap::Tls(config) => match TlsTransport::accept(config, socket) {
+            Ok(transport) => Box::new(transport),
+            Err(e) => {
+                warn!("tls handshake with {} failed: {}", addr, e);
+                return None;
+            }
+        },
+        #[cfg(feature = "websocket")]
+        Wrap::WebSocket => match WsTransport::accept(socket) {
+            Ok(transport) => Box::new(transport),
+            Err(e) => {
+                warn!("websocket handshake with {} failed: {}", addr, e);
+                return None;
+            }
+        },
+    };
+    Some(transport)
+}
+
+// Reader thread body for one client: performs blocking reads of
+// length-prefixed frames and forwards messages to the main loop via the
+// channel. All replies go through the main loop, so the writer thread is the
+// only one writing to the socket. Nothing a client sends may panic this
+// thread: bad input is dropped and I/O failures end the loop. The loop also
+// ends once the main loop has closed the client; the writer shuts the socket
+// down at that point, so a blocked read returns promptly.
+fn read_client(socket: Box<dyn Read + Send>, id: u64, addr: SocketAddr, tx: Sender<Event>, closed: Arc<AtomicBool>, max_len: usize, mode: Arc<AtomicU8>) {
+    let mut socket = BufReader::new(socket);
+    // A JSON client opens with the handshake byte and text frames start with
+    // a zero byte; anything else is a human typing lines.
+    let first = socket.fill_buf().ok().and_then(|buf| buf.first().copied());
+    if let Some(first) = first {
+        if first == JSON_HANDSHAKE {
+            socket.consume(1);
+            mode.store(MODE_JSON, Ordering::SeqCst);
+            info!("{} speaks the json protocol", addr);
+            let _ = tx.send(Event::Notice(id, String::from("protocol: json")));
+        } else if first != 0 {
+            mode.store(MODE_LINE, Ordering::SeqCst);
+            info!("{} speaks the line protocol", addr);
+        }
+    }
+    let json = mode.load(Ordering::SeqCst) == MODE_JSON;
+    let lines = mode.load(Ordering::SeqCst) == MODE_LINE;
+
+    let mut violations = 0;
+    // only the very first frame may be a hello
+    let mut first = true;
+    loop {
+        let frame = if lines { read_line_limited(&mut socket, max_len) } else { read_frame_limited(&mut socket, max_len) };
+        if closed.load(Ordering::SeqCst) {
+            info!("closing connection with: {}", addr);
+            break;
+        }
+        // JSON messages are turned into the equivalent text command, so the
+        // main loop handles both protocols the same way.
+        let frame = match frame {
+            Ok(Some(msg)) if json => match serde_json::from_str::<ClientMessage>(&msg) {
+                Ok(msg) => Ok(Some(msg.to_text())),
+                Err(e) => {
+                    debug!("dropping invalid json message from {}", addr);
+                    if tx.send(Event::Notice(id, format!("invalid json message: {}", e))).is_err() { break; }
+                    continue;
+                }
+            },
+            other => other,
+        };
+        match frame {
+            Ok(Some(msg)) => {
+                // The version handshake comes before anything else, so an
+                // incompatible client is turned away before it can chat.
+                let opening = std::mem::take(&mut first);
+                if let Some(hello) = Hello::parse(&msg).filter(|_| opening) {
+                    match hello.negotiate(&CAPABILITIES) {
+                        Ok(agreed) => {
+                            info!("{} speaks protocol {} ({})", addr, agreed.version, agreed.capabilities.join(","));
+                            if tx.send(Event::Hello(id, agreed)).is_err() { break; }
+                        }
+                        Err(rejection) => {
+                            info!("{} speaks unsupported protocol {}, closing", addr, hello.version);
+                            let _ = tx.send(Event::Kick(id, rejection));
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                // Tag with the connection id so main thread can identify
+                // sender; every command is handled there.
+                if tx.send(Event::Message(id, msg)).is_err() { break; }
+            },
+            // A frame that is not valid UTF-8 was still consumed whole, so
+            // the stream stays in sync: drop it and keep serving the client.
+            Err(ref err) if err.kind() == ErrorKind::InvalidData => {
+                debug!("dropping invalid utf8 frame from {}", addr);
+            }
+            // Oversized frames are skipped unread; a client that keeps
+            // sending them is disconnected.
+            Err(ref err) if err.kind() == ErrorKind::FileTooLarge => {
+                violations += 1;
+                warn!("dropping oversized frame from {} ({}/{})", addr, violations, MAX_MSG_VIOLATIONS);
+                if violations >= MAX_MSG_VIOLATIONS {
+                    let _ = tx.send(Event::Kick(id, String::from("too many oversized messages, disconnecting")));
+                    info!("closing connection with: {}", addr);
+                    break;
+                }
+                let notice = format!("message rejected: longer than {} bytes", max_len);
+                if tx.send(Event::Notice(id, notice)).is_err() { break; }
+            }
+            Ok(None) | Err(_) => {
+                info!("closing connection with: {}", addr);
+                let _ = tx.send(Event::Disconnected(id));
+                break;
+            }
+        }
+    }
+}
+
+// Accept thread body: blocks on accept and hands each new client to the main
+// loop before starting its reader thread, so the main loop always knows a
+// client before it sees any of its messages.
+fn accept_clients(server: TcpListener, tx: Sender<Event>, max_len: usize, rate: RateLimit, wrap: Wrap, max_clients: Option<usize>) {
+    for stream in server.incoming() {
+        let Ok(socket) = stream else { continue; };
+        let Ok(addr) = socket.peer_addr() else { continue; };
+        // A full server turns the client away without telling anyone else.
+        let Some(slot) = ClientSlot::take(max_clients) else {
+            info!("Server full, turning away {}", addr);
+            let wrap = wrap.clone();
+            thread::spawn(move || turn_away(socket, wrap, addr));
+            continue;
+        };
+        let Ok(write_handle) = socket.try_clone() else { continue; };
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        info!("Client {} connected (id {})", addr, id);
+
+        let (outbox_tx, outbox_rx) = mpsc::sync_channel::<ServerMessage>(OUTBOX_CAPACITY);
+        let closed = Arc::new(AtomicBool::new(false));
+        let mode = Arc::new(AtomicU8::new(MODE_TEXT));
+        let (writer_closed, writer_mode) = (closed.clone(), mode.clone());
+        let (half_tx, half_rx) = mpsc::sync_channel::<Box<dyn Write + Send>>(1);
+        let writer = thread::spawn(move || write_client(write_handle, half_rx, outbox_rx, writer_closed, writer_mode));
+        let handle = Connection::new(id, slot, outbox_tx, rate, closed.clone(), mode.clone());
+        if tx.send(Event::Connected(Box::new(handle), addr, writer)).is_err() { break; }
+
+        // Clone the transmitter for the new client thread. The client
+        // thread will send framed messages into the shared channel so the
+        // central loop can perform routing and broadcasting.
+        let tx = tx.clone();
+        let wrap = wrap.clone();
+        thread::spawn(move || {
+            let Some(reader) = open_transport(socket, wrap, half_tx, addr) else {
+                let _ = tx.send(Event::Disconnected(id));
+                return;
+            };
+            read_client(reader, id, addr, tx, closed, max_len, mode);
+        });
+    }
+}
+
+// Binds every address in a comma-separated list such as
+// `127.0.0.1:9090,[::1]:9090`. Addresses that cannot be bound are skipped
+// with a warning; if none could be bound, the last error is returned.
+fn bind_all(addrs: &str) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    let mut last_error = io::Error::new(ErrorKind::InvalidInput, "no address given");
+    for addr in addrs.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+        info!("Binding server to {}", addr);
+        match TcpListener::bind(addr) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => {
+                if e.kind() == ErrorKind::AddrInUse {
+                    error!("could not bind {}: {} (is another server running?)", addr, e);
+                } else {
+                    error!("could not bind {}: {}", addr, e);
+                }
+                last_error = e;
+            }
+        }
+    }
+    if listeners.is_empty() { Err(last_error) } else { Ok(listeners) }
+}
+
+// Sends `event()` to the main loop every `interval` until the loop is gone.
+fn start_timer(tx: Sender<Event>, interval: Duration, event: fn() -> Event) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if tx.send(event()).is_err() { break; }
+    });
+}
+
+// Counters for :stats and the metrics endpoint. Atomics, so the reader
+// threads and the metrics thread can read or bump them without going
+// through the main loop.
+struct Metrics {
+    clients: AtomicU64,
+    messages: AtomicU64,
+    hangman_games: AtomicU64,
+    flips: AtomicU64,
+}
+
+static METRICS: Metrics = Metrics {
+    clients: AtomicU64::new(0),
+    messages: AtomicU64::new(0),
+    hangman_games: AtomicU64::new(0),
+    flips: AtomicU64::new(0),
+};
+
+impl Metrics {
+    fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The :stats reply.
+    fn summary(&self) -> String {
+        format!(
+            "stats: {} clients connected, {} messages routed, {} hangman games started, {} coins flipped",
+            self.clients.load(Ordering::Relaxed),
+            self.messages.load(Ordering::Relaxed),
+            self.hangman_games.load(Ordering::Relaxed),
+            self.flips.load(Ordering::Relaxed),
+        )
+    }
+
+    // The counters in the Prometheus text format.
+    fn prometheus(&self) -> String {
+        let metrics = [
+            ("chat_clients", "gauge", "Connected clients.", &self.clients),
+            ("chat_messages_total", "counter", "Messages routed by the server.", &self.messages),
+            ("chat_hangman_games_total", "counter", "Hangman games started.", &self.hangman_games),
+            ("chat_flips_total", "counter", "Coins flipped.", &self.flips),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value.load(Ordering::Relaxed)));
+        }
+        text
+    }
+}
+
+// Serves the counters at GET /metrics on SERVER_METRICS_ADDR, one request
+// per connection. Anything else gets a 404.
+fn serve_metrics(listener: TcpListener) {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue; };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+        let mut request_line = String::new();
+        if BufReader::new(&mut stream).read_line(&mut request_line).is_err() { continue; }
+        let response = if request_line.starts_with("GET /metrics ") {
+            let body = METRICS.prometheus();
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+        } else {
+            String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        };
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
+
+// The chat itself: who is connected, where they are and everything the
+// commands keep track of. It never touches a socket: clients come in as
+// Connection handles whose outbound queues are drained by writer threads,
+// and main feeds it the events the reader threads and timers produce.
+struct Server {
+    // clients: Vec of (connection handle, peer_addr_string, display_name, room)
+    clients: Vec<(Connection, String, String, String)>,
+    // track clients who recently received a name_taken so we can confirm when they later pick a unique name
+    name_rejected: HashSet<u64>,
+    // running hangman games, one per room
+    hangman_games: HashMap<String, HangmanRound>,
+    // hangman wins per display name, kept for the whole server session
+    hangman_scores: HashMap<String, u32>,
+    // how the last hangman game in each room was started, for :hang restart
+    hangman_settings: HashMap<String, RoundSettings>,
+    // running polls, one per room
+    polls: HashMap<String, Poll>,
+    // room topics set with :topic
+    topics: HashMap<String, String>,
+    // who joined each room while it was empty; they may set its topic
+    room_creators: HashMap<String, u64>,
+    // banned names and address prefixes, see is_banned
+    bans: HashSet<String>,
+    filter: WordFilter,
+    aliases: Aliases,
+    history: History,
+    // sent to every client that picks its first name, see load_motd
+    motd: Option<String>,
+    // (message id, sender id, room) of the latest chat lines, oldest first,
+    // for :edit and :delete
+    recent_messages: VecDeque<(u64, u64, String)>,
+    next_message_id: u64,
+    timestamps: bool,
+    // :op is only available when a password is set
+    op_password: Option<String>,
+}
+
+impl Server {
+    fn new(bans: HashSet<String>, filter: WordFilter, aliases: Aliases, history: History, motd: Option<String>, timestamps: bool, op_password: Option<String>) -> Server {
+        Server {
+            clients: Vec::new(),
+            name_rejected: HashSet::new(),
+            hangman_games: HashMap::new(),
+            hangman_scores: HashMap::new(),
+            hangman_settings: HashMap::new(),
+            polls: HashMap::new(),
+            topics: HashMap::new(),
+            room_creators: HashMap::new(),
+            bans,
+            filter,
+            aliases,
+            history,
+            motd,
+            recent_messages: VecDeque::new(),
+            next_message_id: 1,
+            timestamps,
+            op_password,
+        }
+    }
+
+    // Adds a new client under its guest name in the default room. Dropping
+    // the handle of a banned client instead flushes the notice and closes
+    // the connection.
+    fn connect(&mut self, handle: Connection, addr: SocketAddr) {
+        if is_banned(&self.bans, "", &addr.to_string()) {
+            info!("refusing banned address {}", addr);
+            let _ = handle.send("you are banned from this server");
+            return;
+        }
+        // store (handle, addr, display_name, room) - display_name defaults to guest-<id>, room to the lobby
+        let name = guest_name(handle.id);
+        self.clients.push((handle, addr.to_string(), name, DEFAULT_ROOM.to_string()));
+    }
+
+    // A private notice for one client.
+    fn notify(&mut self, id: u64, notice: &str) {
+        send_to_client(&mut self.clients, id, notice);
+    }
+
+    // Drops a client and tells its room "<name> <how>".
+    fn remove(&mut self, id: u64, how: &str) {
+        let dropped = remove_client(&mut self.clients, &mut self.name_rejected, id, how);
+        self.announce_dropped(dropped);
+    }
+
+    // Tells each dropped client's room that it disconnected. The
+    // announcements may drop more clients, which are announced in turn;
+    // every round removes someone, so this ends.
+    fn announce_dropped(&mut self, mut dropped: Dropped) {
+        if dropped.is_empty() {
+            return;
+        }
+        while let Some((room, name)) = dropped.pop() {
+            info!("{} disconnected: could not be sent to", name);
+            dropped.extend(send_to_all(&mut self.clients, Some(&room), format!("{} disconnected", name)));
+        }
+        self.name_rejected.retain(|id| self.clients.iter().any(|(client, _, _, _)| client.id == *id));
+    }
+
+    // Removes the clients a private send failed on and announces them like
+    // the ones a broadcast dropped. The main loop calls this after every
+    // event.
+    fn drop_closed(&mut self) {
+        let failed = self.clients.iter().enumerate().filter(|(_, (client, _, _, _))| client.is_closed()).map(|(i, _)| i).collect();
+        let dropped = remove_failed(&mut self.clients, failed);
+        self.announce_dropped(dropped);
+    }
+
+    // Stores what the client negotiated and confirms it with the server's
+    // own hello.
+    fn hello(&mut self, id: u64, hello: Hello) {
+        let reply = hello.to_text();
+        if let Some((client, _, _, _)) = self.clients.iter_mut().find(|(client, _, _, _)| client.id == id) {
+            client.hello = Some(hello);
+        }
+        send_to_client(&mut self.clients, id, reply);
+    }
+
+    fn pong(&mut self, id: u64) {
+        if let Some((client, _, _, _)) = self.clients.iter_mut().find(|(client, _, _, _)| client.id == id) {
+            client.missed_pings = 0;
+        }
+    }
+
+    // Pings every client and removes those that left MAX_MISSED_PINGS in a
+    // row unanswered. People typing into netcat or telnet cannot answer
+    // pings, so line-mode clients are left alone, as are clients whose
+    // hello did not offer "ping".
+    fn heartbeat(&mut self) {
+        let mut unresponsive = Vec::new();
+        for (client, _, _, _) in self.clients.iter_mut() {
+            if client.mode.load(Ordering::SeqCst) == MODE_LINE || !client.supports("ping") { continue; }
+            if client.missed_pings >= MAX_MISSED_PINGS {
+                unresponsive.push(client.id);
+                client.close();
+            } else {
+                client.missed_pings += 1;
+                let _ = client.send(ServerMessage::Ping);
+            }
+        }
+        for id in unresponsive {
+            info!("client {} stopped answering pings", id);
+            self.remove(id, "timed out");
+        }
+    }
+
+    // Unmutes the clients whose SPAM_MUTE ran out at or before `now` and
+    // tells them so.
+    fn lift_mutes(&mut self, now: Instant) {
+        let mut unmuted = Vec::new();
+        for (client, _, _, _) in self.clients.iter_mut() {
+            if client.muted_until.is_some_and(|until| until <= now) {
+                client.muted_until = None;
+                unmuted.push(client.id);
+            }
+        }
+        for id in unmuted {
+            info!("client {} is no longer muted", id);
+            send_to_client(&mut self.clients, id, "you are no longer muted");
+        }
+    }
+
+    // Removes the clients that have not sent anything for `idle`. They are
+    // told why first; like a kick, the writer flushes the notice and then
+    // closes.
+    fn drop_idle(&mut self, idle: Duration) {
+        let idle_ids: Vec<u64> = self.clients.iter().filter(|(client, _, _, _)| client.last_active.elapsed() >= idle).map(|(client, _, _, _)| client.id).collect();
+        for id in idle_ids {
+            info!("client {} is idle, disconnecting", id);
+            self.notify(id, "disconnected for inactivity");
+            self.remove(id, "was disconnected for inactivity");
+        }
+    }
+
+    // Ends the timed hangman rounds whose deadline is at or before `now`
+    // and reveals their word, like :hang end with a "time's up".
+    fn expire_games(&mut self, now: Instant) {
+        let expired: Vec<String> = self.hangman_games.iter().filter(|(_, round)| round.deadline.is_some_and(|deadline| deadline <= now)).map(|(room, _)| room.clone()).collect();
+        for room in expired {
+            let Some(round) = self.hangman_games.remove(&room) else { continue; };
+            info!("hangman game in {} timed out", room);
+            let dropped = send_to_all(&mut self.clients, Some(&room), format!("Hangman: time's up! the word was: {}", round.game.secret_word()));
+            self.announce_dropped(dropped);
+        }
+    }
+
+    // Saves the running hangman games, says goodbye to everyone and drops
+    // every queue, which lets the writer threads flush and close.
+    fn shutdown(&mut self) {
+        autosave_games(&self.hangman_games);
+        // everyone is dropped right after, so there is nobody to tell
+        let _ = send_to_all(&mut self.clients, None, "server shutting down");
+        self.clients.clear();
+    }
+
+    // Routes one message from a client: commands are answered or acted on,
+    // anything else is chat for the sender's room.
+    fn handle_message(&mut self, sender: u64, content: &str) {
+        trace!("routing message from client {} ({} bytes)", sender, content.len());
+        let content = self.aliases.expand(content);
+        let content = content.as_ref();
+        let command = parse(content);
+        // A pong only answers a heartbeat: it is no activity and does not
+        // count against the rate limit.
+        if command == Command::Pong {
+            self.pong(sender);
+            return;
+        }
+        // :quit is an explicit disconnect, so it goes through even while the
+        // client is rate limited or muted. Removing the client closes its
+        // socket, which ends the reader thread; the room hears "<name> left"
+        // now instead of whenever the connection's end is noticed.
+        if command == Command::Quit {
+            self.remove(sender, "left");
+            return;
+        }
+        // Late messages from a client that was already removed are dropped.
+        let Some((client, _, _, _)) = self.clients.iter_mut().find(|(client, _, _, _)| client.id == sender) else {
+            return;
+        };
+        client.missed_pings = 0;
+        client.last_active = Instant::now();
+
+        // Read-only clients ("lurk" in their hello) watch without talking:
+        // they never show up as typing, and besides :name and :quit only the
+        // queries answered to them alone go through. Chat, whispers, games,
+        // files and anything else others would see are refused.
+        let query = matches!(
+            command,
+            Command::List | Command::Who(_) | Command::Rooms | Command::Help | Command::Version | Command::Motd
+                | Command::History(_) | Command::Ping(_) | Command::Name(_) | Command::NameCheck(_)
+        );
+        if client.supports("lurk") && !query {
+            if command != Command::Typing {
+                let _ = client.send("lurk: read-only clients cannot send messages");
+            }
+            return;
+        }
+
+        // Typing notifications are not chat: they bypass the rate limit
+        // (clients send them at most once per TYPING_INTERVAL, faster ones
+        // are dropped here) and are neither logged nor kept in history.
+        if command == Command::Typing {
+            if client.muted_until.is_some() || client.typing_sent.is_some_and(|sent| sent.elapsed() < TYPING_INTERVAL / 2) {
+                return;
+            }
+            client.typing_sent = Some(Instant::now());
+            let from = display_name(&self.clients, sender);
+            let room = client_room(&self.clients, sender);
+            let dropped = send_to_typists(&mut self.clients, sender, &room, ServerMessage::Typing { from });
+            self.announce_dropped(dropped);
+            return;
+        }
+
+        // Flood protection: messages over the client's rate are dropped
+        // before they can reach anyone else.
+        if !client.limiter.allow() {
+            if client.limiter.should_notify() {
+                let _ = client.send("slow down: message dropped (rate limit)");
+            }
+            return;
+        }
+
+        // Spam protection: a client that keeps sending the same line is
+        // muted for a while, which the rate limit alone does not catch.
+        let now = Instant::now();
+        if let Some(until) = client.muted_until {
+            let left = until.saturating_duration_since(now);
+            let left = (left.as_secs() + u64::from(left.subsec_nanos() > 0)).max(1);
+            let _ = client.send(format!("muted: message dropped, you can talk again in {}s", left));
+            return;
+        }
+        if matches!(command, Command::Chat(_) | Command::Me(_) | Command::Macro(_)) && client.repeats(content, now) {
+            client.muted_until = Some(now + SPAM_MUTE);
+            client.recent_lines.clear();
+            info!("muting client {} for repeating itself", sender);
+            let _ = client.send(format!(
+                "muted: you sent the same message {} times in {}s, your messages are dropped for {}s",
+                SPAM_REPEATS,
+                SPAM_WINDOW.as_secs(),
+                SPAM_MUTE.as_secs()
+            ));
+            return;
+        }
+        Metrics::count(&METRICS.messages);
+
+        match command {
+            Command::NameCheck(requested) => {
+                handle_name_check(&mut self.clients, &self.bans, &self.filter, sender, requested);
+                return;
+            }
+            Command::Name("") => {
+                let dropped = reset_client_name(&mut self.clients, sender);
+                self.announce_dropped(dropped);
+                return;
+            }
+            Command::Name(requested) => {
+                if self.bans.contains(requested.trim()) {
+                    send_to_client(&mut self.clients, sender, "invalid name: this name is banned\nchange the name with :name <new_name>");
+                    return;
+                }
+                if self.filter.blocks(requested) {
+                    send_to_client(&mut self.clients, sender, "invalid name: this name is not allowed\nchange the name with :name <new_name>");
+                    return;
+                }
+                // New joiners get the message of the day and catch up on
+                // what was said in their room.
+                let (joined, dropped) = try_client_name_assignment(&mut self.clients, &mut self.name_rejected, sender, requested);
+                self.announce_dropped(dropped);
+                if joined {
+                    if let Some(motd) = &self.motd {
+                        send_to_client(&mut self.clients, sender, motd);
+                    }
+                    let room = client_room(&self.clients, sender);
+                    if let Some(topic) = self.topics.get(&room) {
+                        send_to_client(&mut self.clients, sender, format!("topic for {}: {}", room, topic));
+                    }
+                    if let Some(replay) = self.history.replay(&room, usize::MAX) {
+                        send_to_client(&mut self.clients, sender, &replay);
+                    }
+                }
+                return;
+            }
+            Command::Hang(_) => {
+                let dropped = handle_hangman_command(&mut self.clients, sender, content, &mut self.hangman_games, &mut self.hangman_scores, &mut self.hangman_settings);
+                self.announce_dropped(dropped);
+                return;
+            }
+            Command::Whisper(rest) => {
+                handle_whisper(&mut self.clients, sender, rest);
+                return;
+            }
+            Command::File(line) => {
+                handle_file(&mut self.clients, sender, line);
+                return;
+            }
+            // Whoever joins an empty room created it; joiners are told the
+            // room's topic.
+            Command::Join(args) => {
+                let created = args != DEFAULT_ROOM && !self.clients.iter().any(|(_, _, _, room)| room == args);
+                let (joined, dropped) = handle_join(&mut self.clients, sender, args);
+                self.announce_dropped(dropped);
+                if joined {
+                    if created {
+                        self.room_creators.insert(args.to_string(), sender);
+                    }
+                    if let Some(topic) = self.topics.get(args) {
+                        send_to_client(&mut self.clients, sender, format!("topic for {}: {}", args, topic));
+                    }
+                }
+                return;
+            }
+
+            // :topic [text] - show the room's topic, or set it (operators
+            // and the room's creator)
+            Command::Topic(text) => {
+                let room = client_room(&self.clients, sender);
+                if text.is_empty() {
+                    let reply = match self.topics.get(&room) {
+                        Some(topic) => format!("topic for {}: {}", room, topic),
+                        None => format!("no topic set for {}", room),
+                    };
+                    send_to_client(&mut self.clients, sender, reply);
+                    return;
+                }
+                let operator = self.clients.iter().any(|(client, _, _, _)| client.id == sender && client.operator);
+                if !operator && self.room_creators.get(&room) != Some(&sender) {
+                    send_to_client(&mut self.clients, sender, "topic: only operators and the room's creator can set the topic");
+                    return;
+                }
+                let topic = self.filter.mask(text);
+                let announce = format!("{} set the topic: {}", display_name(&self.clients, sender), topic);
+                info!("topic for {} set by client {}", room, sender);
+                self.topics.insert(room.clone(), topic);
+                let dropped = send_to_all(&mut self.clients, Some(&room), announce);
+                self.announce_dropped(dropped);
+                return;
+            }
+
+            // :away [reason] / :back only change the sender's own status; the
+            // other users see it in :list and when they whisper.
+            Command::Away(reason) => {
+                let reason = reason.to_string();
+                let reply = if reason.is_empty() { String::from("you are marked as away") } else { format!("you are marked as away: {}", reason) };
+                if let Some((client, _, _, _)) = self.clients.iter_mut().find(|(client, _, _, _)| client.id == sender) {
+                    client.away = Some(reason);
+                }
+                send_to_client(&mut self.clients, sender, &reply);
+                return;
+            }
+            Command::Back => {
+                if let Some((client, _, _, _)) = self.clients.iter_mut().find(|(client, _, _, _)| client.id == sender) {
+                    client.away = None;
+                }
+                send_to_client(&mut self.clients, sender, "welcome back");
+                return;
+            }
+
+            Command::Op(password) => {
+                let granted = self.op_password.as_deref() == Some(password);
+                if granted {
+                    if let Some((client, _, _, _)) = self.clients.iter_mut().find(|(client, _, _, _)| client.id == sender) {
+                        client.operator = true;
+                    }
+                    info!("client {} is now an operator", sender);
+                }
+                let reply = if granted { "you are now an operator" } else { "op: wrong password" };
+                send_to_client(&mut self.clients, sender, reply);
+                return;
+            }
+            Command::Kick(name) => {
+                let dropped = handle_kick(&mut self.clients, &mut self.name_rejected, sender, name);
+                self.announce_dropped(dropped);
+                return;
+            }
+            Command::Ban(target) => {
+                let dropped = handle_ban(&mut self.clients, &mut self.name_rejected, &mut self.bans, sender, target);
+                self.announce_dropped(dropped);
+                return;
+            }
+            Command::Unban(target) => {
+                handle_unban(&mut self.clients, &mut self.bans, sender, target);
+                return;
+            }
+
+            Command::Uptime => {
+                let Some((client, _, _, _)) = self.clients.iter().find(|(client, _, _, _)| client.id == sender) else { return; };
+                let reply = format!("you have been connected for {}", format_uptime(client.connected_at));
+                send_to_client(&mut self.clients, sender, &reply);
+                return;
+            }
+            Command::Who(name) => {
+                handle_who(&mut self.clients, sender, name);
+                return;
+            }
+            Command::Poll(args) => {
+                let dropped = handle_poll(&mut self.clients, &mut self.polls, sender, args);
+                self.announce_dropped(dropped);
+                return;
+            }
+            Command::Vote(args) => {
+                handle_vote(&mut self.clients, &mut self.polls, sender, args);
+                return;
+            }
+
+            // :clear only concerns the sender's own screen, and is not sent
+            // to clients that said they cannot handle it
+            Command::Clear => {
+                if self.clients.iter().any(|(client, _, _, _)| client.id == sender && client.supports("clear")) {
+                    send_to_client(&mut self.clients, sender, ServerMessage::Clear);
+                }
+                return;
+            }
+            Command::Help => {
+                let mut help = help_text("");
+                let mut aliases: Vec<(&String, &String)> = self.aliases.map.iter().collect();
+                aliases.sort();
+                for (name, target) in aliases {
+                    help.push_str(&format!("\n{} - same as {}", name, target));
+                }
+                send_to_client(&mut self.clients, sender, help);
+                return;
+            }
+            Command::Stats => {
+                let is_operator = self.clients.iter().any(|(client, _, _, _)| client.id == sender && client.operator);
+                let reply = if is_operator { METRICS.summary() } else { String::from("stats: only operators may see the stats (see :op)") };
+                send_to_client(&mut self.clients, sender, reply);
+                return;
+            }
+            // :ping <token> - echoed to the sender only, for the round trip
+            Command::Ping(token) => {
+                send_to_client(&mut self.clients, sender, ServerMessage::Pong { token: token.to_string() });
+                return;
+            }
+            Command::Motd => {
+                let reply = self.motd.as_deref().unwrap_or("no MOTD set");
+                send_to_client(&mut self.clients, sender, reply);
+                return;
+            }
+            Command::Version => {
+                send_to_client(&mut self.clients, sender, version_text());
+                return;
+            }
+            Command::Scores => {
+                send_to_client(&mut self.clients, sender, format_leaderboard(&self.hangman_scores));
+                return;
+            }
+
+            // Handle a private :rooms request: list every room that has
+            // at least one occupant along with its occupant count.
+            Command::Rooms => {
+                let mut counts: Vec<(String, usize)> = Vec::new();
+                for (_, _, _, room) in &self.clients {
+                    match counts.iter_mut().find(|(r, _)| r == room) {
+                        Some((_, n)) => *n += 1,
+                        None => counts.push((room.clone(), 1)),
+                    }
+                }
+                counts.sort();
+                let mut resp = String::from("rooms:\n");
+                for (room, n) in counts {
+                    resp.push_str(&format!("{} ({})\n", room, n));
+                }
+                send_to_client(&mut self.clients, sender, &resp);
+                return;
+            }
+
+            // Handle a private :list request. The requesting client
+            // asks for the current list of display names. Build a
+            // multi-line response and send it only to that client.
+            Command::List => {
+                // write only to the requesting client (don't move the clients vec)
+                let resp = user_list(&self.clients);
+                send_to_client(&mut self.clients, sender, resp);
+                return;
+            }
+
+            // :me <action> - IRC-style emote, shown as "* name action". Unlike
+            // normal chat the sender sees it too.
+            Command::Me(action) => {
+                let emote = format!("* {} {}", display_name(&self.clients, sender), self.filter.mask(action));
+                let emote = if self.timestamps { format!("[{}] {}", timestamp(), emote) } else { emote };
+                debug!("{}", emote);
+                let room = client_room(&self.clients, sender);
+                self.history.record(&room, &emote);
+                let dropped = send_to_all(&mut self.clients, Some(&room), &emote);
+                self.announce_dropped(dropped);
+                return;
+            }
+
+            // :roll NdM - roll dice for the whole room, e.g. "kai rolled 2d6: 4+3 = 7".
+            // Malformed or out of range specs are answered privately.
+            Command::Roll(args) => {
+                let (count, sides) = match parse_dice(args) {
+                    Ok(dice) => dice,
+                    Err(e) => {
+                        send_to_client(&mut self.clients, sender, e);
+                        return;
+                    }
+                };
+                let rolls = roll_dice(count, sides);
+                let total: u32 = rolls.iter().sum();
+                let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+                let result = format!("{} rolled {}d{}: {} = {}", display_name(&self.clients, sender), count, sides, rolls.join("+"), total);
+                let result = if self.timestamps { format!("[{}] {}", timestamp(), result) } else { result };
+                debug!("{}", result);
+                let room = client_room(&self.clients, sender);
+                self.history.record(&room, &result);
+                let dropped = send_to_all(&mut self.clients, Some(&room), &result);
+                self.announce_dropped(dropped);
+                return;
+            }
+
+            // :8ball <question> - a random answer for the whole room
+            Command::EightBall(args) => {
+                if args.is_empty() {
+                    send_to_client(&mut self.clients, sender, "usage: :8ball <question>");
+                    return;
+                }
+                let answer = format!("{} asked the 8-ball: {}", display_name(&self.clients, sender), shake_eight_ball());
+                let answer = if self.timestamps { format!("[{}] {}", timestamp(), answer) } else { answer };
+                debug!("{}", answer);
+                let room = client_room(&self.clients, sender);
+                self.history.record(&room, &answer);
+                let dropped = send_to_all(&mut self.clients, Some(&room), &answer);
+                self.announce_dropped(dropped);
+                return;
+            }
+
+            // :history [n] - re-send the last n (default all kept) lines of the room
+            Command::History(args) => {
+                let n = if args.is_empty() { Some(usize::MAX) } else { args.parse().ok() };
+                let Some(n) = n else {
+                    send_to_client(&mut self.clients, sender, "usage: :history [n]");
+                    return;
+                };
+                let room = client_room(&self.clients, sender);
+                let reply = self.history.replay(&room, n).unwrap_or_else(|| String::from("no history yet"));
+                send_to_client(&mut self.clients, sender, &reply);
+                return;
+            }
+
+            // :flip - the result is chat from the sender, shown to the
+            // sender too
+            Command::Flip => {
+                let result = flip_coin();
+                Metrics::count(&METRICS.flips);
+                debug!("client {} requested flip -> {}", sender, result);
+                self.chat(sender, &format!("flipped: {}", result), true);
+                return;
+            }
+
+            Command::Edit(args) => {
+                self.edit(sender, args, false);
+                return;
+            }
+            Command::Delete(id) => {
+                self.edit(sender, id, true);
+                return;
+            }
+
+            // :shrug and the other text macros - chat from the sender,
+            // shown to the sender too, like a flip
+            Command::Macro(text) => {
+                self.chat(sender, text, true);
+                return;
+            }
+
+            // :send reads a local file, so only the bundled client can do
+            // it; anyone else typing it should not have it shown as chat
+            Command::SendFile(_) => {
+                send_to_client(&mut self.clients, sender, "send: :send is a client command (use the bundled client)");
+                return;
+            }
+
+            Command::Chat(_) | Command::Pong | Command::Typing | Command::Quit => {}
+        }
+
+        self.chat(sender, content, false);
+    }
+
+    // Sends `body` as a chat line from `sender` to the sender's room. Normal
+    // chat skips the sender, whose client echoes it locally.
+    fn chat(&mut self, sender: u64, body: &str, to_sender: bool) {
+        let sender_name = display_name(&self.clients, sender);
+        let from = sender_name;
+        let body = self.filter.mask(body);
+        let ts = self.timestamps.then(timestamp);
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        let numbered = ServerMessage::Chat { from: from.clone(), body: body.clone(), ts: ts.clone(), id: Some(id) };
+        let chat = ServerMessage::Chat { from, body, ts, id: None };
+        let to_send_str = chat.to_text();
+
+        // server log using the sender name
+        debug!("{}", to_send_str);
+
+        // Messages only reach clients in the sender's room.
+        let room = client_room(&self.clients, sender);
+        self.history.record(&room, &to_send_str);
+        if self.recent_messages.len() == RECENT_MESSAGES {
+            self.recent_messages.pop_front();
+        }
+        self.recent_messages.push_back((id, sender, room.clone()));
+
+        // remove clients that fail; normal chat skips the sender
+        let dropped = send_chat(&mut self.clients, sender, &room, chat, numbered, to_sender);
+        self.announce_dropped(dropped);
+    }
+
+    // :edit <id> <text> / :delete <id> - change or withdraw one of the
+    // sender's recent chat lines. Only clients that asked for "edit" hear
+    // about it; history keeps the line as it was sent.
+    fn edit(&mut self, sender: u64, args: &str, delete: bool) {
+        let (command, usage) = if delete { ("delete", "usage: :delete <id>") } else { ("edit", "usage: :edit <id> <text>") };
+        let (id, text) = if delete { (args, "") } else { args.split_once(' ').unwrap_or((args, "")) };
+        let text = text.trim();
+        let Some(id) = id.parse::<u64>().ok().filter(|_| delete || !text.is_empty()) else {
+            send_to_client(&mut self.clients, sender, usage);
+            return;
+        };
+        let Some(pos) = self.recent_messages.iter().position(|(msg_id, _, _)| *msg_id == id) else {
+            send_to_client(&mut self.clients, sender, format!("{}: no such message: {}", command, id));
+            return;
+        };
+        if self.recent_messages[pos].1 != sender {
+            send_to_client(&mut self.clients, sender, format!("{}: you can only {} your own messages", command, command));
+            return;
+        }
+        let room = self.recent_messages[pos].2.clone();
+        let msg = if delete {
+            self.recent_messages.remove(pos);
+            ServerMessage::Delete { id }
+        } else {
+            ServerMessage::Edit { id, body: self.filter.mask(text) }
+        };
+        debug!("client {} {}s message {}", sender, command, id);
+        let dropped = send_to_editors(&mut self.clients, &room, msg);
+        self.announce_dropped(dropped);
+    }
+}
+
+// Startup problems (config, addresses, certificates) end the server with
+// an error; once it runs, failures only ever affect single clients.
+fn main() -> Result<(), Box<dyn Error>> {
+    // Connections and other lifecycle events are logged at info, chat and
+    // other per-message content at debug and routing at trace; RUST_LOG
+    // picks the level (e.g. RUST_LOG=warn for a quiet console).
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).target(env_logger::Target::Stdout).init();
+    let _ = CONFIG.set(load_config()?);
+    // :op is only available when SERVER_OP_PASSWORD is set
+    let op_password = setting("SERVER_OP_PASSWORD").filter(|p| !p.is_empty());
+    let aliases = Aliases { map: CONFIG.get().and_then(|config| config.aliases.clone()).unwrap_or_default() };
+    let mut server = Server::new(load_bans(ban_file().as_deref())?, WordFilter::load(), aliases, History::new(history_len()), load_motd(), timestamps_enabled(), op_password);
+    // broadcasts are appended to SERVER_LOG_FILE if set
+    let chat_log = start_chat_log();
+
+    // SERVER_RESUME_HANGMAN=1 picks up the games saved on the last Ctrl-C.
+    if env::var("SERVER_RESUME_HANGMAN").is_ok_and(|v| v == "1") {
+        resume_games(&mut server.hangman_games, &save_dir());
+    }
+
+    // Allow overriding the listening addresses via SERVER_ADDR environment variable.
+    let local = setting("SERVER_ADDR").unwrap_or_else(|| DEFAULT_LOCAL.to_string());
+    let listeners = bind_all(&local)
+        .map_err(|e| StartupError::boxed(format!("could not listen on {}: {}, set SERVER_ADDR to a free address", local, e)))?;
+    let wrap = match tls_config()? {
+        Some(config) => {
+            info!("tls enabled");
+            Wrap::Tls(config)
+        }
+        None => Wrap::Plain,
+    };
+
+    // writer threads, joined on shutdown so pending messages get flushed
+    let mut writers: Vec<thread::JoinHandle<()>> = Vec::new();
+    let (tx, rx) = mpsc::channel::<Event>();
+
+    // Ctrl-C is just another event: the main loop saves the running hangman
+    // games, says goodbye to every client and exits.
+    {
+        let tx = tx.clone();
+        ctrlc::set_handler(move || { let _ = tx.send(Event::Shutdown); })?;
+    }
+
+    // Timers wake the main loop to ping everyone, to drop idle clients and
+    // to end timed hangman rounds.
+    if let Some(interval) = heartbeat_interval() {
+        start_timer(tx.clone(), interval, || Event::Heartbeat);
+    }
+    start_timer(tx.clone(), HANGMAN_CLOCK_INTERVAL, || Event::HangmanClock);
+    start_timer(tx.clone(), MUTE_CHECK_INTERVAL, || Event::MuteCheck);
+    let idle = idle_timeout();
+    if idle.is_some() {
+        start_timer(tx.clone(), IDLE_CHECK_INTERVAL, || Event::IdleCheck);
+    }
+
+    let max_len = max_msg_len();
+    let max_clients = max_clients();
+    let rate = rate_limit();
+    // Browsers connect through a separate WebSocket listener, if one is
+    // configured.
+    #[cfg(feature = "websocket")]
+    if let Ok(ws_addr) = env::var("SERVER_WS_ADDR") {
+        for listener in bind_all(&ws_addr).unwrap_or_default() {
+            if let Ok(addr) = listener.local_addr() {
+                info!("Accepting websocket clients on {}", addr);
+            }
+            let tx = tx.clone();
+            thread::spawn(move || accept_clients(listener, tx, max_len, rate, Wrap::WebSocket, max_clients));
+        }
+    }
+    // Prometheus can scrape the counters from a side port.
+    if let Some(metrics_addr) = setting("SERVER_METRICS_ADDR") {
+        for listener in bind_all(&metrics_addr).unwrap_or_default() {
+            if let Ok(addr) = listener.local_addr() {
+                info!("Serving metrics on http://{}/metrics", addr);
+            }
+            thread::spawn(move || serve_metrics(listener));
+        }
+    }
+    // One accept thread per address; they all feed the same channel.
+    for listener in listeners {
+        let (tx, wrap) = (tx.clone(), wrap.clone());
+        thread::spawn(move || accept_clients(listener, tx, max_len, rate, wrap, max_clients));
+    }
+
+    // The main loop sleeps until an event arrives instead of polling.
+    while let Ok(event) = rx.recv() {
+        match event {
+            Event::Connected(handle, addr, writer) => {
+                writers.retain(|w| !w.is_finished());
+                writers.push(writer);
+                server.connect(*handle, addr);
+            }
+            // Graceful shutdown: tell everyone, then drop the queues and wait
+            // for the writer threads, which flush the farewell and close the
+            // sockets. Leaving main ends the process, which also closes the
+            // listener owned by the accept thread.
+            Event::Shutdown => {
+                server.shutdown();
+                for writer in writers.drain(..) {
+                    let _ = writer.join();
+                }
+                stop_chat_log(chat_log);
+                info!("server stopped");
+                break;
+            }
+            Event::Notice(id, notice) => server.notify(id, &notice),
+            Event::Kick(id, notice) => {
+                server.notify(id, &notice);
+                server.remove(id, "left");
+            }
+            Event::Disconnected(id) => server.remove(id, "left"),
+            Event::Hello(id, hello) => server.hello(id, hello),
+            Event::Heartbeat => server.heartbeat(),
+            Event::HangmanClock => server.expire_games(Instant::now()),
+            Event::MuteCheck => server.lift_mutes(Instant::now()),
+            Event::IdleCheck => {
+                if let Some(idle) = idle {
+                    server.drop_idle(idle);
+                }
+            }
+            Event::Message(id, msg) => server.handle_message(id, &msg),
+        }
+        server.drop_closed();
+        METRICS.clients.store(server.clients.len() as u64, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// A running hangman match in one room together with the order in which
+// players take turns. `turn_order` holds connection ids; the word suggester is
+// never part of it. Players who enter the room mid-game join the end of the
+// queue on their first guess, and players who left the room are skipped.
+struct HangmanRound {
+    game: GameState,
+    room: String,
+    // connection that chose the word, which may not guess or give tips. None
+    // if the server picked it or the game was loaded, since renames or a new
+    // client under the same name must not change who that is.
+    suggester: Option<u64>,
+    // name of whoever chose the word of a game resumed after a restart. The
+    // save file only knows names, so the first client to play under this
+    // name becomes `suggester`.
+    resumed_suggester: Option<String>,
+    turn_order: Vec<u64>,
+    turn: usize,
+    // set by `:hang start --timeout <secs>`; the game ends unsolved then
+    deadline: Option<Instant>,
+}
+
+// The options a room's last game was started with. `:hang restart` starts
+// the next game with them.
+struct RoundSettings {
+    timeout: Option<Duration>,
+    // difficulty of the random word when no word is given
+    level: Difficulty,
+}
+
+impl HangmanRound {
+    fn new(game: GameState, room: &str, suggester: Option<u64>, clients: &[(Connection, String, String, String)]) -> HangmanRound {
+        let turn_order = clients
+            .iter()
+            .filter(|(client, _, _, client_room)| client_room == room && Some(client.id) != suggester)
+            .map(|(client, _, _, _)| client.id)
+            .collect();
+        HangmanRound { game, room: room.to_string(), suggester, resumed_suggester: None, turn_order, turn: 0, deadline: None }
+    }
+
+    // Binds a resumed game's suggester to the first client that plays under
+    // their name, see `resumed_suggester`.
+    fn claim_suggester(&mut self, clients: &[(Connection, String, String, String)], sender: u64) {
+        if self.resumed_suggester.is_some() && self.resumed_suggester == Some(display_name(clients, sender)) {
+            self.resumed_suggester = None;
+            self.suggester = Some(sender);
+            self.turn_order.retain(|&id| id != sender);
+        }
+    }
+
+    // Drops players who disconnected or left the room and keeps `turn`
+    // pointing at the same player (or the next one if the current one left).
+    fn prune(&mut self, clients: &[(Connection, String, String, String)]) {
+        let mut i = 0;
+        while i < self.turn_order.len() {
+            if clients.iter().any(|(client, _, _, room)| client.id == self.turn_order[i] && room == &self.room) {
+                i += 1;
+                continue;
+            }
+            self.turn_order.remove(i);
+            if i < self.turn {
+                self.turn -= 1;
+            }
+        }
+        if self.turn >= self.turn_order.len() {
+            self.turn = 0;
+        }
+    }
+
+    // Ok if it is the sender's turn, otherwise the private refusal to send.
+    fn check_turn(&mut self, clients: &[(Connection, String, String, String)], sender: u64) -> Result<(), String> {
+        self.prune(clients);
+        if self.suggester != Some(sender) && !self.turn_order.contains(&sender) {
+            self.turn_order.push(sender);
+        }
+        match self.turn_order.get(self.turn) {
+            Some(&id) if id == sender => Ok(()),
+            Some(&id) => Err(format!("not your turn - it is {}'s turn", display_name(clients, id))),
+            None => Err(String::from("not your turn")),
+        }
+    }
+
+    // Passes the turn to the next player and returns their name.
+    fn advance_turn(&mut self, clients: &[(Connection, String, String, String)]) -> String {
+        self.turn = (self.turn + 1) % self.turn_order.len().max(1);
+        self.prune(clients);
+        self.turn_order.get(self.turn).map(|&id| display_name(clients, id)).unwrap_or_default()
+    }
+}
+
+// Helper: display name of a client (by id)
+fn display_name(clients: &[(Connection, String, String, String)], id: u64) -> String {
+    clients.iter().find(|(c, _, _, _)| c.id == id).map(|(_, _, d, _)| d.clone()).unwrap_or_default()
+}
+
+#[must_use]
+fn handle_hangman_command(
+    clients: &mut Vec<(Connection, String, String, String)>,
+    sender: u64,
+    content: &str,
+    games: &mut HashMap<String, HangmanRound>,
+    scores: &mut HashMap<String, u32>,
+    settings: &mut HashMap<String, RoundSettings>,
+) -> Dropped {
+    // get display name of sender; every room runs its own game
+    let sender_name = display_name(clients, sender);
+    let room = client_room(clients, sender);
+
+    // :hang restart [word] - once a game is over, start the next one with
+    // the same timeout. Without a word the server picks one of the same
+    // difficulty as before.
+    let mut restart = String::new();
+    let content = match content.strip_prefix(":hang restart") {
+        Some(word) if word.is_empty() || word.starts_with(' ') => {
+            if games.contains_key(&room) {
+                send_to_client(clients, sender, "hangman: the game is still running, finish it or :hang end first");
+                return Vec::new();
+            }
+            let Some(last) = settings.get(&room) else {
+                send_to_client(clients, sender, "hangman: nothing to restart, use :hang start");
+                return Vec::new();
+            };
+            let word = word.trim();
+            let word = if word.is_empty() { last.level.to_string() } else { word.to_string() };
+            let timeout = last.timeout.map(|timeout| format!(" --timeout {}", timeout.as_secs())).unwrap_or_default();
+            restart = format!(":hang start{} {}", timeout, word);
+            &restart
+        }
+        _ => content,
+    };
+
+    if let Some(rest) = content.strip_prefix(":hang start") {
+        if games.contains_key(&room) {
+            send_to_client(clients, sender, "hangman: game already active in this room");
+            return Vec::new();
+        }
+
+        // Without an explicit word the server picks one from the built-in
+        // list, so the secret never shows up in the chat. Nobody chose it,
+        // so the starter may play along.
+        // A difficulty token (easy/medium/hard) also asks for a random word;
+        // medium is used when nothing is given.
+        let mut secret = rest.trim();
+        // `--timeout <secs>` in front of the word limits the round's length.
+        let mut timeout = None;
+        if let Some(args) = secret.strip_prefix("--timeout") {
+            let (secs, word) = args.trim_start().split_once(' ').unwrap_or((args.trim(), ""));
+            match secs.parse::<u64>() {
+                Ok(secs) if secs > 0 => timeout = Some(Duration::from_secs(secs)),
+                _ => {
+                    send_to_client(clients, sender, "usage: :hang start --timeout <seconds> [word|easy|medium|hard]");
+                    return Vec::new();
+                }
+            }
+            secret = word.trim();
+        }
+        let level = secret.parse::<Difficulty>().unwrap_or(Difficulty::Medium);
+        let (suggester, chooser, secret) = if secret.is_empty() || secret.parse::<Difficulty>().is_ok() {
+            (String::from("the server"), None, random_word_by_difficulty(level))
+        } else {
+            (sender_name.clone(), Some(sender), secret)
+        };
+
+        let game = match create_hangman_match(&suggester, secret) {
+            Ok(game) => game.with_max_incorrect(level.lives()),
+            Err(e) => {
+                send_to_client(clients, sender, &e);
+                return Vec::new();
+            }
+        };
+        settings.insert(room.clone(), RoundSettings { timeout, level });
+        let mut round = HangmanRound::new(game, &room, chooser, clients);
+        round.deadline = timeout.map(|timeout| Instant::now() + timeout);
+        Metrics::count(&METRICS.hangman_games);
+        let first = round.turn_order.first().map(|&id| display_name(clients, id)).unwrap_or_default();
+
+        let limit = timeout.map(|timeout| format!(" - {}s on the clock", timeout.as_secs())).unwrap_or_default();
+        let announce = format!(
+            "Hangman {} by {} (word chosen by {}){}\n{}\nfirst turn: {}",
+            if restart.is_empty() { "started" } else { "restarted" },
+            sender_name,
+            suggester,
+            limit,
+            render_hangman_state(&round.game),
+            first
+        );
+        games.insert(room.clone(), round);
+
+        return send_to_all(clients, Some(&room), &announce);
+    }
+
+
+    // :hang end
+    if content.trim() == ":hang end" {
+        let Some(round) = games.remove(&room) else {
+            send_to_client(clients, sender, "hangman: no active game");
+            return Vec::new();
+        };
+
+        return send_to_all(clients, Some(&room), format!("Hangman game ended - the word was: {}", round.game.secret_word()));
+    }
+
+
+    // :hang save <name> - snapshot this room's running game to disk
+    if let Some(name) = content.strip_prefix(":hang save ") {
+        let name = name.trim();
+        let Some(round) = games.get(&room) else {
+            send_to_client(clients, sender, "hangman: no active game");
+            return Vec::new();
+        };
+        if !is_safe_name(name) {
+            send_to_client(clients, sender, "hangman: snapshot names may only use up to 32 letters, digits, - or _");
+            return Vec::new();
+        }
+
+        let dir = save_dir();
+        let result = fs::create_dir_all(&dir).and_then(|_| save_game(&round.game, &dir.join(format!("{}.json", name))));
+        return match result {
+            Ok(()) => send_to_all(clients, Some(&room), format!("{} saved the hangman game as '{}'", sender_name, name)),
+            Err(e) => {
+                send_to_client(clients, sender, format!("hangman: could not save '{}': {}", name, e));
+                Vec::new()
+            }
+        };
+    }
+
+    // :hang load <name> - resume a snapshot in this room
+    if let Some(name) = content.strip_prefix(":hang load ") {
+        let name = name.trim();
+        if games.contains_key(&room) {
+            send_to_client(clients, sender, "hangman: game already active in this room");
+            return Vec::new();
+        }
+        if !is_safe_name(name) {
+            send_to_client(clients, sender, "hangman: snapshot names may only use up to 32 letters, digits, - or _");
+            return Vec::new();
+        }
+
+        return match load_game(&save_dir().join(format!("{}.json", name))) {
+            Ok(game) => {
+                let round = HangmanRound::new(game, &room, None, clients);
+                let announce = format!(
+                    "{} loaded the hangman game '{}' (word chosen by {})\n{}",
+                    sender_name,
+                    name,
+                    round.game.suggester(),
+                    render_hangman_state(&round.game)
+                );
+                games.insert(room.clone(), round);
+                settings.insert(room.clone(), RoundSettings { timeout: None, level: Difficulty::Medium });
+                send_to_all(clients, Some(&room), &announce)
+            }
+            Err(e) => {
+                send_to_client(clients, sender, format!("hangman: could not load '{}': {}", name, e));
+                Vec::new()
+            }
+        };
+    }
+
+    // :hang status - re-send the current board to the requester only
+    if content.trim() == ":hang status" {
+        let Some(round) = games.get_mut(&room) else {
+            send_to_client(clients, sender, "hangman: no active game");
+            return Vec::new();
+        };
+
+        round.prune(clients);
+        let current = round.turn_order.get(round.turn).map(|&id| display_name(clients, id)).unwrap_or_default();
+        let status = format!(
+            "Hangman (word chosen by {})\n{}\ncurrent turn: {}",
+            round.game.suggester(),
+            render_hangman_state(&round.game),
+            current
+        );
+        send_to_client(clients, sender, &status);
+        return Vec::new();
+    }
+
+
+    // :hang suggest <letter> - a tip from anyone, whoever's turn it is. It
+    // is only chat: the game itself does not see it.
+    if let Some(rest) = content.strip_prefix(":hang suggest ") {
+        let Some(round) = games.get_mut(&room) else {
+            send_to_client(clients, sender, "hangman: no active game");
+            return Vec::new();
+        };
+        round.claim_suggester(clients, sender);
+        if round.suggester == Some(sender) {
+            send_to_client(clients, sender, "hangman: you chose this word, no hints from you");
+            return Vec::new();
+        }
+        let suggestion = rest.trim();
+        if suggestion.chars().count() != 1 || !suggestion.chars().all(char::is_alphabetic) {
+            send_to_client(clients, sender, "usage: :hang suggest <letter>");
+            return Vec::new();
+        }
+        return send_to_all(clients, Some(&room), format!("{} suggests '{}'", sender_name, suggestion));
+    }
+
+    // The remaining commands are moves in the running game. Each one is
+    // only accepted from the player whose turn it is, and passes the turn on.
+    let hint = content.trim() == ":hang hint";
+    let word = content.strip_prefix(":hang word ");
+    // :hang guess <letter>, or the short form :hang <letter>
+    let letter = content.strip_prefix(":hang guess ").or_else(|| {
+        content.strip_prefix(":hang ").filter(|rest| rest.trim().chars().count() == 1)
+    });
+
+    if !hint && word.is_none() && letter.is_none() {
+        send_to_client(clients, sender, "usage: :hang start [--timeout <secs>] [word|easy|medium|hard] | :hang restart [word] | :hang end | :hang guess <letter> | :hang <letter> | :hang word <word> | :hang hint | :hang suggest <letter> | :hang status | :hang save <name> | :hang load <name>");
+        return Vec::new();
+    }
+
+    let Some(round) = games.get_mut(&room) else {
+        send_to_client(clients, sender, "hangman: no active game");
+        return Vec::new();
+    };
+
+    // The player who chose the word knows it and may not guess.
+    round.claim_suggester(clients, sender);
+    if round.suggester == Some(sender) {
+        send_to_client(clients, sender, "hangman: you chose this word, let the others guess it");
+        return Vec::new();
+    }
+
+    if let Err(e) = round.check_turn(clients, sender) {
+        send_to_client(clients, sender, &e);
+        return Vec::new();
+    }
+    let game = &mut round.game;
+
+    let mut msg = if hint {
+        // :hang hint - reveal a letter at the cost of one incorrect guess
+        match reveal_hint(game, &mut rand::thread_rng()) {
+            Ok(letter) => {
+                let mut msg = format!(
+                    "{} took a hint: '{}' (counts as a wrong guess)\n{}",
+                    sender_name,
+                    letter,
+                    render_hangman_state(game)
+                );
+                if is_game_lost(game) {
+                    msg.push_str("\nThe hangman could not be saved - the game is over.");
+                } else if is_word_solved(game) {
+                    msg.push_str("\nThe hint revealed the last letter - the word is solved!");
+                }
+                msg
+            }
+            Err(e) => {
+                send_to_client(clients, sender, &e);
+                return Vec::new();
+            }
+        }
+    } else if let Some(rest) = word {
+        // :hang word <guess> - risk guessing the whole word
+        match guess_word(rest, game) {
+            Ok(true) => {
+                *scores.entry(sender_name.clone()).or_insert(0) += 1;
+                format!(
+                    "{} guessed the word '{}'\n{}\n{} solved the word chosen by {}!",
+                    sender_name,
+                    rest.trim(),
+                    render_hangman_state(game),
+                    sender_name,
+                    game.suggester()
+                )
+            }
+            Ok(false) => {
+                let mut msg = format!(
+                    "{} guessed the word '{}' (wrong)\n{}",
+                    sender_name,
+                    rest.trim(),
+                    render_hangman_state(game)
+                );
+                if !game.is_ongoing() {
+                    msg.push_str("\nThe hangman could not be saved - the game is over.");
+                }
+                msg
+            }
+            Err(e) => {
+                send_to_client(clients, sender, &e);
+                return Vec::new();
+            }
+        }
+    } else {
+        let rest = letter.unwrap_or_default();
+        match check_letter(rest.trim(), game) {
+            Ok(GuessOutcome::Hit) => {
+                format!(
+                    "{} guessed '{}'\n{}",
+                    sender_name,
+                    rest.trim(),
+                    render_hangman_state(game)
+                )
+            }
+            Ok(GuessOutcome::Miss) => {
+                format!(
+                    "{} guessed '{}' (wrong)\n{}",
+                    sender_name,
+                    rest.trim(),
+                    render_hangman_state(game)
+                )
+            }
+            Ok(GuessOutcome::Won) => {
+                *scores.entry(sender_name.clone()).or_insert(0) += 1;
+                format!(
+                    "{} guessed '{}'\n{}\n{} solved the word chosen by {}!",
+                    sender_name,
+                    rest.trim(),
+                    render_hangman_state(game),
+                    sender_name,
+                    game.suggester()
+                )
+            }
+            Ok(GuessOutcome::Lost) => {
+                format!(
+                    "{} guessed '{}' (wrong)\n{}\nThe hangman could not be saved - the game is over.",
+                    sender_name,
+                    rest.trim(),
+                    render_hangman_state(game)
+                )
+            }
+            Ok(GuessOutcome::AlreadyGuessed) => {
+                send_to_client(clients, sender, format!("'{}' was already guessed", rest.trim()));
+                return Vec::new();
+            }
+            Err(e) => {
+                send_to_client(clients, sender, &e);
+                return Vec::new();
+            }
+        }
+    };
+
+    if round.game.is_ongoing() {
+        let next = round.advance_turn(clients);
+        msg.push_str(&format!("\nnext turn: {}", next));
+    } else {
+        // a lost word is revealed; a solved one is already on the board
+        if is_game_lost(&round.game) {
+            msg.push_str(&format!("\nthe word was: {}", round.game.secret_word()));
+        }
+        games.remove(&room);
+    }
+    send_to_all(clients, Some(&room), &msg)
+}
+
+// handle_whisper delivers a private message to a single user. `rest` is
+// "<name> <message>"; the target is the longest display name that `rest`
+// starts with (followed by a space), so names are always matched exactly
+// against the display-name field.
+fn handle_whisper(clients: &mut [(Connection, String, String, String)], sender: u64, rest: &str) {
+    let sender_name = display_name(clients, sender);
+
+    let target = clients
+        .iter()
+        .filter_map(|(client, _, disp, _)| {
+            let body = rest.strip_prefix(disp.as_str())?.strip_prefix(' ')?;
+            Some((client.id, disp.clone(), body))
+        })
+        .max_by_key(|(_, disp, _)| disp.len());
+
+    let Some((target_id, target_name, body)) = target else {
+        let name = rest.split_whitespace().next().unwrap_or("");
+        send_to_client(clients, sender, format!("no such user: {}", name));
+        return;
+    };
+
+    if body.trim().is_empty() {
+        send_to_client(clients, sender, "usage: :w <name> <message>");
+        return;
+    }
+
+    // The confirmation doubles as a delivery receipt: it is only sent once
+    // the whisper is queued for the target.
+    if !send_to_client(clients, target_id, format!("{} whispers: {}", sender_name, body)) {
+        send_to_client(clients, sender, format!("whisper to {} failed: the message could not be delivered", target_name));
+        return;
+    }
+    send_to_client(clients, sender, format!("you whispered to {}: {}", target_name, body));
+
+    // Auto-reply on behalf of an away user.
+    let away = clients.iter().find(|(client, _, _, _)| client.id == target_id).and_then(|(client, _, _, _)| client.away.clone());
+    match away.as_deref() {
+        Some("") => { send_to_client(clients, sender, format!("{} is away", target_name)); }
+        Some(reason) => { send_to_client(clients, sender, format!("{} is away: {}", target_name, reason)); }
+        None => {}
+    }
+}
+
+// handle_file passes one chunk of a file on to its recipient (see
+// shared::transfer), with the sender's name in place of the recipient's.
+// The data is not looked at. Problems are reported with the first chunk
+// only, so a rejected file causes one reply rather than one per chunk.
+fn handle_file(clients: &mut [(Connection, String, String, String)], sender: u64, line: &str) {
+    let Some(chunk) = FileChunk::parse(line) else {
+        send_to_client(clients, sender, "file: malformed file chunk");
+        return;
+    };
+    let target = clients.iter().find(|(_, _, disp, _)| disp == chunk.peer).map(|(client, _, _, _)| client.id);
+    let problem = match target {
+        _ if chunk.size > MAX_FILE_SIZE => Some(format!("file: files are limited to {} bytes", MAX_FILE_SIZE)),
+        None => Some(format!("no such user: {}", chunk.peer)),
+        Some(id) if id == sender => Some(String::from("file: you cannot send files to yourself")),
+        Some(_) => None,
+    };
+    if let Some(problem) = problem {
+        if chunk.index == 0 {
+            send_to_client(clients, sender, problem);
+        }
+        return;
+    }
+
+    let sender_name = display_name(clients, sender);
+    let forwarded = FileChunk { peer: &sender_name, ..chunk }.to_text();
+    if let Some(target) = target {
+        send_to_client(clients, target, forwarded);
+    }
+}
+
+// handle_kick lets an operator disconnect another user. The target is told
+// why before the connection closes, and its room is told who kicked it.
+#[must_use]
+fn handle_kick(clients: &mut Vec<(Connection, String, String, String)>, name_rejected: &mut HashSet<u64>, sender: u64, name: &str) -> Dropped {
+    let is_operator = clients.iter().any(|(client, _, _, _)| client.id == sender && client.operator);
+    if !is_operator {
+        send_to_client(clients, sender, "kick: only operators may kick (see :op)");
+        return Vec::new();
+    }
+    let Some(target) = clients.iter().find(|(_, _, disp, _)| disp == name).map(|(client, _, _, _)| client.id) else {
+        send_to_client(clients, sender, format!("no such user: {}", name));
+        return Vec::new();
+    };
+
+    let sender_name = display_name(clients, sender);
+    // the room announcement covers operators in the same room
+    let same_room = client_room(clients, sender) == client_room(clients, target);
+    info!("{} kicked {}", sender_name, name);
+    send_to_client(clients, target, format!("you were kicked by {}", sender_name));
+    let dropped = remove_client(clients, name_rejected, target, &format!("was kicked by {}", sender_name));
+    if !same_room {
+        send_to_client(clients, sender, format!("you kicked {}", name));
+    }
+    dropped
+}
+
+// handle_ban lets an operator ban a display name or an address prefix. Every
+// connected client the new entry matches is disconnected right away.
+#[must_use]
+fn handle_ban(
+    clients: &mut Vec<(Connection, String, String, String)>,
+    name_rejected: &mut HashSet<u64>,
+    bans: &mut HashSet<String>,
+    sender: u64,
+    target: &str,
+) -> Dropped {
+    let is_operator = clients.iter().any(|(client, _, _, _)| client.id == sender && client.operator);
+    if !is_operator {
+        send_to_client(clients, sender, "ban: only operators may ban (see :op)");
+        return Vec::new();
+    }
+    if target.is_empty() {
+        send_to_client(clients, sender, "usage: :ban <name|address prefix>");
+        return Vec::new();
+    }
+
+    bans.insert(target.to_string());
+    save_bans(bans);
+    let sender_name = display_name(clients, sender);
+    info!("{} banned {}", sender_name, target);
+
+    let matched: Vec<u64> = clients
+        .iter()
+        .filter(|(client, addr, disp, _)| client.id != sender && ban_matches(target, disp, addr))
+        .map(|(client, _, _, _)| client.id)
+        .collect();
+    let mut dropped = Vec::new();
+    for id in matched {
+        send_to_client(clients, id, format!("you were banned by {}", sender_name));
+        dropped.extend(remove_client(clients, name_rejected, id, &format!("was banned by {}", sender_name)));
+    }
+    send_to_client(clients, sender, format!("banned {}", target));
+    dropped
+}
+
+fn handle_unban(clients: &mut [(Connection, String, String, String)], bans: &mut HashSet<String>, sender: u64, target: &str) {
+    let is_operator = clients.iter().any(|(client, _, _, _)| client.id == sender && client.operator);
+    if !is_operator {
+        send_to_client(clients, sender, "unban: only operators may unban (see :op)");
+        return;
+    }
+    if !bans.remove(target) {
+        send_to_client(clients, sender, format!("{} is not banned", target));
+        return;
+    }
+    save_bans(bans);
+    send_to_client(clients, sender, format!("unbanned {}", target));
+}
+
+// handle_who replies privately with the details of one user.
+fn handle_who(clients: &mut [(Connection, String, String, String)], sender: u64, name: &str) {
+    let Some((client, _, disp, room)) = clients.iter().find(|(_, _, disp, _)| disp == name) else {
+        send_to_client(clients, sender, format!("no such user: {}", name));
+        return;
+    };
+
+    let status = match client.away.as_deref() {
+        None => String::from("here"),
+        Some("") => String::from("away"),
+        Some(reason) => format!("away: {}", reason),
+    };
+    let info = format!(
+        "{} (id {}) in {}, {}, connected for {}",
+        disp,
+        client.id,
+        room,
+        status,
+        format_uptime(client.connected_at)
+    );
+    send_to_client(clients, sender, &info);
+}
+
+// handle_poll starts a poll in the sender's room, closes it (`:poll close`,
+// creator or operators only) or, without arguments, shows the running one.
+#[must_use]
+fn handle_poll(clients: &mut Vec<(Connection, String, String, String)>, polls: &mut HashMap<String, Poll>, sender: u64, args: &str) -> Dropped {
+    let room = client_room(clients, sender);
+    match args {
+        "" => {
+            let reply = polls.get(&room).map(Poll::describe).unwrap_or_else(|| String::from("no poll running in this room"));
+            send_to_client(clients, sender, reply);
+            Vec::new()
+        }
+        "close" => {
+            let Some(poll) = polls.get(&room) else {
+                send_to_client(clients, sender, "no poll running in this room");
+                return Vec::new();
+            };
+            let is_operator = clients.iter().any(|(client, _, _, _)| client.id == sender && client.operator);
+            if poll.creator != sender && !is_operator {
+                send_to_client(clients, sender, "poll: only the creator can close the poll");
+                return Vec::new();
+            }
+            match polls.remove(&room) {
+                Some(poll) => send_to_all(clients, Some(&room), poll.results()),
+                None => Vec::new(),
+            }
+        }
+        _ => {
+            if polls.contains_key(&room) {
+                send_to_client(clients, sender, "a poll is already running in this room (see :poll)");
+                return Vec::new();
+            }
+            match Poll::parse(sender, display_name(clients, sender), args) {
+                Ok(poll) => {
+                    let dropped = send_to_all(clients, Some(&room), poll.describe());
+                    polls.insert(room, poll);
+                    dropped
+                }
+                Err(e) => {
+                    send_to_client(clients, sender, e);
+                    Vec::new()
+                }
+            }
+        }
+    }
+}
+
+// handle_vote records the sender's vote in their room's poll.
+fn handle_vote(clients: &mut [(Connection, String, String, String)], polls: &mut HashMap<String, Poll>, sender: u64, arg: &str) {
+    let Some(poll) = polls.get_mut(&client_room(clients, sender)) else {
+        send_to_client(clients, sender, "no poll running in this room");
+        return;
+    };
+    match arg.parse::<usize>() {
+        Ok(n) if (1..=poll.options.len()).contains(&n) => {
+            poll.votes.insert(sender, n - 1);
+            send_to_client(clients, sender, format!("vote recorded: {}", poll.options[n - 1]));
+        }
+        _ => { send_to_client(clients, sender, format!("usage: :vote <1-{}>", poll.options.len())); }
+    }
+}
+
+// handle_join moves the sender into another room. The old room is told the
+// user left and the new room is told they joined; clients in other rooms see
+// nothing. Returns whether the sender joined, and the clients the
+// announcements had to drop.
+#[must_use]
+fn handle_join(clients: &mut Vec<(Connection, String, String, String)>, sender: u64, room: &str) -> (bool, Dropped) {
+    // Room names end up in autosave file names, hence the restricted charset.
+    if !is_safe_name(room) {
+        send_to_client(clients, sender, "usage: :join <room> (up to 32 letters, digits, - or _)");
+        return (false, Vec::new());
+    }
+
+    let previous_room = client_room(clients, sender);
+    if previous_room == room {
+        send_to_client(clients, sender, format!("you are already in {}", room));
+        return (false, Vec::new());
+    }
+
+    let mut sender_name = String::new();
+    for (client, _addr, disp, client_room) in clients.iter_mut() {
+        if client.id == sender {
+            *client_room = room.to_string();
+            sender_name = disp.clone();
+            break;
+        }
+    }
+
+    let mut dropped = send_to_others(clients, sender, Some(&previous_room), format!("{} left {}", sender_name, previous_room));
+    dropped.extend(send_to_others(clients, sender, Some(room), format!("{} joined {}", sender_name, room)));
+    (send_to_client(clients, sender, format!("you joined {}", room)), dropped)
+}
+
+// Whether `name` is used by a client other than `sender`. Shared by :name
+// and :name? so both agree on what counts as taken.
+fn is_name_taken(clients: &[(Connection, String, String, String)], sender: u64, name: &str) -> bool {
+    clients.iter().any(|(client, _, disp, _)| client.id != sender && disp == name)
+}
+
+// handle_name_check answers :name? privately with whether a name could be
+// picked right now, without changing anything.
+fn handle_name_check(clients: &mut [(Connection, String, String, String)], bans: &HashSet<String>, filter: &WordFilter, sender: u64, requested: &str) {
+    let reply = match validate_name(requested) {
+        Err(e) => e,
+        Ok(name) if bans.contains(name) => String::from("invalid name: this name is banned"),
+        Ok(name) if filter.blocks(name) => String::from("invalid name: this name is not allowed"),
+        Ok(name) if is_name_taken(clients, sender, name) => format!("name_taken: {}", name),
+        Ok(name) => format!("name_available: {}", name),
+    };
+    send_to_client(clients, sender, reply);
+}
+
+// `:name` without a name: the client goes back to its guest name, and its
+// room sees the change like any other rename.
+#[must_use]
+fn reset_client_name(clients: &mut Vec<(Connection, String, String, String)>, sender: u64) -> Dropped {
+    let guest = guest_name(sender);
+    let Some(previous) = clients
+        .iter_mut()
+        .find(|(client, _, _, _)| client.id == sender)
+        .map(|(_, _, disp, _)| std::mem::replace(disp, guest.clone()))
+    else {
+        return Vec::new();
+    };
+    if previous == guest {
+        send_to_client(clients, sender, format!("you are already {}", guest));
+        return Vec::new();
+    }
+    info!("Client {} dropped the name '{}'", sender, previous);
+    if !send_to_client(clients, sender, format!("you are now {}", guest)) {
+        return Vec::new();
+    }
+    let room = client_room(clients, sender);
+    send_to_others(clients, sender, Some(&room), format!("{} changed their name to {}", previous, guest))
+}
+
+// try_client_name_assignment centralizes the name-change flow. It follows a
+// small three-phase approach:
+//  1) read-only checks for name collisions and the previous name
+//  2) mutate the client's display_name if the name is available
+//  3) send appropriate messages (reject, confirmation or announce) after
+//     the mutation so there are no active borrows when writing to sockets
+// This ordering prevents borrow/ownership conflicts when updating the
+// `clients` Vec while also writing to streams owned by the same Vec. A send
+// that fails removes the client, so nothing is announced for it after that.
+// Returns true if the client joined, i.e. picked its first name, and the
+// clients the announcement had to drop.
+#[must_use]
+fn try_client_name_assignment(
+    clients: &mut Vec<(Connection, String, String, String)>, 
+    name_rejected: &mut HashSet<u64>, 
+    sender: u64, 
+    requested: &str,
+) -> (bool, Dropped) {
+    let name = match validate_name(requested) {
+        Ok(name) => name.to_string(),
+        Err(e) => {
+            send_to_client(clients, sender, format!("{}\nchange the name with :name <new_name>", e));
+            return (false, Vec::new());
+        }
+    };
+    info!("Registering name '{}' for client {}", name, sender);
+
+    // ---- PHASE 1: READ ONLY ----
+    let name_taken = is_name_taken(clients, sender, &name);
+
+    let previous_name = clients
+        .iter()
+        .find(|(client, _, _, _)| client.id == sender)
+        .map(|(_, _, disp, _)| disp.clone());
+
+    // Asking for the name you already have changes nothing.
+    if previous_name.as_deref() == Some(name.as_str()) {
+        send_to_client(clients, sender, format!("you are already {}", name));
+        return (false, Vec::new());
+    }
+
+    // ---- PHASE 2: MUTATE STATE ----
+    if !name_taken {
+        for (client, _addr, disp, _room) in clients.iter_mut() {
+            if client.id == sender {
+                *disp = name.clone();
+                break;
+            }
+        }
+    }
+
+    // ---- PHASE 3: SEND MESSAGES (no borrows alive) ----
+    if name_taken {
+        let reject = format!(
+            "name_taken: {}\nchange the name with :name <new_name>",
+            name
+        );
+
+        if send_to_client(clients, sender, &reject) {
+            name_rejected.insert(sender);
+        }
+        return (false, Vec::new());
+    }
+
+    if name_rejected.remove(&sender) {
+        let confirm = format!("{} is unique and was appended to your client!", name);
+        if !send_to_client(clients, sender, &confirm) {
+            return (false, Vec::new());
+        }
+    }
+
+    // Leaving the guest name counts as joining the chat.
+    let (announce, joined) = match previous_name {
+        Some(prev) if prev != guest_name(sender) =>
+            (format!("{} changed their name to {}", prev, name), false),
+        _ => (format!("{} joined", name), true),
+    };
+
+    let room = client_room(clients, sender);
+    let dropped = send_to_others(clients, sender, Some(&room), &announce);
+
+    // Someone who just joined gets the roster without having to ask.
+    if joined {
+        let roster = user_list(clients);
+        send_to_client(clients, sender, roster);
+    }
+    (joined, dropped)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chatproject::shared::commands::COMMANDS;
+    use chatproject::shared::transfer::{chunk_file, Assembler};
+    use chatproject::shared::transport::memory_pair;
+
+    // A connected client called `name` in `room`; what is sent to it can be
+    // read from the receiver, and dropping the receiver makes sends fail.
+    fn client(id: u64, name: &str, room: &str) -> ((Connection, String, String, String), Receiver<ServerMessage>) {
+        let (outbox, inbox) = mpsc::sync_channel(16);
+        let slot = ClientSlot::take(None).expect("no client limit");
+        let rate = RateLimit { per_sec: 100.0, burst: 100.0 };
+        let handle = Connection::new(id, slot, outbox, rate, Arc::new(AtomicBool::new(false)), Arc::new(AtomicU8::new(MODE_TEXT)));
+        ((handle, format!("127.0.0.1:{}", 40000 + id), name.to_string(), room.to_string()), inbox)
+    }
+
+    fn server() -> Server {
+        Server::new(HashSet::new(), WordFilter::default(), Aliases::default(), History::new(10), None, false, None)
+    }
+
+    // Every line queued for a client so far.
+    fn received(inbox: &Receiver<ServerMessage>) -> Vec<String> {
+        inbox.try_iter().map(|msg| msg.to_text()).collect()
+    }
+
+    // A word filter for `words`, as if read from SERVER_FILTER_FILE.
+    fn filter(words: &[&str]) -> WordFilter {
+        WordFilter { words: words.iter().map(|w| w.to_string()).collect() }
+    }
+
+    // An alias table, as if read from the config file.
+    fn aliases(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, target)| (name.to_string(), target.to_string())).collect()
+    }
+
+    // Keeps every log record, so tests can check what is logged at which
+    // level. Tests run in parallel, so they look for their own text.
+    struct TestLogger;
+
+    static LOGGED: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGGED.lock().unwrap_or_else(|e| e.into_inner()).push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INSTALLED: OnceLock<()> = OnceLock::new();
+        INSTALLED.get_or_init(|| {
+            let _ = log::set_logger(&TestLogger);
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    // The levels of the records containing `text`.
+    fn levels_of(text: &str) -> Vec<log::Level> {
+        LOGGED.lock().unwrap_or_else(|e| e.into_inner()).iter().filter(|(_, line)| line.contains(text)).map(|(level, _)| *level).collect()
+    }
+
+    #[test]
+    fn send_to_all_returns_the_clients_it_dropped() {
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, _carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        let (dave, dave_inbox) = client(4, "dave", "games");
+        drop(bob_inbox);
+        drop(dave_inbox);
+        let mut clients = vec![alice, bob, carol, dave];
+
+        let dropped = send_to_all(&mut clients, Some(DEFAULT_ROOM), "hello");
+
+        // dave is in another room, so that queue was never tried
+        assert_eq!(dropped, vec![(DEFAULT_ROOM.to_string(), String::from("bob"))]);
+        let names: Vec<&str> = clients.iter().map(|(_, _, name, _)| name.as_str()).collect();
+        assert_eq!(names, ["alice", "carol", "dave"]);
+    }
+
+    #[test]
+    fn announcing_a_drop_can_drop_and_announce_more_clients() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        drop(carol_inbox);
+        server.clients = vec![alice, carol];
+
+        server.announce_dropped(vec![(DEFAULT_ROOM.to_string(), String::from("bob"))]);
+
+        assert_eq!(received(&alice_inbox), ["bob disconnected", "carol disconnected"]);
+        assert_eq!(server.clients.len(), 1);
+    }
+
+    #[test]
+    fn joining_a_room_announces_clients_dropped_on_the_way() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        drop(bob_inbox);
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":join games");
+
+        assert_eq!(received(&carol_inbox), ["alice left lobby", "bob disconnected"]);
+    }
+
+    #[test]
+    fn asking_for_your_own_name_changes_nothing() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, carol];
+
+        server.handle_message(1, ":name alice");
+
+        assert_eq!(received(&alice_inbox), ["you are already alice"]);
+        assert!(received(&carol_inbox).is_empty());
+    }
+
+    #[test]
+    fn leaving_the_guest_name_joins_and_sends_the_roster() {
+        let mut server = server();
+        let (guest, guest_inbox) = client(1, &guest_name(1), DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![guest, carol];
+
+        server.handle_message(1, ":name alice");
+
+        assert_eq!(received(&carol_inbox), ["alice joined"]);
+        assert_eq!(received(&guest_inbox), [user_list(&server.clients).to_text()]);
+    }
+
+    #[test]
+    fn the_difficulty_sets_the_number_of_lives() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, _bob_inbox) = client(2, "bob", "games");
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang start easy");
+        server.handle_message(2, ":hang start hard");
+
+        assert_eq!(server.hangman_games[DEFAULT_ROOM].game.max_incorrect(), 12);
+        assert_eq!(server.hangman_games["games"].game.max_incorrect(), 6);
+    }
+
+    #[test]
+    fn hangman_players_take_turns() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+        server.handle_message(1, ":hang start secret");
+
+        server.handle_message(2, ":hang e");
+        assert!(received(&carol_inbox).last().unwrap().ends_with("next turn: carol"));
+        received(&bob_inbox);
+        server.handle_message(2, ":hang s");
+        assert_eq!(received(&bob_inbox), ["not your turn - it is carol's turn"]);
+
+        server.handle_message(3, ":hang x");
+        assert!(received(&bob_inbox).last().unwrap().ends_with("next turn: bob"));
+        assert_eq!(server.hangman_games[DEFAULT_ROOM].game.guessed_letters(), ['e', 'x']);
+    }
+
+    #[test]
+    fn the_suggester_cannot_guess_after_a_rename() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, _bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+        server.handle_message(1, ":hang start secret");
+        server.handle_message(1, ":name mallory");
+        received(&alice_inbox);
+
+        server.handle_message(1, ":hang word secret");
+
+        assert_eq!(received(&alice_inbox), ["hangman: you chose this word, let the others guess it"]);
+        assert!(server.hangman_games[DEFAULT_ROOM].game.is_ongoing());
+    }
+
+    #[test]
+    fn a_new_client_with_the_suggesters_old_name_can_guess() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, _bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+        server.handle_message(1, ":hang start secret");
+        server.remove(1, "left");
+        let (new_alice, new_alice_inbox) = client(3, "alice", DEFAULT_ROOM);
+        server.clients.push(new_alice);
+        // the first try only queues the new client behind bob
+        server.handle_message(3, ":hang s");
+        server.handle_message(2, ":hang x");
+        received(&new_alice_inbox);
+
+        server.handle_message(3, ":hang s");
+
+        let lines = received(&new_alice_inbox);
+        assert!(lines.iter().any(|line| line.starts_with("alice guessed 's'")), "{:?}", lines);
+    }
+
+    #[test]
+    fn a_file_sent_through_the_server_arrives_whole() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", "games");
+        server.clients = vec![alice, bob];
+        let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+
+        for line in chunk_file("bob", "notes.bin", &data).unwrap() {
+            server.handle_message(1, &line);
+        }
+
+        let mut assembler = Assembler::new();
+        let mut file = None;
+        for line in received(&bob_inbox) {
+            let chunk = FileChunk::parse(&line).unwrap();
+            assert_eq!(chunk.peer, "alice");
+            file = assembler.add(&chunk).unwrap();
+        }
+        assert_eq!(file, Some(data));
+    }
+
+    #[test]
+    fn a_file_for_an_absent_user_is_refused_once() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        server.clients = vec![alice];
+
+        for line in chunk_file("nobody", "notes.bin", &[1; 5000]).unwrap() {
+            server.handle_message(1, &line);
+        }
+
+        assert_eq!(received(&alice_inbox), ["no such user: nobody"]);
+    }
+
+    #[test]
+    fn chat_is_logged_at_debug_and_disconnects_at_info() {
+        capture_logs();
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "log-alice", DEFAULT_ROOM);
+        server.clients = vec![alice];
+
+        server.handle_message(1, "a line only this test sends");
+        server.remove(1, "left");
+
+        assert_eq!(levels_of("a line only this test sends"), [log::Level::Debug]);
+        assert_eq!(levels_of("log-alice disconnected after"), [log::Level::Info]);
+    }
+
+    #[test]
+    fn hangman_status_is_only_sent_to_the_asker() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+        server.handle_message(1, ":hang start secret");
+        server.handle_message(2, ":hang e");
+        received(&alice_inbox);
+        received(&bob_inbox);
+
+        server.handle_message(2, ":hang status");
+
+        let status = received(&bob_inbox);
+        assert_eq!(status.len(), 1);
+        assert!(status[0].contains("Word: _e__e_"), "{}", status[0]);
+        assert!(received(&alice_inbox).is_empty());
+    }
+
+    #[test]
+    fn each_room_plays_its_own_hangman_game() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", "games");
+        let (dave, dave_inbox) = client(4, "dave", "games");
+        server.clients = vec![alice, bob, carol, dave];
+        server.handle_message(1, ":hang start apple");
+        server.handle_message(3, ":hang start melon");
+        received(&bob_inbox);
+        received(&dave_inbox);
+
+        server.handle_message(2, ":hang p");
+        server.handle_message(4, ":hang word melon");
+
+        assert!(received(&bob_inbox)[0].starts_with("bob guessed 'p'"));
+        assert!(received(&carol_inbox).iter().any(|line| line.contains("dave solved the word chosen by carol")));
+        assert_eq!(server.hangman_games[DEFAULT_ROOM].game.masked_word(), "_pp__");
+        assert!(!server.hangman_games.contains_key("games"));
+    }
+
+    #[test]
+    fn connections_from_the_same_address_are_separate_clients() {
+        let mut server = server();
+        let (mut alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (mut bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        alice.1 = String::from("127.0.0.1:40000");
+        bob.1 = alice.1.clone();
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, "hi");
+        server.remove(1, "left");
+        server.handle_message(2, ":list");
+
+        assert!(received(&alice_inbox).is_empty());
+        let bob_lines = received(&bob_inbox);
+        assert!(bob_lines[0].ends_with("alice: hi"));
+        assert_eq!(bob_lines[1..], [String::from("alice left"), user_list(&server.clients).to_text()]);
+        assert_eq!(server.clients.len(), 1);
+    }
+
+    #[test]
+    fn messages_over_the_rate_limit_are_not_broadcast() {
+        let mut server = server();
+        let (mut alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        alice.0.limiter = RateLimiter::new(RateLimit { per_sec: 0.01, burst: 3.0 });
+        server.clients = vec![alice, bob];
+
+        for i in 0..10 {
+            server.handle_message(1, &format!("message {}", i));
+        }
+
+        let heard = received(&bob_inbox);
+        assert_eq!(heard.len(), 3);
+        assert!(heard[2].ends_with("alice: message 2"));
+        // told once, not once per dropped message
+        assert_eq!(received(&alice_inbox), ["slow down: message dropped (rate limit)"]);
+    }
+
+    #[test]
+    fn a_taken_name_is_refused() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (guest, guest_inbox) = client(2, &guest_name(2), DEFAULT_ROOM);
+        server.clients = vec![alice, guest];
+
+        server.handle_message(2, ":name alice");
+
+        assert_eq!(received(&guest_inbox), ["name_taken: alice\nchange the name with :name <new_name>"]);
+        assert_eq!(display_name(&server.clients, 2), guest_name(2));
+    }
+
+    #[test]
+    fn away_users_are_marked_in_the_list() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, _bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":away lunch");
+        server.handle_message(2, ":away");
+        server.handle_message(3, ":list");
+        server.handle_message(1, ":back");
+        server.handle_message(3, ":list");
+
+        assert_eq!(received(&carol_inbox), [
+            "connected:\nalice (away: lunch)\nbob (away)\ncarol\n",
+            "connected:\nalice\nbob (away)\ncarol\n",
+        ]);
+    }
+
+    #[test]
+    fn me_sends_an_emote_to_the_room() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", "games");
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":me waves hello");
+
+        assert_eq!(received(&alice_inbox), ["* alice waves hello"]);
+        assert_eq!(received(&bob_inbox), ["* alice waves hello"]);
+        assert!(received(&carol_inbox).is_empty());
+    }
+
+    #[test]
+    fn who_describes_a_known_user() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, _bob_inbox) = client(2, "bob", "games");
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":who bob");
+        server.handle_message(1, ":who nobody");
+
+        let lines = received(&alice_inbox);
+        assert!(lines[0].starts_with("bob (id 2) in games, here, connected for "), "{}", lines[0]);
+        assert_eq!(lines[1], "no such user: nobody");
+    }
+
+    #[test]
+    fn uptimes_leave_out_leading_zero_units() {
+        let secs = Duration::from_secs;
+        assert_eq!(format_duration(secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_millis(59_999)), "59s");
+        assert_eq!(format_duration(secs(60)), "1m 0s");
+        assert_eq!(format_duration(secs(3599)), "59m 59s");
+        assert_eq!(format_duration(secs(3600)), "1h 0m 0s");
+        assert_eq!(format_duration(secs(90_061)), "25h 1m 1s");
+    }
+
+    #[test]
+    fn only_operators_can_kick() {
+        let mut server = Server::new(HashSet::new(), WordFilter::default(), Aliases::default(), History::new(10), None, false, Some(String::from("hunter2")));
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(2, ":kick carol");
+        server.handle_message(1, ":op wrong");
+        server.handle_message(1, ":kick carol");
+        assert_eq!(received(&bob_inbox), ["kick: only operators may kick (see :op)"]);
+        assert_eq!(received(&alice_inbox), ["op: wrong password", "kick: only operators may kick (see :op)"]);
+        assert_eq!(server.clients.len(), 3);
+
+        server.handle_message(1, ":op hunter2");
+        server.handle_message(1, ":kick carol");
+
+        assert_eq!(received(&carol_inbox), ["you were kicked by alice"]);
+        assert_eq!(received(&bob_inbox), ["carol was kicked by alice"]);
+        assert_eq!(received(&alice_inbox), ["you are now an operator", "carol was kicked by alice"]);
+        assert!(!server.clients.iter().any(|(_, _, name, _)| name == "carol"));
+    }
+
+    #[test]
+    fn banning_a_connected_user_removes_them() {
+        let mut server = server();
+        let (mut alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        alice.0.operator = true;
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":ban bob");
+
+        assert_eq!(received(&bob_inbox), ["you were banned by alice"]);
+        assert_eq!(received(&alice_inbox), ["bob was banned by alice", "banned bob"]);
+        assert_eq!(server.clients.len(), 1);
+        assert!(server.bans.contains("bob"));
+    }
+
+    #[test]
+    fn a_banned_address_is_turned_away_on_connect() {
+        let mut server = Server::new(HashSet::from([String::from("10.0.0.")]), WordFilter::default(), Aliases::default(), History::new(10), None, false, None);
+        let ((banned, ..), banned_inbox) = client(1, "", DEFAULT_ROOM);
+        let ((allowed, ..), _allowed_inbox) = client(2, "", DEFAULT_ROOM);
+
+        server.connect(banned, "10.0.0.7:5000".parse().unwrap());
+        server.connect(allowed, "10.0.1.7:5000".parse().unwrap());
+
+        assert_eq!(received(&banned_inbox), ["you are banned from this server"]);
+        let names: Vec<&str> = server.clients.iter().map(|(_, _, name, _)| name.as_str()).collect();
+        assert_eq!(names, ["guest-2"]);
+    }
+
+    #[test]
+    fn a_late_joiner_catches_up_on_the_room() {
+        let mut server = server();
+        server.history = History::new(2);
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, _bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (guest, guest_inbox) = client(3, &guest_name(3), DEFAULT_ROOM);
+        server.clients = vec![alice, bob, guest];
+        server.handle_message(1, "first");
+        server.handle_message(2, "second");
+        server.handle_message(1, "third");
+        received(&guest_inbox);
+
+        server.handle_message(3, ":name dave");
+
+        let lines = received(&guest_inbox);
+        assert_eq!(lines.last().unwrap(), "--- history ---\nbob: second\nalice: third");
+    }
+
+    #[test]
+    fn a_message_read_from_a_memory_transport_is_broadcast() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+        let (mut alice_end, server_end) = memory_pair();
+        let (reader, _writer) = (Box::new(server_end) as Box<dyn Transport>).split().unwrap();
+        let (tx, events) = mpsc::channel();
+        let addr = "127.0.0.1:40001".parse().unwrap();
+        let reader = thread::spawn(move || read_client(reader, 1, addr, tx, Arc::new(AtomicBool::new(false)), MAX_MSG_LEN, Arc::new(AtomicU8::new(MODE_TEXT))));
+
+        write_frame(&mut alice_end, "hello everyone").unwrap();
+        drop(alice_end);
+        for event in events {
+            match event {
+                Event::Message(id, msg) => server.handle_message(id, &msg),
+                Event::Disconnected(id) => server.remove(id, "left"),
+                _ => panic!("unexpected event"),
+            }
+        }
+        reader.join().unwrap();
+
+        assert_eq!(received(&bob_inbox), ["alice: hello everyone", "alice left"]);
+        assert_eq!(received(&carol_inbox), ["alice: hello everyone", "alice left"]);
+    }
+
+    #[test]
+    fn a_client_that_stops_answering_pings_is_reaped() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        for _ in 0..MAX_MISSED_PINGS {
+            server.heartbeat();
+            server.handle_message(2, ":pong");
+        }
+        assert_eq!(server.clients.len(), 2);
+        server.heartbeat();
+
+        assert_eq!(received(&alice_inbox), vec![":ping"; MAX_MISSED_PINGS as usize]);
+        assert_eq!(received(&bob_inbox).last().unwrap(), "alice timed out");
+        let names: Vec<&str> = server.clients.iter().map(|(_, _, name, _)| name.as_str()).collect();
+        assert_eq!(names, ["bob"]);
+    }
+
+    #[test]
+    fn idle_clients_are_dropped_and_active_ones_stay() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        thread::sleep(Duration::from_millis(60));
+        server.handle_message(2, "still here");
+        server.drop_idle(Duration::from_millis(50));
+
+        assert_eq!(received(&alice_inbox), ["bob: still here", "disconnected for inactivity"]);
+        assert_eq!(received(&bob_inbox), ["alice was disconnected for inactivity"]);
+        assert_eq!(server.clients.len(), 1);
+    }
+
+    #[test]
+    fn bind_all_skips_addresses_it_cannot_bind() {
+        let listeners = bind_all("127.0.0.1:0, 127.0.0.1:0,not-an-address,").unwrap();
+
+        assert_eq!(listeners.len(), 2);
+        assert_ne!(listeners[0].local_addr().unwrap(), listeners[1].local_addr().unwrap());
+        assert!(bind_all("not-an-address").is_err());
+        assert!(bind_all(" ,").is_err());
+    }
+
+    #[test]
+    fn parse_dice_reads_count_and_sides() {
+        assert_eq!(parse_dice("2d6"), Ok((2, 6)));
+        assert_eq!(parse_dice("D20"), Ok((1, 20)));
+        assert_eq!(parse_dice("100d1000"), Ok((100, 1000)));
+    }
+
+    #[test]
+    fn parse_dice_refuses_malformed_specs_and_out_of_bounds_dice() {
+        for spec in ["", "2", "2x6", "d", "2d", "ad6", "2d6d", "-1d6", "2 d6"] {
+            assert!(parse_dice(spec).unwrap_err().starts_with("usage: "), "{}", spec);
+        }
+        for spec in ["0d6", "101d6", "1d1", "1d1001"] {
+            assert!(parse_dice(spec).unwrap_err().starts_with("invalid roll: "), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn a_roll_is_shown_to_the_room_and_a_bad_one_to_the_roller() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":roll 3d6");
+        server.handle_message(1, ":roll 3d1");
+
+        let lines = received(&bob_inbox);
+        let (dice, total) = lines[0].strip_prefix("alice rolled 3d6: ").unwrap().split_once(" = ").unwrap();
+        let dice: Vec<u32> = dice.split('+').map(|d| d.parse().unwrap()).collect();
+        assert_eq!(dice.len(), 3);
+        assert!(dice.iter().all(|d| (1..=6).contains(d)));
+        assert_eq!(total.parse::<u32>().unwrap(), dice.iter().sum::<u32>());
+        assert_eq!(lines.len(), 1);
+        assert_eq!(received(&alice_inbox)[1], "invalid roll: dice have between 2 and 1000 sides");
+    }
+
+    #[test]
+    fn the_eight_ball_answers_from_its_list() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":8ball");
+        // within the burst of the rate limiter
+        for _ in 0..9 {
+            server.handle_message(1, ":8ball will it rain?");
+        }
+
+        assert_eq!(received(&alice_inbox)[0], "usage: :8ball <question>");
+        let answers = received(&bob_inbox);
+        assert_eq!(answers.len(), 9);
+        for answer in answers {
+            let answer = answer.strip_prefix("alice asked the 8-ball: ").unwrap();
+            assert!(EIGHT_BALL_ANSWERS.contains(&answer), "{}", answer);
+        }
+    }
+
+    #[test]
+    fn a_poll_counts_the_last_vote_of_each_user() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":poll \"Lunch?\" pizza sushi");
+        server.handle_message(1, ":vote 1");
+        server.handle_message(2, ":vote 1");
+        server.handle_message(2, ":vote 2");
+        server.handle_message(3, ":vote 2");
+        server.handle_message(3, ":vote 3");
+        server.handle_message(2, ":poll close");
+        server.handle_message(1, ":poll close");
+
+        let results = "poll closed: Lunch?\n  1) pizza - 1 vote\n  2) sushi - 2 votes";
+        assert_eq!(received(&alice_inbox), [
+            "poll by alice: Lunch?\n  1) pizza\n  2) sushi\nvote with :vote <1-2>",
+            "vote recorded: pizza",
+            results,
+        ]);
+        assert_eq!(received(&bob_inbox)[1..], ["vote recorded: pizza", "vote recorded: sushi", "poll: only the creator can close the poll", results]);
+        assert_eq!(received(&carol_inbox)[1..], ["vote recorded: sushi", "usage: :vote <1-2>", results]);
+        assert!(server.polls.is_empty());
+    }
+
+    #[test]
+    fn checking_a_name_reports_it_without_taking_it() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":name? bob");
+        server.handle_message(1, ":name? carol");
+        server.handle_message(1, ":name? alice");
+
+        assert_eq!(received(&alice_inbox), ["name_taken: bob", "name_available: carol", "name_available: alice"]);
+        assert!(received(&bob_inbox).is_empty());
+        assert_eq!(display_name(&server.clients, 1), "alice");
+    }
+
+    #[test]
+    fn a_multibyte_name_can_be_registered() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "guest-1", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":name 你好");
+        server.handle_message(1, "hi");
+
+        assert_eq!(display_name(&server.clients, 1), "你好");
+        assert_eq!(received(&alice_inbox), ["connected:\n你好\nbob\n"]);
+        assert_eq!(received(&bob_inbox).last().unwrap(), "你好: hi");
+    }
+
+    #[test]
+    fn clear_is_only_sent_to_the_requester() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (mut bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        bob.0.hello = Some(Hello { capabilities: Vec::new(), ..Hello::ours() });
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":clear");
+        server.handle_message(2, ":clear");
+
+        assert_eq!(alice_inbox.try_iter().collect::<Vec<_>>(), [ServerMessage::Clear]);
+        assert!(received(&bob_inbox).is_empty());
+        assert!(received(&carol_inbox).is_empty());
+    }
+
+    #[test]
+    fn chat_goes_to_the_others_in_the_room() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", "games");
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, "hello");
+
+        assert!(received(&alice_inbox).is_empty());
+        assert_eq!(received(&bob_inbox), ["alice: hello"]);
+        assert!(received(&carol_inbox).is_empty());
+    }
+
+    #[test]
+    fn a_flip_is_shown_to_the_whole_room() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", "games");
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":flip");
+
+        let flip = received(&alice_inbox);
+        assert!(flip == ["alice: flipped: heads"] || flip == ["alice: flipped: tails"], "{:?}", flip);
+        assert_eq!(received(&bob_inbox), flip);
+        assert!(received(&carol_inbox).is_empty());
+    }
+
+    #[test]
+    fn the_list_is_only_sent_to_the_asker() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":list");
+
+        assert_eq!(received(&alice_inbox), ["connected:\nalice\nbob\n"]);
+        assert!(received(&bob_inbox).is_empty());
+    }
+
+    #[test]
+    fn a_hangman_round_can_be_won() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang start ox");
+        server.handle_message(2, ":hang o");
+        server.handle_message(2, ":hang x");
+
+        assert!(server.hangman_games.is_empty());
+        let lines = received(&bob_inbox);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Hangman started by alice (word chosen by alice)") && lines[0].ends_with("first turn: bob"), "{}", lines[0]);
+        assert!(lines[1].starts_with("bob guessed 'o'") && lines[1].contains("Word: o_"), "{}", lines[1]);
+        assert!(lines[2].contains("Word: ox") && lines[2].ends_with("bob solved the word chosen by alice!"), "{}", lines[2]);
+        assert_eq!(received(&alice_inbox), lines);
+    }
+
+    #[test]
+    fn a_sample_config_is_parsed() {
+        let config: FileConfig = toml::from_str(r#"
+            addr = ["127.0.0.1:9090", "[::1]:9090"]
+            max_msg = 2048
+            rate = 2.5
+            idle_timeout = 300
+            op_password = "hunter2"
+
+            [aliases]
+            "/f" = ":flip"
+        "#).unwrap();
+
+        assert_eq!(config, FileConfig {
+            addr: Some(Addrs::Many(vec![String::from("127.0.0.1:9090"), String::from("[::1]:9090")])),
+            max_msg: Some(2048),
+            rate: Some(2.5),
+            idle_timeout: Some(300),
+            op_password: Some(String::from("hunter2")),
+            aliases: Some(HashMap::from([(String::from("/f"), String::from(":flip"))])),
+            ..FileConfig::default()
+        });
+        assert_eq!(config.value("SERVER_ADDR").as_deref(), Some("127.0.0.1:9090,[::1]:9090"));
+        assert_eq!(config.value("SERVER_RATE").as_deref(), Some("2.5"));
+        assert_eq!(config.value("SERVER_IDLE_TIMEOUT").as_deref(), Some("300"));
+        assert_eq!(config.value("SERVER_BURST"), None);
+    }
+
+    #[test]
+    fn a_config_with_an_unknown_or_mistyped_key_is_refused() {
+        assert!(toml::from_str::<FileConfig>("adr = \"127.0.0.1:9090\"").is_err());
+        assert!(toml::from_str::<FileConfig>("max_msg = \"big\"").is_err());
+        let config: FileConfig = toml::from_str("addr = \"127.0.0.1:9090\"").unwrap();
+        assert_eq!(config.value("SERVER_ADDR").as_deref(), Some("127.0.0.1:9090"));
+    }
+
+    #[test]
+    fn routed_messages_show_up_in_the_stats() {
+        let mut server = server();
+        let (mut alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        alice.0.operator = true;
+        server.clients = vec![alice, bob];
+        // other tests route messages at the same time, so only a lower bound holds
+        let before = METRICS.messages.load(Ordering::Relaxed);
+
+        for line in ["one", "two", "three"] {
+            server.handle_message(1, line);
+        }
+        server.handle_message(1, ":stats");
+        server.handle_message(2, ":stats");
+
+        assert!(METRICS.messages.load(Ordering::Relaxed) >= before + 3);
+        let stats = received(&alice_inbox).pop().unwrap();
+        let routed: u64 = stats.split(", ").nth(1).and_then(|part| part.strip_suffix(" messages routed")).unwrap().parse().unwrap();
+        assert!(routed >= before + 3, "{}", stats);
+        assert_eq!(received(&bob_inbox).pop().unwrap(), "stats: only operators may see the stats (see :op)");
+    }
+
+    #[test]
+    fn metrics_are_served_in_the_prometheus_format() {
+        let metrics = Metrics { clients: AtomicU64::new(2), messages: AtomicU64::new(0), hangman_games: AtomicU64::new(0), flips: AtomicU64::new(0) };
+        Metrics::count(&metrics.messages);
+        Metrics::count(&metrics.messages);
+
+        let text = metrics.prometheus();
+
+        assert!(text.contains("# TYPE chat_clients gauge\nchat_clients 2\n"), "{}", text);
+        assert!(text.contains("# TYPE chat_messages_total counter\nchat_messages_total 2\n"), "{}", text);
+        assert_eq!(metrics.summary(), "stats: 2 clients connected, 2 messages routed, 0 hangman games started, 0 coins flipped");
+    }
+
+    #[test]
+    fn a_timed_round_ends_at_its_deadline_and_reveals_the_word() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang start --timeout 60 secret");
+        assert!(received(&bob_inbox)[0].contains(" - 60s on the clock"));
+        let deadline = server.hangman_games[DEFAULT_ROOM].deadline.unwrap();
+
+        server.expire_games(deadline - Duration::from_secs(1));
+        assert!(server.hangman_games.contains_key(DEFAULT_ROOM));
+        assert!(received(&bob_inbox).is_empty());
+
+        server.expire_games(deadline);
+        assert!(server.hangman_games.is_empty());
+        assert_eq!(received(&bob_inbox), ["Hangman: time's up! the word was: secret"]);
+        assert_eq!(received(&alice_inbox).last().unwrap(), "Hangman: time's up! the word was: secret");
+    }
+
+    #[test]
+    fn an_untimed_round_never_expires() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        server.clients = vec![alice];
+
+        server.handle_message(1, ":hang start secret");
+        server.expire_games(Instant::now() + Duration::from_secs(24 * 60 * 60));
+
+        assert!(server.hangman_games.contains_key(DEFAULT_ROOM));
+    }
+
+    #[test]
+    fn ending_a_game_reveals_the_word() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang start quiz");
+        server.handle_message(2, ":hang u");
+        let during = received(&bob_inbox);
+        server.handle_message(1, ":hang end");
+
+        assert!(during.iter().all(|line| !line.contains("quiz")), "{:?}", during);
+        assert_eq!(received(&bob_inbox), ["Hangman game ended - the word was: quiz"]);
+        assert!(server.hangman_games.is_empty());
+    }
+
+    #[test]
+    fn losing_a_game_reveals_the_word() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang start quiz");
+        for letter in "abcdefgh".chars() {
+            server.handle_message(2, &format!(":hang {}", letter));
+        }
+        let during = received(&bob_inbox);
+        server.handle_message(2, ":hang j");
+
+        assert!(during.iter().all(|line| !line.contains("quiz")), "{:?}", during);
+        let lost = received(&bob_inbox);
+        assert!(lost[0].contains("Game Over!") && lost[0].ends_with("\nthe word was: quiz"), "{}", lost[0]);
+        assert!(server.hangman_games.is_empty());
+    }
+
+    #[test]
+    fn a_suggestion_leaves_the_game_alone() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, _carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":hang start quiz");
+        received(&bob_inbox);
+        received(&alice_inbox);
+        server.handle_message(3, ":hang suggest q");
+        server.handle_message(1, ":hang suggest u");
+
+        let round = &server.hangman_games[DEFAULT_ROOM];
+        assert!(round.game.guessed_letters().is_empty());
+        assert_eq!(round.turn, 0);
+        assert_eq!(received(&bob_inbox), ["carol suggests 'q'"]);
+        assert_eq!(received(&alice_inbox), ["carol suggests 'q'", "hangman: you chose this word, no hints from you"]);
+    }
+
+    #[test]
+    fn a_game_restarts_only_once_it_is_over() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang restart");
+        server.handle_message(1, ":hang start ox");
+        server.handle_message(1, ":hang restart");
+        let lines = received(&alice_inbox);
+        assert_eq!(lines[0], "hangman: nothing to restart, use :hang start");
+        assert_eq!(lines[2], "hangman: the game is still running, finish it or :hang end first");
+        assert_eq!(server.hangman_games[DEFAULT_ROOM].game.secret_word(), "ox");
+        server.handle_message(2, ":hang o");
+        server.handle_message(2, ":hang x");
+        received(&bob_inbox);
+
+        server.handle_message(2, ":hang restart cat");
+
+        let round = &server.hangman_games[DEFAULT_ROOM];
+        assert_eq!(round.game.secret_word(), "cat");
+        assert!(round.game.guessed_letters().is_empty());
+        let fresh = received(&alice_inbox).pop().unwrap();
+        assert!(fresh.starts_with("Hangman restarted by bob (word chosen by bob)") && fresh.contains("Word: ___"), "{}", fresh);
+    }
+
+    #[test]
+    fn a_bad_word_is_only_reported_to_the_starter() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang start 1234");
+
+        assert_eq!(received(&alice_inbox), ["The word must contain at least one letter"]);
+        assert!(received(&bob_inbox).is_empty());
+        assert!(server.hangman_games.is_empty());
+    }
+
+    #[test]
+    fn help_is_only_sent_to_the_asker() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":help");
+
+        let lines = received(&alice_inbox);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("Available commands:"), "{}", lines[0]);
+        assert!(received(&bob_inbox).is_empty());
+    }
+
+    #[test]
+    fn help_lists_every_command_and_alias() {
+        let mut server = server();
+        server.aliases = Aliases { map: HashMap::from([(String::from("/f"), String::from(":flip"))]) };
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        server.clients = vec![alice];
+
+        server.handle_message(1, ":help");
+
+        let help = received(&alice_inbox).pop().unwrap();
+        for command in COMMANDS {
+            assert!(help.lines().any(|line| line.starts_with(command.usage)), "{} is missing", command.usage);
+        }
+        assert!(help.ends_with("\n/f - same as :flip"), "{}", help);
+    }
+
+    #[test]
+    fn version_is_answered_with_the_crate_version() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        server.clients = vec![alice];
+
+        server.handle_message(1, ":version");
+
+        let reply = received(&alice_inbox).pop().unwrap();
+        assert!(reply.contains(env!("CARGO_PKG_VERSION")), "{}", reply);
+    }
+
+    #[test]
+    fn a_whisper_is_acknowledged_or_reported_as_failed() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":w bob psst");
+        drop(carol_inbox);
+        server.handle_message(1, ":w carol psst");
+        server.drop_closed();
+
+        assert_eq!(received(&bob_inbox), ["alice whispers: psst", "carol disconnected"]);
+        assert_eq!(received(&alice_inbox), [
+            "you whispered to bob: psst",
+            "whisper to carol failed: the message could not be delivered",
+            "carol disconnected",
+        ]);
+        assert_eq!(server.clients.len(), 2);
+    }
+
+    #[test]
+    fn a_private_send_to_a_dead_client_closes_it_until_it_is_dropped() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+        drop(bob_inbox);
+
+        assert!(send_to_client(&mut server.clients, 1, "hi alice"));
+        assert!(!send_to_client(&mut server.clients, 2, "hi bob"));
+        assert!(!send_to_client(&mut server.clients, 3, "hi nobody"));
+        assert!(server.clients[1].0.is_closed());
+
+        server.drop_closed();
+
+        assert_eq!(server.clients.len(), 1);
+        assert_eq!(server.clients[0].0.id, 1);
+        assert_eq!(received(&alice_inbox), ["hi alice", "bob disconnected"]);
+    }
+
+    #[test]
+    fn a_macro_is_chat_from_the_sender() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":tableflip");
+
+        assert_eq!(received(&alice_inbox), ["alice: (╯°□°)╯︵ ┻━┻"]);
+        assert_eq!(received(&bob_inbox), ["alice: (╯°□°)╯︵ ┻━┻"]);
+    }
+
+    #[test]
+    fn nick_renames_like_name() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":nick ally");
+
+        assert_eq!(display_name(&server.clients, 1), "ally");
+        assert_eq!(received(&bob_inbox), ["alice changed their name to ally"]);
+        assert!(received(&alice_inbox).is_empty());
+    }
+
+    #[test]
+    fn a_bare_name_goes_back_to_the_guest_name() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":name");
+        server.handle_message(1, ":nick");
+
+        assert_eq!(display_name(&server.clients, 1), "guest-1");
+        assert_eq!(received(&alice_inbox), ["you are now guest-1", "you are already guest-1"]);
+        assert_eq!(received(&bob_inbox), ["alice changed their name to guest-1"]);
+    }
+
+    #[test]
+    fn filtered_words_are_masked_whole_and_in_any_case() {
+        let filter = filter(&["darn", "heck"]);
+
+        assert_eq!(filter.mask("Darn it, what the HECK!"), "**** it, what the ****!");
+        assert_eq!(filter.mask("darned heckler"), "darned heckler");
+        assert_eq!(filter.mask("no bad words here"), "no bad words here");
+        assert!(filter.blocks("heck"));
+        assert!(!filter.blocks("hello"));
+        assert_eq!(WordFilter::default().mask("darn"), "darn");
+    }
+
+    #[test]
+    fn chat_emotes_and_names_are_filtered() {
+        let mut server = server();
+        server.filter = filter(&["darn"]);
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, "darn it");
+        server.handle_message(1, "fine then");
+        server.handle_message(1, ":me says darn");
+        server.handle_message(1, ":name darn");
+
+        assert_eq!(received(&bob_inbox), ["alice: **** it", "alice: fine then", "* alice says ****"]);
+        assert_eq!(received(&alice_inbox)[1], "invalid name: this name is not allowed\nchange the name with :name <new_name>");
+        assert_eq!(display_name(&server.clients, 1), "alice");
+    }
+
+    #[test]
+    fn only_the_author_can_edit_or_delete_a_message() {
+        let mut server = server();
+        let editor = || Some(Hello { capabilities: vec![String::from("edit")], ..Hello::ours() });
+        let (mut alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (mut bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        alice.0.hello = editor();
+        bob.0.hello = editor();
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, "helo");
+        let id = server.recent_messages.back().unwrap().0;
+        received(&bob_inbox);
+        server.handle_message(2, &format!(":edit {} pwned", id));
+        server.handle_message(2, &format!(":delete {}", id));
+        server.handle_message(1, &format!(":edit {} hello", id));
+
+        assert_eq!(received(&bob_inbox), [
+            String::from("edit: you can only edit your own messages"),
+            String::from("delete: you can only delete your own messages"),
+            format!(":edit {} hello", id),
+        ]);
+        // editors get their own lines back, numbered
+        assert_eq!(received(&alice_inbox), [format!(":msg {} alice: helo", id), format!(":edit {} hello", id)]);
+    }
+
+    #[test]
+    fn typing_is_forwarded_to_the_others_but_not_the_sender() {
+        let mut server = server();
+        let typist = || Some(Hello { capabilities: vec![String::from("typing")], ..Hello::ours() });
+        let (mut alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (mut bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        let (carol, carol_inbox) = client(3, "carol", DEFAULT_ROOM);
+        alice.0.hello = typist();
+        bob.0.hello = typist();
+        server.clients = vec![alice, bob, carol];
+
+        server.handle_message(1, ":typing");
+        // a second notice this soon is dropped
+        server.handle_message(1, ":typing");
+
+        assert!(received(&alice_inbox).is_empty());
+        assert_eq!(bob_inbox.try_iter().collect::<Vec<_>>(), [ServerMessage::Typing { from: String::from("alice") }]);
+        // carol did not ask for typing notices
+        assert!(received(&carol_inbox).is_empty());
+        assert!(server.history.replay(DEFAULT_ROOM, usize::MAX).is_none());
+    }
+
+    #[test]
+    fn a_new_joiner_gets_the_motd_once() {
+        let mut server = server();
+        server.motd = Some(String::from("--- message of the day ---\nbe nice"));
+        let (alice, alice_inbox) = client(1, "guest-1", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":name alice");
+        server.handle_message(1, ":name ally");
+        server.handle_message(1, ":motd");
+
+        assert_eq!(received(&alice_inbox), [
+            "connected:\nalice\nbob\n",
+            "--- message of the day ---\nbe nice",
+            "--- message of the day ---\nbe nice",
+        ]);
+        assert!(received(&bob_inbox).iter().all(|line| !line.contains("be nice")));
+    }
+
+    #[test]
+    fn motd_without_one_says_so() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        server.clients = vec![alice];
+
+        server.handle_message(1, ":motd");
+
+        assert_eq!(received(&alice_inbox), ["no MOTD set"]);
+    }
+
+    #[test]
+    fn a_ping_is_echoed_to_the_sender_only() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":ping 1700000000000");
+
+        assert_eq!(received(&alice_inbox), [":pong 1700000000000"]);
+        assert!(received(&bob_inbox).is_empty());
+    }
+
+    #[test]
+    fn joining_a_room_delivers_its_topic() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":join games");
+        server.handle_message(1, ":topic board games only");
+        received(&bob_inbox);
+        server.handle_message(2, ":join games");
+        server.handle_message(2, ":topic chess only");
+
+        assert_eq!(received(&bob_inbox), [
+            "you joined games",
+            "topic for games: board games only",
+            "topic: only operators and the room's creator can set the topic",
+        ]);
+        let lines = received(&alice_inbox);
+        assert_eq!(lines[lines.len() - 2..], ["alice set the topic: board games only", "bob joined games"]);
+    }
+
+    #[test]
+    fn a_repeating_client_is_muted_and_later_unmuted() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        for _ in 0..SPAM_REPEATS {
+            server.handle_message(1, "buy now");
+        }
+        server.handle_message(1, "something else");
+        assert_eq!(received(&bob_inbox), vec!["alice: buy now"; SPAM_REPEATS - 1]);
+        let lines = received(&alice_inbox);
+        assert_eq!(lines[0], "muted: you sent the same message 5 times in 10s, your messages are dropped for 30s");
+        assert!(lines[1].starts_with("muted: message dropped, you can talk again in "), "{}", lines[1]);
+
+        let until = server.clients[0].0.muted_until.unwrap();
+        server.lift_mutes(until - Duration::from_secs(1));
+        assert!(received(&alice_inbox).is_empty());
+        server.lift_mutes(until);
+        assert_eq!(received(&alice_inbox), ["you are no longer muted"]);
+
+        server.handle_message(1, "sorry");
+        assert_eq!(received(&bob_inbox), ["alice: sorry"]);
+    }
+
+    #[test]
+    fn an_alias_expands_to_its_command() {
+        let aliases = Aliases { map: aliases(&[("/w", ":w"), ("/f", "/flip"), ("/flip", ":flip")]) };
+
+        assert_eq!(aliases.expand("/w bob psst"), ":w bob psst");
+        assert_eq!(aliases.expand("/f"), ":flip");
+        assert_eq!(aliases.expand("/wow"), "/wow");
+        assert!(matches!(aliases.expand("hello /w"), Cow::Borrowed("hello /w")));
+    }
+
+    #[test]
+    fn an_alias_works_for_any_user() {
+        let mut server = server();
+        server.aliases = Aliases { map: aliases(&[("/w", ":w")]) };
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, "/w bob psst");
+
+        assert_eq!(received(&bob_inbox), ["alice whispers: psst"]);
+        assert_eq!(received(&alice_inbox), ["you whispered to bob: psst"]);
+    }
+
+    #[test]
+    fn check_aliases_refuses_cycles_and_bad_names() {
+        assert_eq!(check_aliases(&aliases(&[("/w", ":w"), ("/f", "/flip"), ("/flip", ":flip")])), Ok(()));
+        let cycle = check_aliases(&aliases(&[("/a", "/b now"), ("/b", "/c"), ("/c", "/a")])).unwrap_err();
+        assert!(cycle.starts_with("aliases form a cycle: "), "{}", cycle);
+        assert_eq!(check_aliases(&aliases(&[("/me", "/me")])), Err(String::from("aliases form a cycle: /me -> /me")));
+        assert_eq!(check_aliases(&aliases(&[(":flip", ":roll 1d2")])), Err(String::from("alias :flip would hide the command of the same name")));
+        assert_eq!(check_aliases(&aliases(&[("/w x", ":w")])), Err(String::from("alias \"/w x\" must be a single word")));
+        assert_eq!(check_aliases(&aliases(&[("/w", " ")])), Err(String::from("alias /w stands for nothing")));
+    }
+
+    #[test]
+    fn quit_removes_the_client_and_tells_the_room() {
+        let mut server = server();
+        let (alice, _alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":quit");
+
+        assert_eq!(received(&bob_inbox), ["alice left"]);
+        assert_eq!(server.clients.len(), 1);
+    }
+
+    #[test]
+    fn a_lurker_hears_the_room_but_cannot_speak() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (mut bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        bob.0.hello = Some(Hello::lurker());
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, "anyone here?");
+        let refused = [
+            "me!", ":me waves", ":shrug", ":w alice psst", ":flip", ":roll", ":8ball will it rain?", ":poll Lunch? | pizza | sushi",
+            ":topic lurking", ":hang start secret", ":file alice notes.txt 2 0/1 aGk=", ":send alice notes.txt",
+        ];
+        for line in refused {
+            server.handle_message(2, line);
+        }
+        server.handle_message(2, ":typing");
+        server.handle_message(2, ":list");
+        server.handle_message(2, ":version");
+
+        let bob_got = received(&bob_inbox);
+        assert_eq!(bob_got[0], "alice: anyone here?");
+        assert!(bob_got[1..=refused.len()].iter().all(|line| line == "lurk: read-only clients cannot send messages"), "{:?}", bob_got);
+        assert_eq!(bob_got[refused.len() + 1..], ["connected:\nalice\nbob\n".to_string(), version_text()]);
+        assert!(received(&alice_inbox).is_empty());
+        assert!(server.hangman_games.is_empty());
+    }
+
+    #[test]
+    fn the_suggester_of_a_resumed_game_still_cannot_guess() {
+        let dir = std::env::temp_dir().join(format!("chatproject-resume-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let game = create_hangman_match("alice", "secret").unwrap();
+        save_game(&game, &dir.join(format!("{}{}.json", AUTOSAVE_PREFIX, DEFAULT_ROOM))).unwrap();
+        let mut server = server();
+        resume_games(&mut server.hangman_games, &dir);
+        let _ = fs::remove_dir_all(&dir);
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":hang word secret");
+        server.handle_message(1, ":name mallory");
+        server.handle_message(1, ":hang suggest s");
+        server.handle_message(2, ":hang s");
+
+        // the claim sticks to the connection once made
+        assert_eq!(received(&alice_inbox)[..2], [
+            "hangman: you chose this word, let the others guess it",
+            "hangman: you chose this word, no hints from you",
+        ]);
+        assert!(server.hangman_games[DEFAULT_ROOM].game.is_ongoing());
+        assert!(received(&bob_inbox).iter().any(|m| m.starts_with("bob guessed 's'")));
+    }
+
+    #[test]
+    fn only_a_missing_ban_file_means_no_bans() {
+        let dir = std::env::temp_dir().join(format!("chatproject-bans-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bad = dir.join("bad.txt");
+        fs::write(&bad, b"mallory\n\xff\n").unwrap();
+        let good = dir.join("good.txt");
+        fs::write(&good, "mallory\n10.0.0.\n").unwrap();
+
+        let missing = load_bans(Some(&dir.join("missing.txt")));
+        let unreadable = load_bans(Some(&bad)).map_err(|e| e.to_string());
+        let read = load_bans(Some(&good));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(missing.unwrap().is_empty());
+        assert!(unreadable.unwrap_err().starts_with("could not read ban file"));
+        assert_eq!(read.unwrap(), HashSet::from([String::from("mallory"), String::from("10.0.0.")]));
+        assert!(load_bans(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_send_from_another_client_is_not_chat() {
+        let mut server = server();
+        let (alice, alice_inbox) = client(1, "alice", DEFAULT_ROOM);
+        let (bob, bob_inbox) = client(2, "bob", DEFAULT_ROOM);
+        server.clients = vec![alice, bob];
+
+        server.handle_message(1, ":send bob notes.txt");
+
+        assert_eq!(received(&alice_inbox), ["send: :send is a client command (use the bundled client)"]);
+        assert!(received(&bob_inbox).is_empty());
+    }
+}
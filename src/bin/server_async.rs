@@ -0,0 +1,488 @@
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use rand::Rng;
+use log::{debug, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use chatproject::shared::error::StartupError;
+use chatproject::shared::hangman::*;
+use chatproject::shared::protocol::{parse, validate_name, version_text, Command, Hello, ServerMessage};
+use chatproject::shared::transfer::FileChunk;
+use chatproject::shared::words::{random_word_by_difficulty, Difficulty};
+
+// Async variant of the chat server built on tokio. Instead of one OS thread
+// per socket, every connection runs as two lightweight tasks: a reader that
+// parses frames and handles commands, and a writer that forwards outgoing
+// messages from a shared broadcast channel. This lets a single process serve
+// hundreds of clients.
+//
+// The wire format is the same length-prefixed framing as server.rs, so the
+// existing client works unchanged and a migration can happen incrementally.
+// This binary covers the core command set (:name, :list, :flip, the text
+// macros, :hang, :help and plain chat); rooms, whispers and the other
+// extensions still live in the threaded server only.
+
+// What a hello can agree on here; there are no message ids, so no "edit".
+const SUPPORTED_CAPABILITIES: [&str; 2] = ["ping", "clear"];
+
+// Default bind address. Can be overridden with the SERVER_ADDR env var.
+const DEFAULT_LOCAL: &str = "127.0.0.1:9090";
+
+// Number of outgoing messages buffered per connection. A client that falls
+// further behind skips the oldest messages instead of slowing down others.
+const BROADCAST_CAPACITY: usize = 1024;
+
+const HELP: &str = "Available commands:\n:name <name> - set/change your display name (must be unique)\n:list - list connected users\n:flip - flip a coin (result sent to all)\n:shrug, :tableflip, :unflip, :lenny, :disapprove - send a text face to all\n:version - show the server version and protocol\n:hang start [word|easy|medium|hard] - start a hangman game (random medium word if none is given)\n:hang end - end the current hangman game\n:hang guess <letter> (or :hang <letter>) - send a hangman guess, must be one letter\n:hang word <word> - guess the whole word, a wrong guess costs one attempt\n:hang hint - reveal a letter, costs one wrong guess\n:hang status - show the current board (only to you)\n:quit - disconnect from server";
+
+// Who an outgoing message is meant for. Every writer task sees every message
+// on the broadcast channel and keeps only the ones addressed to it.
+#[derive(Clone)]
+enum Target {
+    All,
+    AllExcept(u64),
+    Only(u64),
+    // The last message for a client: its writer task ends after sending it,
+    // which closes the connection.
+    Last(u64),
+}
+
+#[derive(Clone)]
+struct Outgoing {
+    target: Target,
+    text: Arc<str>,
+}
+
+// Shared server state. Only touched briefly and never across an .await, so a
+// plain std Mutex is enough.
+struct State {
+    // (connection id, display name) of every connected client
+    clients: Vec<(u64, String)>,
+    // clients whose last :name attempt was rejected
+    name_rejected: HashSet<u64>,
+    // the running game and the connection that chose its word, if a client did
+    hangman: Option<(GameState, Option<u64>)>,
+    next_id: u64,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+// Locks the shared state. A task that panics while holding the lock poisons
+// it, but the state itself stays usable, so the other clients carry on
+// instead of panicking in turn.
+fn lock(state: &Shared) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn flip_coin() -> &'static str {
+    let mut rng = rand::thread_rng();
+    if rng.gen_bool(0.5) { "heads" } else { "tails" }
+}
+
+fn publish(out: &broadcast::Sender<Outgoing>, target: Target, text: &str) {
+    // An error only means that nobody is connected right now.
+    let _ = out.send(Outgoing { target, text: Arc::from(text) });
+}
+
+// Largest message (in bytes) the server accepts from a client. Can be
+// overridden with the SERVER_MAX_MSG env var, like in server.rs.
+const MAX_MSG_LEN: usize = 4096;
+
+// Oversized messages a client may send before it is disconnected.
+const MAX_MSG_VIOLATIONS: u32 = 3;
+
+// Reads one frame: a 4-byte big-endian length followed by UTF-8 text.
+// Returns Ok(None) on a clean EOF between frames. The length is checked
+// before anything is allocated: a payload longer than `max_len` bytes is
+// read and thrown away as it arrives and reported as a `FileTooLarge` error,
+// so the stream stays in sync.
+async fn read_frame(stream: &mut OwnedReadHalf, max_len: usize) -> io::Result<Option<String>> {
+    let len = match stream.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if len > max_len {
+        let skipped = tokio::io::copy(&mut (&mut *stream).take(len as u64), &mut tokio::io::sink()).await?;
+        if skipped < len as u64 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!("frame of {} bytes exceeds the limit of {} bytes", len, max_len),
+        ));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await?;
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_frame(stream: &mut OwnedWriteHalf, msg: &str) -> io::Result<()> {
+    let len = u32::try_from(msg.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long for a frame"))?;
+    let mut buf = Vec::with_capacity(4 + msg.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(msg.as_bytes());
+    stream.write_all(&buf).await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    // Logged like server.rs: lifecycle at info, message content at debug;
+    // RUST_LOG picks the level.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).target(env_logger::Target::Stdout).init();
+    let local = env::var("SERVER_ADDR").unwrap_or_else(|_| DEFAULT_LOCAL.to_string());
+    info!("Binding async server to {}", local);
+    let listener = TcpListener::bind(&local).await.map_err(|e| StartupError::boxed(format!("could not bind {}: {}", local, e)))?;
+    let max_len = env::var("SERVER_MAX_MSG").ok().and_then(|v| v.parse().ok()).unwrap_or(MAX_MSG_LEN);
+
+    let state: Shared = Arc::new(Mutex::new(State {
+        clients: Vec::new(),
+        name_rejected: HashSet::new(),
+        hangman: None,
+        next_id: 0,
+    }));
+    let (out, _) = broadcast::channel::<Outgoing>(BROADCAST_CAPACITY);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, addr) = accepted?;
+                info!("Client {} connected", addr);
+
+                let id = {
+                    let mut state = lock(&state);
+                    state.next_id += 1;
+                    let id = state.next_id;
+                    // display_name defaults to the peer address
+                    state.clients.push((id, addr.to_string()));
+                    id
+                };
+
+                let (reader, writer) = socket.into_split();
+                tokio::spawn(write_client(writer, id, out.subscribe()));
+                tokio::spawn(read_client(reader, id, state.clone(), out.clone(), max_len));
+            }
+            _ = tokio::signal::ctrl_c() => {
+                publish(&out, Target::All, "server shutting down");
+                // give the writer tasks a moment to flush the farewell
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                info!("server stopped");
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Writer task: forwards every broadcast message addressed to this client.
+async fn write_client(mut writer: OwnedWriteHalf, id: u64, mut inbox: broadcast::Receiver<Outgoing>) {
+    loop {
+        let msg = match inbox.recv().await {
+            Ok(msg) => msg,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("client {} is too slow, skipped {} messages", id, skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let wanted = match msg.target {
+            Target::All => true,
+            Target::AllExcept(sender) => sender != id,
+            Target::Only(recipient) | Target::Last(recipient) => recipient == id,
+        };
+        if wanted && write_frame(&mut writer, &msg.text).await.is_err() {
+            return;
+        }
+        if matches!(msg.target, Target::Last(recipient) if recipient == id) {
+            return;
+        }
+    }
+}
+
+// Reader task: reads frames and handles them until the client goes away.
+// A hello is only recognized as the very first frame; an unsupported
+// version ends the connection right there.
+async fn read_client(mut reader: OwnedReadHalf, id: u64, state: Shared, out: broadcast::Sender<Outgoing>, max_len: usize) {
+    let mut first = true;
+    let mut violations = 0;
+    // everyone but a client turned away by its hello hears that it left,
+    // whether it sent :quit, hung up or broke the connection
+    let mut announce = true;
+    loop {
+        match read_frame(&mut reader, max_len).await {
+            Ok(Some(msg)) => {
+                let opening = std::mem::take(&mut first);
+                // :quit ends the connection right away; the client closes
+                // its end too, which stops the writer task
+                if parse(&msg) == Command::Quit {
+                    break;
+                }
+                let Some(hello) = Hello::parse(&msg).filter(|_| opening) else {
+                    handle_message(&state, &out, id, &msg);
+                    continue;
+                };
+                match hello.negotiate(&SUPPORTED_CAPABILITIES) {
+                    Ok(agreed) => publish(&out, Target::Only(id), &agreed.to_text()),
+                    Err(rejection) => {
+                        publish(&out, Target::Last(id), &rejection);
+                        announce = false;
+                        break;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("dropping invalid utf8 frame from client {}", id);
+            }
+            // Oversized frames are skipped unread; a client that keeps
+            // sending them is disconnected.
+            Err(e) if e.kind() == io::ErrorKind::FileTooLarge => {
+                violations += 1;
+                warn!("dropping oversized frame from client {} ({}/{})", id, violations, MAX_MSG_VIOLATIONS);
+                if violations >= MAX_MSG_VIOLATIONS {
+                    publish(&out, Target::Last(id), "too many oversized messages, disconnecting");
+                    break;
+                }
+                publish(&out, Target::Only(id), &format!("message rejected: longer than {} bytes", max_len));
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    let mut state = lock(&state);
+    if announce {
+        let name = display_name(&state, id);
+        publish(&out, Target::AllExcept(id), &format!("{} left", name));
+    }
+    state.clients.retain(|(client, _)| *client != id);
+    state.name_rejected.remove(&id);
+    info!("closing connection with client {}", id);
+}
+
+fn display_name(state: &State, id: u64) -> String {
+    state.clients.iter().find(|(client, _)| *client == id).map(|(_, name)| name.clone()).unwrap_or_default()
+}
+
+fn handle_message(state: &Shared, out: &broadcast::Sender<Outgoing>, id: u64, content: &str) {
+    let mut state = lock(state);
+    let sender_name = display_name(&state, id);
+
+    if let Command::Name(name) = parse(content) {
+        // clients keep their address as the default name, there is no
+        // guest name to go back to
+        if name.is_empty() {
+            publish(out, Target::Only(id), "usage: :name <name>");
+            return;
+        }
+        let name = match validate_name(name) {
+            Ok(name) => name.to_string(),
+            Err(e) => {
+                publish(out, Target::Only(id), &format!("{}\nchange the name with :name <new_name>", e));
+                return;
+            }
+        };
+        if sender_name == name {
+            publish(out, Target::Only(id), &format!("you are already {}", name));
+            return;
+        }
+        if state.clients.iter().any(|(client, disp)| *client != id && disp == &name) {
+            state.name_rejected.insert(id);
+            publish(out, Target::Only(id), &format!("name_taken: {}\nchange the name with :name <new_name>", name));
+            return;
+        }
+        if let Some((_, disp)) = state.clients.iter_mut().find(|(client, _)| *client == id) {
+            *disp = name.clone();
+        }
+        if state.name_rejected.remove(&id) {
+            publish(out, Target::Only(id), &format!("{} is unique and was appended to your client!", name));
+        }
+        let announce = if sender_name.parse::<std::net::SocketAddr>().is_err() {
+            format!("{} changed their name to {}", sender_name, name)
+        } else {
+            format!("{} joined", name)
+        };
+        publish(out, Target::AllExcept(id), &announce);
+        return;
+    }
+
+    match parse(content) {
+        Command::Help => publish(out, Target::Only(id), HELP),
+        Command::Version => publish(out, Target::Only(id), &version_text()),
+        Command::Ping(token) => publish(out, Target::Only(id), &ServerMessage::Pong { token: token.to_string() }.to_text()),
+        // there is no message of the day to configure here
+        Command::Motd => publish(out, Target::Only(id), "no MOTD set"),
+        Command::List => {
+            let mut resp = String::from("connected:\n");
+            for (_, disp) in &state.clients {
+                resp.push_str(&format!("{}\n", disp));
+            }
+            publish(out, Target::Only(id), &resp);
+        }
+        Command::Flip => publish(out, Target::All, &format!("{}: flipped: {}", sender_name, flip_coin())),
+        Command::Macro(text) => publish(out, Target::All, &format!("{}: {}", sender_name, text)),
+        // a chunk is answered once, not once per chunk
+        Command::File(line) => {
+            if FileChunk::parse(line).is_some_and(|chunk| chunk.index == 0) {
+                publish(out, Target::Only(id), "file: file transfers are not supported by this server");
+            }
+        }
+        Command::SendFile(_) => publish(out, Target::Only(id), "send: :send is a client command (use the bundled client)"),
+        Command::Topic(_) => publish(out, Target::Only(id), "topic: rooms are not supported by this server"),
+        // no typing notifications here, see SUPPORTED_CAPABILITIES
+        Command::Typing => {}
+        Command::Edit(_) | Command::Delete(_) => publish(out, Target::Only(id), "edit: editing messages is not supported by this server"),
+        Command::Hang(_) => handle_hangman(&mut state.hangman, out, id, &sender_name, content),
+        _ => {
+            debug!("{}: {}", sender_name, content);
+            publish(out, Target::AllExcept(id), &format!("{}: {}", sender_name, content));
+        }
+    }
+}
+
+fn handle_hangman(hangman: &mut Option<(GameState, Option<u64>)>, out: &broadcast::Sender<Outgoing>, id: u64, sender_name: &str, content: &str) {
+    if let Some(rest) = content.strip_prefix(":hang start") {
+        if hangman.is_some() {
+            publish(out, Target::Only(id), "hangman: game already active");
+            return;
+        }
+        let secret = rest.trim();
+        let level = secret.parse::<Difficulty>().unwrap_or(Difficulty::Medium);
+        let (suggester, chooser, secret) = if secret.is_empty() || secret.parse::<Difficulty>().is_ok() {
+            ("the server", None, random_word_by_difficulty(level))
+        } else {
+            (sender_name, Some(id), secret)
+        };
+        let game = match create_hangman_match(suggester, secret) {
+            Ok(game) => game.with_max_incorrect(level.lives()),
+            Err(e) => {
+                publish(out, Target::Only(id), &e);
+                return;
+            }
+        };
+        publish(out, Target::All, &format!("Hangman started by {} (word chosen by {})\n{}", sender_name, suggester, render_hangman_state(&game)));
+        *hangman = Some((game, chooser));
+        return;
+    }
+
+    if content.trim() == ":hang end" {
+        match hangman.take() {
+            Some(_) => publish(out, Target::All, "Hangman game ended"),
+            None => publish(out, Target::Only(id), "hangman: no active game"),
+        }
+        return;
+    }
+
+    let Some((game, chooser)) = hangman.as_mut() else {
+        publish(out, Target::Only(id), "hangman: no active game");
+        return;
+    };
+
+    if content.trim() == ":hang status" {
+        publish(out, Target::Only(id), &format!("Hangman (word chosen by {})\n{}", game.suggester(), render_hangman_state(game)));
+        return;
+    }
+    if *chooser == Some(id) {
+        publish(out, Target::Only(id), "hangman: you chose this word, let the others guess it");
+        return;
+    }
+
+    let letter = content.strip_prefix(":hang guess ").or_else(|| {
+        content.strip_prefix(":hang ").filter(|rest| rest.trim().chars().count() == 1)
+    });
+
+    let result = if content.trim() == ":hang hint" {
+        reveal_hint(game, &mut rand::thread_rng())
+            .map(|letter| format!("{} took a hint: '{}' (counts as a wrong guess)", sender_name, letter))
+    } else if let Some(word) = content.strip_prefix(":hang word ") {
+        guess_word(word, game).map(|_| format!("{} guessed the word '{}'", sender_name, word.trim()))
+    } else if let Some(letter) = letter {
+        match check_letter(letter.trim(), game) {
+            Ok(GuessOutcome::AlreadyGuessed) => Err(format!("'{}' was already guessed", letter.trim())),
+            other => other.map(|_| format!("{} guessed '{}'", sender_name, letter.trim())),
+        }
+    } else {
+        Err(String::from("usage: :hang start [word|easy|medium|hard] | :hang end | :hang guess <letter> | :hang <letter> | :hang word <word> | :hang hint | :hang status"))
+    };
+
+    match result {
+        Ok(headline) => {
+            let mut msg = format!("{}\n{}", headline, render_hangman_state(game));
+            if is_game_lost(game) {
+                msg.push_str("\nThe hangman could not be saved - the game is over.");
+            } else if is_word_solved(game) {
+                msg.push_str(&format!("\nThe word chosen by {} is solved!", game.suggester()));
+            }
+            if !game.is_ongoing() {
+                hangman.take();
+            }
+            publish(out, Target::All, &msg);
+        }
+        Err(e) => publish(out, Target::Only(id), &e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(clients: &[(u64, &str)]) -> Shared {
+        Arc::new(Mutex::new(State {
+            clients: clients.iter().map(|&(id, name)| (id, name.to_string())).collect(),
+            name_rejected: HashSet::new(),
+            hangman: None,
+            next_id: clients.len() as u64,
+        }))
+    }
+
+    #[test]
+    fn a_poisoned_lock_does_not_take_down_other_clients() {
+        let state = state(&[(1, "ann")]);
+        let poisoner = state.clone();
+        let _ = std::thread::spawn(move || {
+            let _state = poisoner.lock();
+            panic!("a task died while holding the lock");
+        })
+        .join();
+        assert!(state.is_poisoned());
+
+        let (out, mut inbox) = broadcast::channel(16);
+        handle_message(&state, &out, 1, ":list");
+
+        assert_eq!(&*inbox.try_recv().unwrap().text, "connected:\nann\n");
+    }
+
+    #[test]
+    fn the_suggester_is_known_by_connection_not_name() {
+        let state = state(&[(1, "ann"), (2, "bob")]);
+        let (out, mut inbox) = broadcast::channel(16);
+        handle_message(&state, &out, 1, ":hang start secret");
+        handle_message(&state, &out, 1, ":name zed");
+        // a new client takes the name the suggester gave up
+        lock(&state).clients.push((3, String::from("ann")));
+        while inbox.try_recv().is_ok() {}
+
+        handle_message(&state, &out, 1, ":hang s");
+        handle_message(&state, &out, 3, ":hang s");
+
+        assert_eq!(&*inbox.try_recv().unwrap().text, "hangman: you chose this word, let the others guess it");
+        assert!(inbox.try_recv().unwrap().text.starts_with("ann guessed 's'"));
+    }
+
+    #[test]
+    fn a_send_is_answered_privately() {
+        let state = state(&[(1, "ann"), (2, "bob")]);
+        let (out, mut inbox) = broadcast::channel(16);
+
+        handle_message(&state, &out, 1, ":send bob notes.txt");
+
+        let reply = inbox.try_recv().unwrap();
+        assert!(matches!(reply.target, Target::Only(1)));
+        assert_eq!(&*reply.text, "send: :send is a client command (use the bundled client)");
+        assert!(inbox.try_recv().is_err());
+    }
+}
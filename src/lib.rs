@@ -1,3 +1,11 @@
 pub mod shared {
+    pub mod client;
+    pub mod commands;
+    pub mod error;
+    pub mod framing;
     pub mod hangman;
+    pub mod protocol;
+    pub mod transfer;
+    pub mod transport;
+    pub mod words;
 }
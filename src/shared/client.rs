@@ -0,0 +1,179 @@
+// A programmatic chat client: connect, send lines, wait for lines. The
+// interactive `client` binary is built on it, and tests or scripts can use
+// it to drive the server without a terminal.
+use std::io::{self, ErrorKind, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use rustls::ClientConfig;
+use crate::shared::framing::{read_frame, write_frame};
+use crate::shared::protocol::{Hello, TYPING_INTERVAL};
+use crate::shared::transport::{TlsTransport, Transport};
+
+// One connection to the server. It opens with the version handshake (see
+// protocol::Hello); a server that rejects the version sends a "protocol: ..."
+// line, which `recv` returns, and closes the connection. A background thread
+// reads the server's frames into a queue that `recv`/`recv_timeout` take
+// from, and answers heartbeat pings on its own so the server never sees this
+// client as gone. `send` and `recv` can be used from different threads at
+// the same time.
+pub struct ChatClient {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    incoming: Mutex<Receiver<String>>,
+    // used to shut the connection down from outside the reader thread
+    tcp: TcpStream,
+    // when the last typing notification went out
+    typing_sent: Mutex<Option<Instant>>,
+}
+
+impl ChatClient {
+    // Connects over plain TCP.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<ChatClient> {
+        ChatClient::connect_as(addr, Hello::ours())
+    }
+
+    // Connects over TLS. The server's certificate must be valid for the host
+    // part of `addr` ("host:port", IPv6 hosts in brackets).
+    pub fn connect_tls(addr: &str, config: Arc<ClientConfig>) -> io::Result<ChatClient> {
+        ChatClient::connect_tls_as(addr, config, Hello::ours())
+    }
+
+    // Like `connect`, but opens with `hello` instead of the default one
+    // (e.g. Hello::lurker()).
+    pub fn connect_as(addr: impl ToSocketAddrs, hello: Hello) -> io::Result<ChatClient> {
+        let tcp = TcpStream::connect(addr)?;
+        ChatClient::start(tcp.try_clone()?, Box::new(tcp), hello)
+    }
+
+    // Like `connect_tls`, but opens with `hello`.
+    pub fn connect_tls_as(addr: &str, config: Arc<ClientConfig>, hello: Hello) -> io::Result<ChatClient> {
+        let tcp = TcpStream::connect(addr)?;
+        let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host).trim_matches(['[', ']']);
+        let control = tcp.try_clone()?;
+        ChatClient::start(control, Box::new(TlsTransport::connect(config, host, tcp)?), hello)
+    }
+
+    fn start(tcp: TcpStream, transport: Box<dyn Transport>, hello: Hello) -> io::Result<ChatClient> {
+        let (mut reader, mut writer) = transport.split()?;
+        write_frame(&mut writer, &hello.to_text())?;
+        let writer = Arc::new(Mutex::new(writer));
+        let (tx, rx) = mpsc::channel();
+        let pong = writer.clone();
+        let mut greeted = false;
+        thread::spawn(move || loop {
+            match read_frame(&mut reader) {
+                // The server's answer to the hello; nothing for the user.
+                // Broadcasts may arrive before it.
+                Ok(Some(msg)) if !greeted && Hello::parse(&msg).is_some() => greeted = true,
+                Ok(Some(msg)) if msg == ":ping" => {
+                    if let Ok(mut writer) = pong.lock() {
+                        let _ = write_frame(&mut *writer, ":pong");
+                    }
+                }
+                Ok(Some(msg)) => {
+                    if tx.send(msg).is_err() { break; }
+                }
+                // The undecodable frame was consumed whole; skip it.
+                Err(ref err) if err.kind() == ErrorKind::InvalidData => {}
+                // EOF or a read error: dropping tx tells recv the connection is over.
+                Ok(None) | Err(_) => break,
+            }
+        });
+        Ok(ChatClient { writer, incoming: Mutex::new(rx), tcp, typing_sent: Mutex::new(None) })
+    }
+
+    // Sends one line (a chat message or a command) to the server.
+    pub fn send(&self, msg: &str) -> io::Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| io::Error::other("writer lock poisoned"))?;
+        write_frame(&mut *writer, msg)
+    }
+
+    // Tells the room that this user is typing. Meant to be called on every
+    // keystroke: notifications within TYPING_INTERVAL of the last one are
+    // skipped.
+    pub fn typing(&self) -> io::Result<()> {
+        {
+            let mut sent = self.typing_sent.lock().map_err(|_| io::Error::other("typing lock poisoned"))?;
+            if sent.is_some_and(|sent| sent.elapsed() < TYPING_INTERVAL) {
+                return Ok(());
+            }
+            *sent = Some(Instant::now());
+        }
+        self.send(":typing")
+    }
+
+    // Waits for the next line from the server. None once the connection has
+    // ended and every line received before that has been taken.
+    pub fn recv(&self) -> Option<String> {
+        self.incoming.lock().ok()?.recv().ok()
+    }
+
+    // Like `recv`, but gives up after `timeout`. None on timeout as well as
+    // when the connection has ended.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<String> {
+        self.incoming.lock().ok()?.recv_timeout(timeout).ok()
+    }
+
+    // Closes the connection. Lines already received can still be taken
+    // with `recv`.
+    pub fn close(&self) {
+        let _ = self.tcp.shutdown(Shutdown::Both);
+    }
+}
+
+impl Drop for ChatClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // A client connected to a listener the test plays the server on.
+    fn connected() -> (ChatClient, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = ChatClient::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn the_client_opens_with_its_hello() {
+        let (_client, mut server) = connected();
+
+        let hello = read_frame(&mut server).unwrap().unwrap();
+
+        assert_eq!(Hello::parse(&hello), Some(Hello::ours()));
+    }
+
+    #[test]
+    fn pings_are_answered_and_the_rest_is_queued() {
+        let (client, mut server) = connected();
+        read_frame(&mut server).unwrap();
+
+        write_frame(&mut server, &Hello::ours().to_text()).unwrap();
+        write_frame(&mut server, ":ping").unwrap();
+        write_frame(&mut server, "bob: hi").unwrap();
+
+        assert_eq!(client.recv_timeout(Duration::from_secs(5)).as_deref(), Some("bob: hi"));
+        assert_eq!(read_frame(&mut server).unwrap().as_deref(), Some(":pong"));
+        client.send("hello").unwrap();
+        assert_eq!(read_frame(&mut server).unwrap().as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn recv_ends_when_the_server_hangs_up() {
+        let (client, mut server) = connected();
+        write_frame(&mut server, "bye").unwrap();
+        drop(server);
+
+        assert_eq!(client.recv().as_deref(), Some("bye"));
+        assert_eq!(client.recv(), None);
+    }
+}
@@ -0,0 +1,125 @@
+// The chat commands the server understands. The server's `:help` reply and
+// the client's local `/help` are both generated from this table, so adding a
+// command here is all it takes to document it in both places.
+
+pub struct Command {
+    // The word that starts the command, e.g. ":hang". Several entries may
+    // share one name when a command has subcommands.
+    pub name: &'static str,
+    // How to call it, starting with the name.
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+const fn cmd(name: &'static str, usage: &'static str, description: &'static str) -> Command {
+    Command { name, usage, description }
+}
+
+pub const COMMANDS: &[Command] = &[
+    cmd(":help", ":help", "show this list"),
+    cmd(":name", ":name <name>", "set/change your display name (must be unique)"),
+    cmd(":name", ":name", "drop your name and go back to guest-<n>"),
+    cmd(":nick", ":nick <name>", "same as :name"),
+    cmd(":name?", ":name? <name>", "check whether a name is free without taking it"),
+    cmd(":list", ":list", "list connected users"),
+    cmd(":who", ":who <name>", "show details about one user"),
+    cmd(":uptime", ":uptime", "show how long you have been connected"),
+    cmd(":op", ":op <password>", "become an operator"),
+    cmd(":kick", ":kick <name>", "disconnect a user (operators only)"),
+    cmd(":ban", ":ban <name|address prefix>", "ban a user or address (operators only)"),
+    cmd(":unban", ":unban <entry>", "remove an entry from the ban list (operators only)"),
+    cmd(":w", ":w <name> <message>", "send a private message to one user"),
+    cmd(":send", ":send <name> <path>", "send a file of up to 64 KiB to one user"),
+    cmd(":join", ":join <room>", "move to another room (everyone starts in lobby)"),
+    cmd(":rooms", ":rooms", "list active rooms and their occupant counts"),
+    cmd(":topic", ":topic [text]", "show your room's topic, or set it (operators and the room's creator)"),
+    cmd(":away", ":away [reason]", "mark yourself as away"),
+    cmd(":back", ":back", "clear your away status"),
+    cmd(":me", ":me <action>", "describe an action, e.g. :me waves"),
+    cmd(":edit", ":edit <id> <text>", "change one of your recent chat lines (clients that show message ids)"),
+    cmd(":delete", ":delete <id>", "withdraw one of your recent chat lines (clients that show message ids)"),
+    cmd(":history", ":history [n]", "show the last n chat lines of your room"),
+    cmd(":clear", ":clear", "clear your screen"),
+    cmd(":flip", ":flip", "flip a coin (result sent to all)"),
+    cmd(":roll", ":roll <NdM>", "roll N dice with M sides, e.g. :roll 2d6 (result sent to your room)"),
+    cmd(":shrug", ":shrug", "send ¯\\_(ツ)_/¯ to your room"),
+    cmd(":tableflip", ":tableflip", "send (╯°□°)╯︵ ┻━┻ to your room"),
+    cmd(":unflip", ":unflip", "send ┬─┬ノ( º _ ºノ) to your room"),
+    cmd(":lenny", ":lenny", "send ( ͡° ͜ʖ ͡°) to your room"),
+    cmd(":disapprove", ":disapprove", "send ಠ_ಠ to your room"),
+    cmd(":8ball", ":8ball <question>", "ask the Magic 8-Ball (answer sent to your room)"),
+    cmd(":poll", ":poll \"question\" <option> <option> ...", "start a poll in your room (:poll shows it, :poll close ends it)"),
+    cmd(":vote", ":vote <n>", "vote for option n of your room's poll"),
+    cmd(":hang", ":hang start [--timeout <secs>] [word|easy|medium|hard]", "start a hangman game (random medium word if none is given), optionally ending it after secs"),
+    cmd(":hang", ":hang restart [word]", "start the next game once the last one is over, with the same settings"),
+    cmd(":hang", ":hang end", "end the current hangman game"),
+    cmd(":hang", ":hang guess <letter> (or :hang <letter>)", "send a hangman guess, must be one letter"),
+    cmd(":hang", ":hang word <word>", "guess the whole word, a wrong guess costs one attempt"),
+    cmd(":hang", ":hang hint", "reveal a letter, costs one wrong guess"),
+    cmd(":hang", ":hang suggest <letter>", "suggest a letter to the room without guessing it"),
+    cmd(":hang", ":hang status", "show the current board (only to you)"),
+    cmd(":hang", ":hang save <name> / :hang load <name>", "snapshot or resume this room's game"),
+    cmd(":scores", ":scores", "show the hangman leaderboard"),
+    cmd(":ping", ":ping", "measure the round trip to the server in milliseconds"),
+    cmd(":motd", ":motd", "show the message of the day again"),
+    cmd(":version", ":version", "show the server version, protocol version and build"),
+    cmd(":stats", ":stats", "show server counters: clients, messages, games, flips (operators only)"),
+    cmd(":quit", ":quit", "disconnect from server"),
+];
+
+// The `:help` text: one "usage - description" line per command whose name
+// starts with `prefix` (all of them for an empty prefix).
+pub fn help_text(prefix: &str) -> String {
+    let mut text = String::from("Available commands:");
+    for command in COMMANDS.iter().filter(|c| c.name.starts_with(prefix)) {
+        text.push_str(&format!("\n{} - {}", command.usage, command.description));
+    }
+    text
+}
+
+// Whether `word` is exactly the name of a command.
+pub fn is_command(word: &str) -> bool {
+    COMMANDS.iter().any(|c| c.name == word)
+}
+
+// The distinct command names that start with `prefix`, in table order.
+pub fn completions(prefix: &str) -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = Vec::new();
+    for command in COMMANDS.iter().filter(|c| c.name.starts_with(prefix)) {
+        if !names.contains(&command.name) {
+            names.push(command.name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_help_text_lists_every_command() {
+        let text = help_text("");
+        assert_eq!(text.lines().count(), COMMANDS.len() + 1);
+        for command in COMMANDS {
+            assert!(text.contains(&format!("\n{} - {}", command.usage, command.description)), "{}", command.usage);
+        }
+    }
+
+    #[test]
+    fn a_prefix_narrows_the_help_text() {
+        let text = help_text(":hang");
+        assert!(text.lines().skip(1).all(|line| line.starts_with(":hang")), "{}", text);
+        assert!(text.lines().count() > 2);
+        assert_eq!(help_text(":nope"), "Available commands:");
+    }
+
+    #[test]
+    fn completions_are_distinct_command_names() {
+        assert_eq!(completions(":hang"), [":hang"]);
+        assert!(completions(":").len() < COMMANDS.len());
+        assert!(completions(":nope").is_empty());
+        assert!(is_command(":quit"));
+        assert!(!is_command(":qui"));
+    }
+}
@@ -0,0 +1,30 @@
+// Errors that stop a binary before it gets going: a bad config, an address
+// that cannot be bound, a certificate that cannot be loaded. The binaries'
+// `main` returns them as `Box<dyn Error>`.
+use std::error::Error;
+use std::fmt;
+
+// A startup failure with a message meant for the operator. Rust prints an
+// error returned from `main` with its Debug form, so Debug shows the plain
+// message rather than a struct dump.
+pub struct StartupError(pub String);
+
+impl StartupError {
+    pub fn boxed(msg: impl Into<String>) -> Box<dyn Error> {
+        Box::new(StartupError(msg.into()))
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for StartupError {}
@@ -0,0 +1,235 @@
+// Length-prefixed message framing shared by the client and the server. Every
+// message on the wire is a 4-byte big-endian u32 length followed by exactly
+// that many UTF-8 bytes, so short messages stay short and long messages are
+// never truncated.
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+// Size of the big-endian length header that precedes every payload.
+pub const HEADER_LEN: usize = 4;
+
+// How long to back off when a non-blocking socket has no data yet in the
+// middle of a frame.
+const RETRY_DELAY: Duration = Duration::from_millis(5);
+
+// Reads one complete frame from the stream.
+//
+// Returns `Ok(None)` when the peer closed the connection cleanly between
+// frames. On a non-blocking socket, a `WouldBlock` error is returned only if
+// no byte of a new frame has arrived yet; once a frame has started, partial
+// reads are accumulated until the whole frame is available.
+//
+// The payload is only decoded once every byte of the frame has arrived, so a
+// multi-byte character split across TCP segments is never cut in half. A
+// payload that is still not valid UTF-8 yields an `InvalidData` error; the
+// frame has been consumed whole by then, so the caller can keep reading.
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Option<String>> {
+    read_frame_limited(stream, usize::MAX)
+}
+
+// Like `read_frame`, but refuses payloads longer than `max_len` bytes. The
+// length header is checked before anything is allocated; an oversized payload
+// is read and thrown away in small chunks and reported as a `FileTooLarge`
+// error, so the stream stays in sync and the caller can keep reading.
+pub fn read_frame_limited(stream: &mut impl Read, max_len: usize) -> io::Result<Option<String>> {
+    let mut header = [0u8; HEADER_LEN];
+    if !read_full(stream, &mut header, true)? {
+        return Ok(None);
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > max_len {
+        discard(stream, len)?;
+        return Err(io::Error::new(
+            ErrorKind::FileTooLarge,
+            format!("frame of {} bytes exceeds the limit of {} bytes", len, max_len),
+        ));
+    }
+
+    let mut payload = vec![0; len];
+    read_full(stream, &mut payload, false)?;
+
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+// Encodes `msg` as a single frame (length header + payload).
+pub fn encode_frame(msg: &str) -> io::Result<Vec<u8>> {
+    let payload = msg.as_bytes();
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "message too long for a frame"))?;
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+// Writes `msg` as a single frame (length header + payload).
+pub fn write_frame(stream: &mut impl Write, msg: &str) -> io::Result<()> {
+    let buf = encode_frame(msg)?;
+
+    // write_all gives up on WouldBlock and loses track of how much was sent,
+    // so write manually to keep non-blocking sockets in sync.
+    let mut written = 0;
+    while written < buf.len() {
+        match stream.write(&buf[written..]) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero)),
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(RETRY_DELAY),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Reads and drops `len` payload bytes without buffering them all.
+fn discard(stream: &mut impl Read, mut len: usize) -> io::Result<()> {
+    let mut chunk = [0u8; 4096];
+    while len > 0 {
+        let n = len.min(chunk.len());
+        read_full(stream, &mut chunk[..n], false)?;
+        len -= n;
+    }
+    Ok(())
+}
+
+// Fills `buf` completely. Returns `Ok(false)` on a clean EOF before the first
+// byte of a frame. `frame_start` marks the header read, the only point where
+// giving up on WouldBlock is safe because nothing has been consumed yet.
+fn read_full(stream: &mut impl Read, buf: &mut [u8], frame_start: bool) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) if frame_start && filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if frame_start && filled == 0 {
+                    return Err(e);
+                }
+                thread::sleep(RETRY_DELAY);
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+// Reads one newline-terminated line, for plain-text peers such as netcat or
+// telnet that cannot produce length prefixes. The line ending ("\n" or
+// "\r\n") is stripped. Returns `Ok(None)` on EOF before the first byte of a
+// line. Errors mirror `read_frame_limited`: a line longer than `max_len`
+// bytes is skipped up to its newline and reported as `FileTooLarge`, and a
+// line that is not valid UTF-8 yields `InvalidData`; in both cases the caller
+// can keep reading.
+pub fn read_line_limited(stream: &mut impl BufRead, max_len: usize) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut too_long = false;
+    loop {
+        let buf = match stream.fill_buf() {
+            Ok(buf) => buf,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if buf.is_empty() {
+            if line.is_empty() && !too_long {
+                return Ok(None);
+            }
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        let (chunk, done) = match buf.iter().position(|&b| b == b'\n') {
+            Some(pos) => (&buf[..=pos], true),
+            None => (buf, false),
+        };
+        let used = chunk.len();
+        // Keep at most max_len bytes plus the line ending; the rest of an
+        // oversized line is only consumed.
+        if !too_long {
+            line.extend_from_slice(chunk);
+            if line.len() > max_len.saturating_add(2) {
+                too_long = true;
+                line.clear();
+            }
+        }
+        stream.consume(used);
+        if done {
+            break;
+        }
+    }
+
+    if line.last() == Some(&b'\n') { line.pop(); }
+    if line.last() == Some(&b'\r') { line.pop(); }
+    if too_long || line.len() > max_len {
+        return Err(io::Error::new(ErrorKind::FileTooLarge, format!("line exceeds the limit of {} bytes", max_len)));
+    }
+    String::from_utf8(line).map(Some).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Hands out one byte per read, like a peer whose segments split every
+    // character.
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = buf.len().min(1);
+            self.0.read(&mut buf[..end])
+        }
+    }
+
+    #[test]
+    fn messages_longer_than_500_bytes_round_trip() {
+        let msg = "x".repeat(10_000);
+        let mut wire = Vec::new();
+        write_frame(&mut wire, &msg).unwrap();
+        write_frame(&mut wire, "next").unwrap();
+
+        let mut wire = Cursor::new(wire);
+        assert_eq!(read_frame(&mut wire).unwrap(), Some(msg));
+        assert_eq!(read_frame(&mut wire).unwrap().as_deref(), Some("next"));
+        assert_eq!(read_frame(&mut wire).unwrap(), None);
+    }
+
+    #[test]
+    fn a_frame_cut_short_is_an_error() {
+        let mut wire = encode_frame("hello").unwrap();
+        wire.truncate(6);
+        let err = read_frame(&mut Cursor::new(wire)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn multibyte_text_split_across_reads_arrives_whole() {
+        // 499 ASCII bytes put the 'é' across the old 500-byte boundary
+        let msg = format!("{}é and 🦀", "a".repeat(499));
+        let mut wire = Trickle(Cursor::new(encode_frame(&msg).unwrap()));
+        assert_eq!(read_frame(&mut wire).unwrap(), Some(msg));
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_and_the_next_frame_still_reads() {
+        let mut wire = vec![0, 0, 0, 2, 0xff, 0xfe];
+        wire.extend(encode_frame("still here").unwrap());
+        let mut wire = Cursor::new(wire);
+        assert_eq!(read_frame(&mut wire).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_frame(&mut wire).unwrap().as_deref(), Some("still here"));
+    }
+
+    #[test]
+    fn an_oversized_frame_is_skipped_and_reported() {
+        let mut wire = encode_frame(&"x".repeat(10_000)).unwrap();
+        wire.extend(encode_frame("short").unwrap());
+        let mut wire = Cursor::new(wire);
+        assert_eq!(read_frame_limited(&mut wire, 100).unwrap_err().kind(), ErrorKind::FileTooLarge);
+        assert_eq!(read_frame_limited(&mut wire, 100).unwrap().as_deref(), Some("short"));
+    }
+}
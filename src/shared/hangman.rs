@@ -1,8 +1,14 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
 use unicode_normalization::UnicodeNormalization;
 
 
-pub const HANGMAN_STRINGS: [&'static str; 10] = [
+pub const HANGMAN_STRINGS: [&str; 10] = [
 r#"
  
  
@@ -65,44 +71,103 @@ r" ____
 n∩"
 ];
 
+// Result of a valid guess passed to check_letter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuessOutcome {
+    // The letter is in the word, which is not complete yet.
+    Hit,
+    // The letter is not in the word, the hangman can still be saved.
+    Miss,
+    // The guess completed the word; the match is over.
+    Won,
+    // The guess used up the last attempt; the match is over.
+    Lost,
+    // The letter was guessed before; the game state is unchanged.
+    AlreadyGuessed,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GameState {
     ongoing: bool,
     secret_word: String,
     guessed_letters: Vec<char>,
     word_suggester_name: String,
+    // Hints taken so far; each one counts as an incorrect guess.
+    #[serde(default)]
+    hints_used: usize,
+    // Wrong full-word guesses; each one counts as an incorrect guess.
+    #[serde(default)]
+    wrong_word_guesses: usize,
+    // Incorrect guesses that lose the match. Older saves lack it and get
+    // the default.
+    #[serde(default = "default_max_incorrect")]
+    max_incorrect: usize,
 }
 
-pub fn render_hangman_state(state: &GameState) -> String {
-    let displayed_word: String = state.secret_word
-        .chars()
-        .map(|letter| {
-            let normalized_letter = normalize_char(letter);
-            if state.guessed_letters
-                .iter()
-                .any(|&guess| guess == normalized_letter)
-            {
-                letter  // keep original accent for display
-            } else {
-                '_'
-            }
-        })
-        .collect();
+// By default the match is lost when the gallows art is complete.
+fn default_max_incorrect() -> usize {
+    HANGMAN_STRINGS.len() - 1
+}
 
+impl GameState {
+    // Whether guesses are still accepted for this match.
+    pub fn is_ongoing(&self) -> bool {
+        self.ongoing
+    }
 
-    let normalized_word: Vec<char> = state.secret_word
-        .chars()
-        .map(normalize_char)
-        .collect();
+    // Name of the player who chose the secret word.
+    pub fn suggester(&self) -> &str {
+        &self.word_suggester_name
+    }
 
-    let incorrect_guesses = state.guessed_letters
-        .iter()
-        .filter(|&&letter| !normalized_word.contains(&letter))
-        .count();
+    // Sets how many incorrect guesses lose the match (at least one), e.g.
+    // more for an easy game and fewer for a hard one. The gallows art is
+    // scaled to it.
+    pub fn with_max_incorrect(mut self, max_incorrect: usize) -> GameState {
+        self.max_incorrect = max_incorrect.max(1);
+        self
+    }
+
+    // The word to guess. Only meant to be revealed once the match is over.
+    pub fn secret_word(&self) -> &str {
+        &self.secret_word
+    }
+
+    pub fn max_incorrect(&self) -> usize {
+        self.max_incorrect
+    }
+
+    // Letters guessed so far (normalized), in guessing order.
+    pub fn guessed_letters(&self) -> &[char] {
+        &self.guessed_letters
+    }
+
+    // The secret word with every letter not guessed yet replaced by '_'.
+    // Guessed letters keep their original case and accents. Spaces,
+    // hyphens, apostrophes and other non-letters are never guessed, so they
+    // are shown as they are and phrases keep their word boundaries.
+    pub fn masked_word(&self) -> String {
+        self.secret_word
+            .chars()
+            .map(|letter| {
+                let normalized_letter = normalize_char(letter);
+                if !letter.is_alphabetic() || self.guessed_letters.contains(&normalized_letter) {
+                    letter  // keep original accent for display
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn render_hangman_state(state: &GameState) -> String {
+    let displayed_word = state.masked_word();
+    let incorrect_guesses = incorrect_guess_count(state);
 
 
     let mut out = String::new();
-    out.push_str("\n");
+    out.push('\n');
     out.push_str(" ---------------- \n");
 
     out.push_str(&format!("Word: {}\n", displayed_word));
@@ -117,26 +182,68 @@ pub fn render_hangman_state(state: &GameState) -> String {
     }
 
     out.push_str(&format!(
-        "Incorrect guesses: {}\n",
-        incorrect_guesses
+        "Incorrect guesses: {} ({} of {} guesses left)\n",
+        incorrect_guesses,
+        state.max_incorrect.saturating_sub(incorrect_guesses),
+        state.max_incorrect
     ));
 
-    if is_word_solved(state) && incorrect_guesses < HANGMAN_STRINGS.len() - 1 {
+    let lost = incorrect_guesses >= state.max_incorrect;
+    if is_word_solved(state) && !lost {
         out.push_str("\nSuccess! You guessed the word - hangman is safe.");
-    } else if incorrect_guesses < HANGMAN_STRINGS.len() - 1 {
-        out.push_str(HANGMAN_STRINGS[incorrect_guesses]);
+    } else if !lost {
+        out.push_str(gallows(incorrect_guesses, state.max_incorrect));
         out.push_str("\nHangman can still be saved - guess wisely!");
     } else {
-        out.push_str(HANGMAN_STRINGS.last().unwrap());
+        out.push_str(gallows(incorrect_guesses, state.max_incorrect));
         out.push_str("\nGame Over!");
     }
     out.push_str("\n ---------------- ");
-    out.push_str("\n");
+    out.push('\n');
 
     out
 }
 
 
+// The gallows frame for this many incorrect guesses out of `max_incorrect`,
+// scaled so the figure is complete exactly when the match is lost. Counts
+// past the limit (say, from a hand-edited save file) show the full figure
+// rather than indexing out of bounds.
+fn gallows(incorrect_guesses: usize, max_incorrect: usize) -> &'static str {
+    let last = HANGMAN_STRINGS.len() - 1;
+    let frame = incorrect_guesses.min(max_incorrect) * last / max_incorrect.max(1);
+    HANGMAN_STRINGS[frame.min(last)]
+}
+
+
+// Whether the (normalized) letter occurs in the secret word, in any case or
+// with any accent. The one membership test for both guessing and counting
+// incorrect guesses, so the two cannot disagree.
+fn word_contains(state: &GameState, letter: char) -> bool {
+    state.secret_word
+        .chars()
+        .map(normalize_char)
+        .any(|c| c == letter)
+}
+
+
+// Wrong letters guessed so far plus the hints taken and wrong word guesses.
+fn incorrect_guess_count(state: &GameState) -> usize {
+    state.guessed_letters
+        .iter()
+        .filter(|&&letter| !word_contains(state, letter))
+        .count()
+        + state.hints_used
+        + state.wrong_word_guesses
+}
+
+
+// True once the incorrect guesses reached the match's limit.
+pub fn is_game_lost(state: &GameState) -> bool {
+    incorrect_guess_count(state) >= state.max_incorrect
+}
+
+
 pub fn is_word_solved(state: &GameState) -> bool {
     state.secret_word
         .chars()
@@ -147,16 +254,19 @@ pub fn is_word_solved(state: &GameState) -> bool {
 
 
 
+// The base letter of `c`, without accents and lowercased, so guesses match
+// regardless of case and diacritics. Lowercasing may yield more than one
+// char (e.g. 'İ' becomes "i̇"); the first one is the letter itself.
 fn normalize_char(c: char) -> char {
-    if c.is_alphabetic() {
-        c.nfd().next().unwrap().to_lowercase().next().unwrap()
-    } else {
-        c
+    if !c.is_alphabetic() {
+        return c;
     }
+    let base = c.nfd().next().unwrap_or(c);
+    base.to_lowercase().next().unwrap_or(base)
 }
 
 
-pub fn check_letter(input: &str, game_state: &mut GameState) -> Result<bool, String> {
+pub fn check_letter(input: &str, game_state: &mut GameState) -> Result<GuessOutcome, String> {
     if !game_state.ongoing {
         return Err(String::from("This match is already over, cannot check new letters for it!"));
     }
@@ -166,33 +276,447 @@ pub fn check_letter(input: &str, game_state: &mut GameState) -> Result<bool, Str
 
 
     let letter = normalize_char(input.chars().next().unwrap());
+    // Non-letters are revealed from the start and not part of solving.
+    if !letter.is_alphabetic() {
+        return Err(String::from("Please enter a letter"));
+    }
 
     if game_state.guessed_letters.contains(&letter) {
-        return Err(String::from("You already guessed this letter"));
+        return Ok(GuessOutcome::AlreadyGuessed);
     }
 
     game_state.guessed_letters.push(letter);
 
-    let letter_in_word = game_state.secret_word
-        .chars()
-        .map(normalize_char)
-        .any(|c| c == letter);
+    let letter_in_word = word_contains(game_state, letter);
 
 
+    if is_game_lost(game_state) {
+        game_state.ongoing = false;
+        return Ok(GuessOutcome::Lost);
+    }
     if is_word_solved(game_state) {
         game_state.ongoing = false;
+        return Ok(GuessOutcome::Won);
     }
 
-    Ok(letter_in_word)
+    if letter_in_word {
+        Ok(GuessOutcome::Hit)
+    } else {
+        Ok(GuessOutcome::Miss)
+    }
 }
 
 
-pub fn create_hangman_match(pl_creator: &str, word: &str) -> GameState {
-    let game = GameState {
+// Longest secret (in characters) a match can be created with, spaces and
+// punctuation of phrases included.
+pub const MAX_WORD_LEN: usize = 40;
+
+// Creates a match for `word`. The word must contain at least one letter,
+// otherwise there would be nothing to guess (an empty word counts as solved
+// right away), and may be at most MAX_WORD_LEN characters long.
+pub fn create_hangman_match(pl_creator: &str, word: &str) -> Result<GameState, String> {
+    if word.trim().is_empty() {
+        return Err(String::from("The word must not be empty"));
+    }
+    if !word.chars().any(char::is_alphabetic) {
+        return Err(String::from("The word must contain at least one letter"));
+    }
+    if word.chars().count() > MAX_WORD_LEN {
+        return Err(format!("The word must be at most {} characters long", MAX_WORD_LEN));
+    }
+
+    Ok(GameState {
         ongoing: true,
         secret_word: String::from(word),
         guessed_letters: Vec::new(),
         word_suggester_name: String::from(pl_creator),
+        hints_used: 0,
+        wrong_word_guesses: 0,
+        max_incorrect: default_max_incorrect(),
+    })
+}
+
+
+// Risks a guess of the whole word. The comparison ignores case and
+// diacritics, like letter guesses. A correct guess reveals the remaining
+// letters and ends the match; a wrong one counts as one incorrect guess.
+pub fn guess_word(input: &str, game_state: &mut GameState) -> Result<bool, String> {
+    if !game_state.ongoing {
+        return Err(String::from("This match is already over, cannot guess the word anymore!"));
+    }
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(String::from("Please enter a word to guess"));
+    }
+
+    let matches = input.chars().map(normalize_char)
+        .eq(game_state.secret_word.chars().map(normalize_char));
+
+    if matches {
+        for letter in game_state.secret_word.chars().filter(|c| c.is_alphabetic()).map(normalize_char) {
+            if !game_state.guessed_letters.contains(&letter) {
+                game_state.guessed_letters.push(letter);
+            }
+        }
+        game_state.ongoing = false;
+        return Ok(true);
+    }
+
+    game_state.wrong_word_guesses += 1;
+    if is_game_lost(game_state) {
+        game_state.ongoing = false;
+    }
+    Ok(false)
+}
+
+
+// Reveals one letter of the secret word that has not been guessed yet, chosen
+// with the given rng. The hint costs a life: it counts as one incorrect guess.
+pub fn reveal_hint<R: Rng>(game_state: &mut GameState, rng: &mut R) -> Result<char, String> {
+    if !game_state.ongoing || is_word_solved(game_state) {
+        return Err(String::from("This match is already over, no hints available!"));
+    }
+
+    let mut hidden: Vec<char> = game_state.secret_word
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .map(normalize_char)
+        .filter(|c| !game_state.guessed_letters.contains(c))
+        .collect();
+    hidden.sort_unstable();
+    hidden.dedup();
+
+    let Some(&letter) = hidden.choose(rng) else {
+        return Err(String::from("There are no letters left to reveal"));
     };
-    game
-}
\ No newline at end of file
+
+    game_state.guessed_letters.push(letter);
+    game_state.hints_used += 1;
+
+    if is_game_lost(game_state) || is_word_solved(game_state) {
+        game_state.ongoing = false;
+    }
+
+    Ok(letter)
+}
+
+// Formats the hangman leaderboard: one "<rank>. <name> - <wins>" line per
+// player, most wins first and ties ordered by name.
+pub fn format_leaderboard(scores: &HashMap<String, u32>) -> String {
+    if scores.is_empty() {
+        return String::from("No hangman games won yet.");
+    }
+
+    let mut ranking: Vec<(&String, &u32)> = scores.iter().collect();
+    ranking.sort_by(|(name_a, wins_a), (name_b, wins_b)| {
+        wins_b.cmp(wins_a).then_with(|| name_a.cmp(name_b))
+    });
+
+    let mut out = String::from("Hangman scores:");
+    for (rank, (name, wins)) in ranking.into_iter().enumerate() {
+        out.push_str(&format!("\n{}. {} - {}", rank + 1, name, wins));
+    }
+    out
+}
+
+
+// Writes the game to `path` as JSON so an interrupted round can be resumed.
+pub fn save_game(state: &GameState, path: &Path) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(state)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, json)
+}
+
+
+// Reads a game previously written by save_game.
+pub fn load_game(path: &Path) -> io::Result<GameState> {
+    let json = fs::read_to_string(path)?;
+    serde_json::from_str(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::words::Difficulty;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // Letters that are not in "cat".
+    const WRONG: &str = "bdefghijklmnopqrsuvwxyz";
+
+    // Guesses wrong letters until the game is over; returns how many it took.
+    fn miss_until_lost(game: &mut GameState) -> usize {
+        for (n, letter) in WRONG.chars().enumerate() {
+            if check_letter(&letter.to_string(), game) == Ok(GuessOutcome::Lost) {
+                return n + 1;
+            }
+        }
+        panic!("the game was never lost");
+    }
+
+    #[test]
+    fn easy_games_allow_more_incorrect_guesses() {
+        let mut game = create_hangman_match("ann", "cat").unwrap().with_max_incorrect(Difficulty::Easy.lives());
+        assert_eq!(game.max_incorrect(), 12);
+        assert_eq!(miss_until_lost(&mut game), 12);
+    }
+
+    #[test]
+    fn hard_games_allow_fewer_incorrect_guesses() {
+        let mut game = create_hangman_match("ann", "cat").unwrap().with_max_incorrect(Difficulty::Hard.lives());
+        assert_eq!(miss_until_lost(&mut game), 6);
+        assert!(!game.is_ongoing());
+    }
+
+    #[test]
+    fn medium_games_keep_the_default() {
+        let game = create_hangman_match("ann", "cat").unwrap();
+        assert_eq!(Difficulty::Medium.lives(), game.max_incorrect());
+    }
+
+    #[test]
+    fn the_board_shows_the_guesses_left() {
+        let mut game = create_hangman_match("ann", "cat").unwrap().with_max_incorrect(Difficulty::Hard.lives());
+        check_letter("z", &mut game).unwrap();
+        assert!(render_hangman_state(&game).contains("Incorrect guesses: 1 (5 of 6 guesses left)"));
+    }
+
+    #[test]
+    fn a_solved_word_renders_as_a_success() {
+        let mut game = create_hangman_match("ann", "cat").unwrap();
+        for letter in ["c", "x", "a"] {
+            check_letter(letter, &mut game).unwrap();
+            assert!(!render_hangman_state(&game).contains("Success"));
+        }
+        assert_eq!(check_letter("t", &mut game), Ok(GuessOutcome::Won));
+        let board = render_hangman_state(&game);
+        assert!(board.contains("Word: cat"), "{}", board);
+        assert!(board.contains("Success"), "{}", board);
+    }
+
+    #[test]
+    fn hints_are_repeatable_with_the_same_seed() {
+        let mut first = create_hangman_match("ann", "banana").unwrap();
+        let mut second = create_hangman_match("ann", "banana").unwrap();
+        let letter = reveal_hint(&mut first, &mut StdRng::seed_from_u64(7)).unwrap();
+        assert_eq!(reveal_hint(&mut second, &mut StdRng::seed_from_u64(7)), Ok(letter));
+
+        assert!("ban".contains(letter));
+        assert_eq!(first.guessed_letters(), [letter]);
+        assert!(render_hangman_state(&first).contains("Incorrect guesses: 1"));
+    }
+
+    #[test]
+    fn hints_never_reveal_a_guessed_letter() {
+        let mut game = create_hangman_match("ann", "banana").unwrap();
+        check_letter("a", &mut game).unwrap();
+        check_letter("n", &mut game).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(reveal_hint(&mut game, &mut rng), Ok('b'));
+        assert!(!game.is_ongoing());
+        assert!(reveal_hint(&mut game, &mut rng).is_err());
+    }
+
+    #[test]
+    fn a_lost_game_takes_no_more_guesses() {
+        let mut game = create_hangman_match("ann", "cat").unwrap();
+        assert_eq!(miss_until_lost(&mut game), default_max_incorrect());
+        assert!(is_game_lost(&game));
+        assert!(render_hangman_state(&game).contains("Game Over!"));
+
+        assert!(check_letter("c", &mut game).is_err());
+        assert!(guess_word("cat", &mut game).is_err());
+        assert!(reveal_hint(&mut game, &mut StdRng::seed_from_u64(0)).is_err());
+        assert_eq!(game.masked_word(), "___");
+    }
+
+    #[test]
+    fn check_letter_reports_each_outcome() {
+        let mut game = create_hangman_match("ann", "cat").unwrap().with_max_incorrect(2);
+        assert_eq!(check_letter("c", &mut game), Ok(GuessOutcome::Hit));
+        assert_eq!(check_letter("c", &mut game), Ok(GuessOutcome::AlreadyGuessed));
+        assert_eq!(check_letter("x", &mut game), Ok(GuessOutcome::Miss));
+        assert_eq!(check_letter("x", &mut game), Ok(GuessOutcome::AlreadyGuessed));
+        assert_eq!(check_letter("a", &mut game), Ok(GuessOutcome::Hit));
+        assert_eq!(check_letter("t", &mut game), Ok(GuessOutcome::Won));
+
+        let mut game = create_hangman_match("ann", "cat").unwrap().with_max_incorrect(2);
+        check_letter("x", &mut game).unwrap();
+        assert_eq!(check_letter("y", &mut game), Ok(GuessOutcome::Lost));
+    }
+
+    #[test]
+    fn check_letter_refuses_anything_but_one_letter() {
+        let mut game = create_hangman_match("ann", "cat").unwrap();
+        assert!(check_letter("", &mut game).is_err());
+        assert!(check_letter("ca", &mut game).is_err());
+        assert!(check_letter("7", &mut game).is_err());
+        assert!(game.guessed_letters().is_empty());
+    }
+
+    #[test]
+    fn guessing_the_whole_word_wins() {
+        let mut game = create_hangman_match("ann", "Cat").unwrap();
+        assert_eq!(guess_word(" cAT ", &mut game), Ok(true));
+        assert!(!game.is_ongoing());
+        assert!(is_word_solved(&game));
+        assert_eq!(game.masked_word(), "Cat");
+    }
+
+    #[test]
+    fn a_wrong_word_costs_one_guess() {
+        let mut game = create_hangman_match("ann", "cat").unwrap();
+        assert_eq!(guess_word("cot", &mut game), Ok(false));
+        assert!(game.is_ongoing());
+        assert!(game.guessed_letters().is_empty());
+        assert!(render_hangman_state(&game).contains("Incorrect guesses: 1"));
+        assert!(guess_word("  ", &mut game).is_err());
+    }
+
+    #[test]
+    fn the_leaderboard_ranks_by_wins_then_name() {
+        let scores = HashMap::from([
+            (String::from("cara"), 2),
+            (String::from("bob"), 5),
+            (String::from("ann"), 2),
+        ]);
+        assert_eq!(format_leaderboard(&scores), "Hangman scores:\n1. bob - 5\n2. ann - 2\n3. cara - 2");
+        assert_eq!(format_leaderboard(&HashMap::new()), "No hangman games won yet.");
+    }
+
+    #[test]
+    fn a_saved_game_loads_as_it_was() {
+        let mut game = create_hangman_match("ann", "Rust").unwrap().with_max_incorrect(6);
+        check_letter("r", &mut game).unwrap();
+        check_letter("z", &mut game).unwrap();
+        guess_word("rest", &mut game).unwrap();
+        let path = std::env::temp_dir().join(format!("chatproject-hangman-{}.json", std::process::id()));
+
+        save_game(&game, &path).unwrap();
+        let loaded = load_game(&path);
+        let _ = fs::remove_file(&path);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.suggester(), "ann");
+        assert_eq!(loaded.guessed_letters(), ['r', 'z']);
+        assert_eq!(loaded.max_incorrect(), 6);
+        assert!(loaded.is_ongoing());
+        assert_eq!(render_hangman_state(&loaded), render_hangman_state(&game));
+    }
+
+    #[test]
+    fn saves_without_the_newer_fields_still_load() {
+        let json = r#"{"ongoing":true,"secret_word":"cat","guessed_letters":["a"],"word_suggester_name":"ann"}"#;
+        let game: GameState = serde_json::from_str(json).unwrap();
+        assert_eq!(game.max_incorrect(), default_max_incorrect());
+        assert_eq!(game.masked_word(), "_a_");
+    }
+
+    #[test]
+    fn the_board_renders_exactly_as_pinned() {
+        let mut game = create_hangman_match("ann", "cat").unwrap();
+        check_letter("c", &mut game).unwrap();
+        check_letter("z", &mut game).unwrap();
+
+        assert_eq!(render_hangman_state(&game), concat!(
+            "\n",
+            " ---------------- \n",
+            "Word: c__\n",
+            "Guessed letters: cz\n",
+            "Incorrect guesses: 1 (8 of 9 guesses left)\n",
+            "\n",
+            " |\n",
+            " |\n",
+            " |\n",
+            " |\n",
+            "n∩\n",
+            "Hangman can still be saved - guess wisely!\n",
+            " ---------------- \n",
+        ));
+    }
+
+    #[test]
+    fn a_letter_in_the_word_is_never_incorrect_in_either_case() {
+        let mut game = create_hangman_match("ann", "Rust").unwrap();
+        assert_eq!(check_letter("r", &mut game), Ok(GuessOutcome::Hit));
+        assert_eq!(check_letter("R", &mut game), Ok(GuessOutcome::AlreadyGuessed));
+        assert_eq!(check_letter("U", &mut game), Ok(GuessOutcome::Hit));
+
+        assert_eq!(incorrect_guess_count(&game), 0);
+        assert_eq!(game.masked_word(), "Ru__");
+        assert!(render_hangman_state(&game).contains("Incorrect guesses: 0 (9 of 9 guesses left)"));
+    }
+
+    #[test]
+    fn an_impossibly_high_incorrect_count_shows_the_full_figure() {
+        let mut game = create_hangman_match("ann", "cat").unwrap();
+        // as a hand-edited save could have it: far past the limit, still running
+        game.wrong_word_guesses = 100;
+
+        let board = render_hangman_state(&game);
+
+        assert!(game.is_ongoing());
+        assert!(board.contains(HANGMAN_STRINGS[HANGMAN_STRINGS.len() - 1]), "{}", board);
+        assert!(board.contains("Incorrect guesses: 100 (0 of 9 guesses left)"), "{}", board);
+        assert_eq!(gallows(usize::MAX, 9), HANGMAN_STRINGS[HANGMAN_STRINGS.len() - 1]);
+        assert_eq!(gallows(3, 1), HANGMAN_STRINGS[HANGMAN_STRINGS.len() - 1]);
+    }
+
+    #[test]
+    fn a_phrase_keeps_its_spaces_and_punctuation() {
+        let mut game = create_hangman_match("ann", "rock n' roll").unwrap();
+        assert_eq!(game.masked_word(), "____ _' ____");
+        assert!(render_hangman_state(&game).contains("Word: ____ _' ____\n"));
+        assert_eq!(check_letter(" ", &mut game), Err(String::from("Please enter a letter")));
+        assert_eq!(check_letter("'", &mut game), Err(String::from("Please enter a letter")));
+
+        for letter in ["r", "o", "c", "k"] {
+            assert_eq!(check_letter(letter, &mut game), Ok(GuessOutcome::Hit));
+        }
+        assert_eq!(game.masked_word(), "rock _' ro__");
+        assert_eq!(check_letter("n", &mut game), Ok(GuessOutcome::Hit));
+        assert_eq!(check_letter("l", &mut game), Ok(GuessOutcome::Won));
+    }
+
+    #[test]
+    fn a_letter_that_lowercases_to_two_chars_is_matched() {
+        // 'İ' lowercases to "i̇", an 'i' and a combining dot
+        let mut game = create_hangman_match("ann", "İstanbul").unwrap();
+        assert_eq!(game.masked_word(), "________");
+
+        assert_eq!(check_letter("i", &mut game), Ok(GuessOutcome::Hit));
+        assert_eq!(check_letter("İ", &mut game), Ok(GuessOutcome::AlreadyGuessed));
+        assert_eq!(check_letter("S", &mut game), Ok(GuessOutcome::Hit));
+
+        assert_eq!(game.masked_word(), "İs______");
+        assert_eq!(incorrect_guess_count(&game), 0);
+    }
+
+    #[test]
+    fn accents_and_case_are_kept_on_the_board() {
+        let mut game = create_hangman_match("ann", "Éclair").unwrap();
+        check_letter("e", &mut game).unwrap();
+        check_letter("L", &mut game).unwrap();
+
+        assert_eq!(game.masked_word(), "É_l___");
+    }
+
+    #[test]
+    fn create_hangman_match_refuses_words_with_nothing_to_guess() {
+        assert_eq!(create_hangman_match("ann", "").err(), Some(String::from("The word must not be empty")));
+        assert_eq!(create_hangman_match("ann", "   ").err(), Some(String::from("The word must not be empty")));
+        assert_eq!(create_hangman_match("ann", "1234").err(), Some(String::from("The word must contain at least one letter")));
+        let long = "a".repeat(MAX_WORD_LEN + 1);
+        assert_eq!(create_hangman_match("ann", &long).err(), Some(format!("The word must be at most {} characters long", MAX_WORD_LEN)));
+    }
+
+    #[test]
+    fn create_hangman_match_accepts_a_valid_word() {
+        let game = create_hangman_match("ann", &"a".repeat(MAX_WORD_LEN)).unwrap();
+        assert!(game.is_ongoing());
+        let game = create_hangman_match("ann", "route 66").unwrap();
+        assert_eq!(game.masked_word(), "_____ 66");
+        assert_eq!(game.suggester(), "ann");
+    }
+}
@@ -0,0 +1,627 @@
+// Structured messages for the opt-in JSON protocol. A client that wants JSON
+// sends JSON_HANDSHAKE as the very first byte after connecting; from then on
+// every frame (still length-prefixed, see shared::framing) carries one of the
+// messages below serialized with serde_json. Clients that skip the handshake
+// keep using the plain text protocol.
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+// First byte a JSON client sends. Text frames always start with a zero byte
+// because messages are far below 16 MiB, so the two cannot be confused.
+pub const JSON_HANDSHAKE: u8 = 0x01;
+
+// Version of the wire protocol (framing, commands and their replies). Raised
+// whenever a change would break clients built for an older version.
+pub const PROTOCOL_VERSION: u32 = 1;
+// Oldest protocol version the server still accepts in a hello.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// Optional behaviour a client can ask for in its hello: "ping" means it
+// answers heartbeat pings, "clear" that it handles `:clear` itself, "edit"
+// that it wants chat lines with their ids and the edit/delete frames that
+// refer to them, "typing" that it wants typing notifications (see
+// ServerMessage) and "lurk" that it is read-only: it only watches, so the
+// server drops its chat lines and typing notifications.
+pub const CAPABILITIES: [&str; 5] = ["ping", "clear", "edit", "typing", "lurk"];
+
+// Capabilities that change what a client receives beyond plain lines, or
+// what it may send, so clients get them only by asking; everything else is
+// assumed for clients that skip the handshake.
+pub const OPT_IN_CAPABILITIES: [&str; 3] = ["edit", "typing", "lurk"];
+
+// A client that is typing sends `:typing` at most once per TYPING_INTERVAL;
+// the others show "<name> is typing..." until TYPING_TIMEOUT has passed
+// without another notification or a chat line from that user.
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(3);
+
+// The version handshake. A client may open with `:hello <version>
+// [capability,...]` as its very first frame; the server answers with a hello
+// of its own listing the capabilities both sides support, or rejects the
+// version with a "protocol: ..." notice and closes the connection. Clients
+// that open with anything else are served as before the handshake existed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    // What the bundled client speaks: the current version and every
+    // capability but the opt-in ones, since a terminal can neither change
+    // nor take back lines it has already printed.
+    pub fn ours() -> Hello {
+        let capabilities = CAPABILITIES.iter().filter(|cap| !OPT_IN_CAPABILITIES.contains(cap)).map(|cap| cap.to_string()).collect();
+        Hello { version: PROTOCOL_VERSION, capabilities }
+    }
+
+    // The bundled client's hello in lurk mode: ours plus "lurk".
+    pub fn lurker() -> Hello {
+        let mut hello = Hello::ours();
+        hello.capabilities.push(String::from("lurk"));
+        hello
+    }
+
+    // None unless `line` is a well-formed hello.
+    pub fn parse(line: &str) -> Option<Hello> {
+        let mut words = line.strip_prefix(":hello ")?.split_whitespace();
+        let version = words.next()?.parse().ok()?;
+        let capabilities = words.next().map(|caps| caps.split(',').filter(|cap| !cap.is_empty()).map(String::from).collect());
+        if words.next().is_some() {
+            return None;
+        }
+        Some(Hello { version, capabilities: capabilities.unwrap_or_default() })
+    }
+
+    pub fn to_text(&self) -> String {
+        if self.capabilities.is_empty() {
+            format!(":hello {}", self.version)
+        } else {
+            format!(":hello {} {}", self.version, self.capabilities.join(","))
+        }
+    }
+
+    // The server's answer to a client's hello: the agreed version and the
+    // capabilities both the client and `supported` list, or the rejection
+    // to send before closing.
+    pub fn negotiate(&self, supported: &[&str]) -> Result<Hello, String> {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.version) {
+            return Err(format!(
+                "protocol: version {} is not supported, this server speaks {} to {}; please update your client",
+                self.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ));
+        }
+        let capabilities = supported.iter().filter(|cap| self.has(cap)).map(|cap| cap.to_string()).collect();
+        Ok(Hello { version: self.version, capabilities })
+    }
+
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|cap| cap == capability)
+    }
+}
+
+// The `:version` reply, e.g. "version: chatproject 0.1.0, protocol 1, build
+// 1a2b3c4". The build is the git commit the binary was built from, if known.
+pub fn version_text() -> String {
+    format!(
+        "version: {} {}, protocol {}, build {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        PROTOCOL_VERSION,
+        option_env!("CHAT_GIT_HASH").unwrap_or("unknown")
+    )
+}
+
+// Text macros: a line that is exactly one of these commands is sent to the
+// sender's room as chat from the sender, with the command replaced by its
+// text. The texts are multibyte UTF-8; frames count bytes, so they travel
+// like any other chat.
+pub const MACROS: [(&str, &str); 5] = [
+    (":shrug", "¯\\_(ツ)_/¯"),
+    (":tableflip", "(╯°□°)╯︵ ┻━┻"),
+    (":unflip", "┬─┬ノ( º _ ºノ)"),
+    (":lenny", "( ͡° ͜ʖ ͡°)"),
+    (":disapprove", "ಠ_ಠ"),
+];
+
+// The text `line` stands for if it is a macro command.
+pub fn expand_macro(line: &str) -> Option<&'static str> {
+    MACROS.iter().find(|(command, _)| *command == line).map(|(_, text)| *text)
+}
+
+// Longest display name a client may pick, in characters.
+pub const MAX_NAME_LEN: usize = 24;
+
+// Checks a requested display name and returns it trimmed. Names show up one
+// per line in :list and are matched word by word in :w, so whitespace and
+// control characters are refused, as are the "[" / "]::" tokens of the old
+// message framing. guest-<n> names are reserved for clients without a name.
+pub fn validate_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(String::from("invalid name: the name is empty"));
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("invalid name: longer than {} characters", MAX_NAME_LEN));
+    }
+    if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(String::from("invalid name: spaces and control characters are not allowed"));
+    }
+    if name.contains('[') || name.contains("]::") {
+        return Err(String::from("invalid name: '[' and ']::' are not allowed"));
+    }
+    if name.strip_prefix("guest-").is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())) {
+        return Err(String::from("invalid name: guest-<number> names are reserved"));
+    }
+    Ok(name)
+}
+
+// What a client can send.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    // A chat line for the client's room.
+    Chat { body: String },
+    // Register or change the display name, like `:name`.
+    Name { name: String },
+    // Any other command, e.g. `{"type":"command","name":"join","args":"games"}`
+    // for `:join games`.
+    Command { name: String, args: String },
+    // The answer to a server Ping.
+    Pong,
+}
+
+impl ClientMessage {
+    // The equivalent line of the text protocol.
+    pub fn to_text(&self) -> String {
+        match self {
+            ClientMessage::Chat { body } => body.clone(),
+            ClientMessage::Name { name } => format!(":name {}", name),
+            ClientMessage::Command { name, args } if args.is_empty() => format!(":{}", name),
+            ClientMessage::Command { name, args } => format!(":{} {}", name, args),
+            ClientMessage::Pong => String::from(":pong"),
+        }
+    }
+}
+
+// What the server sends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    // A chat line; `ts` is the server time (HH:MM:SS) if timestamps are on.
+    // `id` is only set for clients that asked for "edit"; the text protocol
+    // then sends `:msg <id> <line>`.
+    Chat {
+        from: String,
+        body: String,
+        ts: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+    // Chat line `id` now reads `body` (`:edit <id> <body>`). Only sent to
+    // clients that asked for "edit".
+    Edit { id: u64, body: String },
+    // Chat line `id` was withdrawn by its sender (`:delete <id>`). Only sent
+    // to clients that asked for "edit".
+    Delete { id: u64 },
+    // The answer to a client's `:ping <token>` (`:pong <token>`), carrying
+    // the token back so the client can time the round trip.
+    Pong { token: String },
+    // `from` is typing (`:typing <name>`), see TYPING_TIMEOUT. Only sent to
+    // clients that asked for "typing".
+    Typing { from: String },
+    // Announcements, replies and everything else that is just text.
+    System { text: String },
+    // The answer to `:list`, one entry per connected user.
+    UserList { names: Vec<String> },
+    // Heartbeat; the client must answer with a pong (`:pong` in the text
+    // protocol) or it is eventually disconnected.
+    Ping,
+    // Asks the client to clear its screen (`:clear`). Clients that do not
+    // know it can simply ignore it.
+    Clear,
+}
+
+impl ServerMessage {
+    // The same message as sent to text protocol clients.
+    pub fn to_text(&self) -> String {
+        match self {
+            ServerMessage::Chat { from, body, ts, id } => {
+                let line = match ts {
+                    Some(ts) => format!("[{}] {}: {}", ts, from, body),
+                    None => format!("{}: {}", from, body),
+                };
+                match id {
+                    Some(id) => format!(":msg {} {}", id, line),
+                    None => line,
+                }
+            }
+            ServerMessage::Edit { id, body } => format!(":edit {} {}", id, body),
+            ServerMessage::Delete { id } => format!(":delete {}", id),
+            ServerMessage::Pong { token } if token.is_empty() => String::from(":pong"),
+            ServerMessage::Pong { token } => format!(":pong {}", token),
+            ServerMessage::Typing { from } => format!(":typing {}", from),
+            ServerMessage::System { text } => text.clone(),
+            ServerMessage::UserList { names } => {
+                let mut text = String::from("connected:\n");
+                for name in names {
+                    text.push_str(name);
+                    text.push('\n');
+                }
+                text
+            }
+            ServerMessage::Ping => String::from(":ping"),
+            ServerMessage::Clear => String::from(":clear"),
+        }
+    }
+}
+
+impl From<&str> for ServerMessage {
+    fn from(text: &str) -> ServerMessage {
+        ServerMessage::System { text: text.to_string() }
+    }
+}
+
+impl From<&String> for ServerMessage {
+    fn from(text: &String) -> ServerMessage {
+        ServerMessage::System { text: text.clone() }
+    }
+}
+
+impl From<String> for ServerMessage {
+    fn from(text: String) -> ServerMessage {
+        ServerMessage::System { text }
+    }
+}
+
+// A text protocol line as the server understands it. `parse` is the one
+// place that decides which command a line is, so the server's router and
+// the client (local echo, `:quit`) cannot disagree about it. Arguments
+// borrow from the parsed line; handlers validate them further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    // The answer to a heartbeat ping.
+    Pong,
+    // `:ping <token>`: the user measures the round trip, the token comes
+    // back in a ServerMessage::Pong. Not to be confused with the server's
+    // heartbeat `:ping`, which carries no token.
+    Ping(&'a str),
+    Flip,
+    // `:name? <name>`: ask whether a name is free without taking it.
+    NameCheck(&'a str),
+    // `:name [name]`, or `:nick [name]` as in IRC. Without a name the client
+    // goes back to its guest name.
+    Name(&'a str),
+    // `:hang [args]`; the hangman handler parses the line itself.
+    Hang(&'a str),
+    // `:w <name> <message>`, the arguments unsplit.
+    Whisper(&'a str),
+    // A chunk of a file for another user, see shared::transfer.
+    File(&'a str),
+    Join(&'a str),
+    Topic(&'a str),
+    Away(&'a str),
+    Back,
+    Op(&'a str),
+    Kick(&'a str),
+    Ban(&'a str),
+    Unban(&'a str),
+    Uptime,
+    Who(&'a str),
+    Poll(&'a str),
+    Vote(&'a str),
+    Clear,
+    Help,
+    Stats,
+    Version,
+    Motd,
+    Scores,
+    Rooms,
+    List,
+    Me(&'a str),
+    Roll(&'a str),
+    EightBall(&'a str),
+    History(&'a str),
+    // `:edit <id> <text>`, the arguments unsplit.
+    Edit(&'a str),
+    Delete(&'a str),
+    // A text macro, carrying the text to send (see MACROS).
+    Macro(&'static str),
+    // The sender is composing a message, see TYPING_INTERVAL.
+    Typing,
+    // Handled by the client, which exits instead of sending it.
+    Quit,
+    // `:send <name> <path>`: the client sends the file as `:file` chunks.
+    SendFile(&'a str),
+    // Everything else is chat, including unknown `:words`.
+    Chat(&'a str),
+}
+
+// Works out which command `line` is. Commands with required arguments
+// (`:name x`, `:kick x`, ...) need the space after the command word; the
+// bare word is chat. Commands with optional arguments (`:join`, `:roll`, ...)
+// get their arguments trimmed.
+pub fn parse(line: &str) -> Command<'_> {
+    match line {
+        ":pong" => return Command::Pong,
+        ":flip" => return Command::Flip,
+        ":back" => return Command::Back,
+        ":uptime" => return Command::Uptime,
+        ":clear" => return Command::Clear,
+        ":help" => return Command::Help,
+        ":stats" => return Command::Stats,
+        ":version" => return Command::Version,
+        ":motd" => return Command::Motd,
+        ":scores" => return Command::Scores,
+        ":rooms" => return Command::Rooms,
+        ":list" => return Command::List,
+        ":quit" => return Command::Quit,
+        ":typing" => return Command::Typing,
+        _ => {}
+    }
+    if let Some(text) = expand_macro(line) {
+        return Command::Macro(text);
+    }
+
+    if let Some(name) = line.strip_prefix(":name? ") {
+        Command::NameCheck(name)
+    } else if let Some(name) = optional_args(line, ":name").or_else(|| optional_args(line, ":nick")) {
+        Command::Name(name)
+    } else if let Some(args) = optional_args(line, ":hang") {
+        Command::Hang(args)
+    } else if let Some(rest) = line.strip_prefix(":w ") {
+        Command::Whisper(rest)
+    } else if line.starts_with(":file ") {
+        Command::File(line)
+    } else if let Some(args) = line.strip_prefix(":send ") {
+        Command::SendFile(args.trim())
+    } else if let Some(args) = optional_args(line, ":join") {
+        Command::Join(args)
+    } else if let Some(text) = optional_args(line, ":topic") {
+        Command::Topic(text)
+    } else if let Some(args) = optional_args(line, ":away") {
+        Command::Away(args)
+    } else if let Some(password) = line.strip_prefix(":op ") {
+        Command::Op(password.trim())
+    } else if let Some(name) = line.strip_prefix(":kick ") {
+        Command::Kick(name.trim())
+    } else if let Some(target) = line.strip_prefix(":ban ") {
+        Command::Ban(target.trim())
+    } else if let Some(target) = line.strip_prefix(":unban ") {
+        Command::Unban(target.trim())
+    } else if let Some(name) = line.strip_prefix(":who ") {
+        Command::Who(name.trim())
+    } else if let Some(args) = optional_args(line, ":poll") {
+        Command::Poll(args)
+    } else if let Some(args) = optional_args(line, ":vote") {
+        Command::Vote(args)
+    } else if let Some(action) = line.strip_prefix(":me ") {
+        Command::Me(action.trim())
+    } else if let Some(args) = optional_args(line, ":roll") {
+        Command::Roll(args)
+    } else if let Some(args) = optional_args(line, ":8ball") {
+        Command::EightBall(args)
+    } else if let Some(args) = optional_args(line, ":history") {
+        Command::History(args)
+    } else if let Some(token) = optional_args(line, ":ping") {
+        Command::Ping(token)
+    } else if let Some(args) = line.strip_prefix(":edit ") {
+        Command::Edit(args)
+    } else if let Some(id) = line.strip_prefix(":delete ") {
+        Command::Delete(id.trim())
+    } else {
+        Command::Chat(line)
+    }
+}
+
+// The trimmed arguments of `command` if `line` is that command, e.g.
+// Some("games") for `:join games` and Some("") for a bare `:join`. Other
+// commands that merely start with the same letters do not match.
+fn optional_args<'a>(line: &'a str, command: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(command)?;
+    if rest.is_empty() {
+        Some(rest)
+    } else {
+        rest.strip_prefix(' ').map(str::trim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::framing::{read_frame, write_frame};
+
+    #[test]
+    fn parses_commands_without_arguments() {
+        assert_eq!(parse(":pong"), Command::Pong);
+        assert_eq!(parse(":flip"), Command::Flip);
+        assert_eq!(parse(":back"), Command::Back);
+        assert_eq!(parse(":uptime"), Command::Uptime);
+        assert_eq!(parse(":clear"), Command::Clear);
+        assert_eq!(parse(":help"), Command::Help);
+        assert_eq!(parse(":stats"), Command::Stats);
+        assert_eq!(parse(":version"), Command::Version);
+        assert_eq!(parse(":motd"), Command::Motd);
+        assert_eq!(parse(":scores"), Command::Scores);
+        assert_eq!(parse(":rooms"), Command::Rooms);
+        assert_eq!(parse(":list"), Command::List);
+        assert_eq!(parse(":quit"), Command::Quit);
+        assert_eq!(parse(":typing"), Command::Typing);
+    }
+
+    #[test]
+    fn parses_commands_with_arguments() {
+        assert_eq!(parse(":name? kai"), Command::NameCheck("kai"));
+        assert_eq!(parse(":name kai"), Command::Name("kai"));
+        assert_eq!(parse(":nick kai"), Command::Name("kai"));
+        assert_eq!(parse(":hang start easy"), Command::Hang("start easy"));
+        assert_eq!(parse(":w kai hi there"), Command::Whisper("kai hi there"));
+        assert_eq!(parse(":file kai a.txt 3 0/1 YWJj"), Command::File(":file kai a.txt 3 0/1 YWJj"));
+        assert_eq!(parse(":send kai notes.txt "), Command::SendFile("kai notes.txt"));
+        assert_eq!(parse(":join games"), Command::Join("games"));
+        assert_eq!(parse(":topic board games"), Command::Topic("board games"));
+        assert_eq!(parse(":away lunch"), Command::Away("lunch"));
+        assert_eq!(parse(":op secret"), Command::Op("secret"));
+        assert_eq!(parse(":kick kai"), Command::Kick("kai"));
+        assert_eq!(parse(":ban 10.0."), Command::Ban("10.0."));
+        assert_eq!(parse(":unban kai"), Command::Unban("kai"));
+        assert_eq!(parse(":who kai"), Command::Who("kai"));
+        assert_eq!(parse(":poll lunch? | pizza | soup"), Command::Poll("lunch? | pizza | soup"));
+        assert_eq!(parse(":vote 2"), Command::Vote("2"));
+        assert_eq!(parse(":me waves"), Command::Me("waves"));
+        assert_eq!(parse(":roll 2d6"), Command::Roll("2d6"));
+        assert_eq!(parse(":8ball will it rain?"), Command::EightBall("will it rain?"));
+        assert_eq!(parse(":history 5"), Command::History("5"));
+        assert_eq!(parse(":ping 123"), Command::Ping("123"));
+        assert_eq!(parse(":edit 7 fixed"), Command::Edit("7 fixed"));
+        assert_eq!(parse(":delete 7"), Command::Delete("7"));
+        assert_eq!(parse(":shrug"), Command::Macro("¯\\_(ツ)_/¯"));
+        assert_eq!(parse("hello"), Command::Chat("hello"));
+    }
+
+    #[test]
+    fn optional_arguments_may_be_left_out() {
+        assert_eq!(parse(":name"), Command::Name(""));
+        assert_eq!(parse(":hang"), Command::Hang(""));
+        assert_eq!(parse(":join"), Command::Join(""));
+        assert_eq!(parse(":topic"), Command::Topic(""));
+        assert_eq!(parse(":away"), Command::Away(""));
+        assert_eq!(parse(":poll"), Command::Poll(""));
+        assert_eq!(parse(":roll"), Command::Roll(""));
+        assert_eq!(parse(":history"), Command::History(""));
+        assert_eq!(parse(":ping"), Command::Ping(""));
+    }
+
+    #[test]
+    fn words_that_only_start_like_a_command_are_chat() {
+        for line in [":hangman", ":hangout", ":hangxyz", ":names", ":nickname", ":joined", ":pinged", ":flipped", ":list2", ":shrugs"] {
+            assert_eq!(parse(line), Command::Chat(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn required_arguments_need_the_space() {
+        for line in [":w", ":kick", ":ban", ":unban", ":who", ":op", ":me", ":edit", ":delete", ":send", ":file", ":name?"] {
+            assert_eq!(parse(line), Command::Chat(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn commands_are_case_sensitive_and_unpadded() {
+        assert_eq!(parse(":LIST"), Command::Chat(":LIST"));
+        assert_eq!(parse(" :list"), Command::Chat(" :list"));
+        assert_eq!(parse(":list "), Command::Chat(":list "));
+        assert_eq!(parse(""), Command::Chat(""));
+    }
+
+    #[test]
+    fn every_client_message_survives_json() {
+        let messages = [
+            ClientMessage::Chat { body: String::from("hi \"there\"") },
+            ClientMessage::Name { name: String::from("zoë") },
+            ClientMessage::Command { name: String::from("join"), args: String::from("games") },
+            ClientMessage::Pong,
+        ];
+        for msg in messages {
+            let json = serde_json::to_string(&msg).unwrap();
+            assert_eq!(serde_json::from_str::<ClientMessage>(&json).unwrap(), msg, "{}", json);
+        }
+    }
+
+    #[test]
+    fn every_server_message_survives_json() {
+        let messages = [
+            ServerMessage::Chat { from: String::from("kai"), body: String::from("hi"), ts: Some(String::from("12:00:00")), id: Some(7) },
+            ServerMessage::Chat { from: String::from("kai"), body: String::from("hi"), ts: None, id: None },
+            ServerMessage::Edit { id: 7, body: String::from("fixed") },
+            ServerMessage::Delete { id: 7 },
+            ServerMessage::Pong { token: String::from("123") },
+            ServerMessage::Typing { from: String::from("kai") },
+            ServerMessage::System { text: String::from("kai joined") },
+            ServerMessage::UserList { names: vec![String::from("kai"), String::from("zoë")] },
+            ServerMessage::Ping,
+            ServerMessage::Clear,
+        ];
+        for msg in messages {
+            let json = serde_json::to_string(&msg).unwrap();
+            assert_eq!(serde_json::from_str::<ServerMessage>(&json).unwrap(), msg, "{}", json);
+        }
+    }
+
+    #[test]
+    fn json_messages_are_tagged_by_type() {
+        let json = r#"{"type":"command","name":"join","args":"games"}"#;
+        assert_eq!(serde_json::from_str::<ClientMessage>(json).unwrap().to_text(), ":join games");
+        assert_eq!(serde_json::to_string(&ServerMessage::Ping).unwrap(), r#"{"type":"ping"}"#);
+        // lines without an id leave the field out for older clients
+        let chat = ServerMessage::Chat { from: String::from("kai"), body: String::from("hi"), ts: None, id: None };
+        assert_eq!(serde_json::to_string(&chat).unwrap(), r#"{"type":"chat","from":"kai","body":"hi","ts":null}"#);
+    }
+
+    #[test]
+    fn the_version_names_the_crate_version() {
+        let version = version_text();
+        assert!(version.starts_with(&format!("version: chatproject {}, protocol {}, build ", env!("CARGO_PKG_VERSION"), PROTOCOL_VERSION)), "{}", version);
+    }
+
+    #[test]
+    fn a_matching_hello_agrees_on_the_shared_capabilities() {
+        let hello = Hello { version: PROTOCOL_VERSION, capabilities: vec![String::from("ping"), String::from("teleport")] };
+
+        let agreed = hello.negotiate(&["ping", "clear"]).unwrap();
+
+        assert_eq!(agreed, Hello { version: PROTOCOL_VERSION, capabilities: vec![String::from("ping")] });
+        assert_eq!(Hello::parse(&agreed.to_text()), Some(agreed));
+    }
+
+    #[test]
+    fn an_unsupported_version_is_refused() {
+        let hello = Hello { version: PROTOCOL_VERSION + 1, capabilities: Vec::new() };
+
+        let refusal = hello.negotiate(&CAPABILITIES).unwrap_err();
+
+        assert!(refusal.starts_with(&format!("protocol: version {} is not supported", PROTOCOL_VERSION + 1)), "{}", refusal);
+        assert_eq!(Hello::parse(":hello"), None);
+        assert_eq!(Hello::parse(":hello one"), None);
+        assert_eq!(Hello::parse(":hello 1 json extra"), None);
+    }
+
+    #[test]
+    fn each_macro_expands_to_its_text() {
+        assert_eq!(expand_macro(":shrug"), Some("¯\\_(ツ)_/¯"));
+        assert_eq!(expand_macro(":tableflip"), Some("(╯°□°)╯︵ ┻━┻"));
+        assert_eq!(expand_macro(":unflip"), Some("┬─┬ノ( º _ ºノ)"));
+        assert_eq!(expand_macro(":lenny"), Some("( ͡° ͜ʖ ͡°)"));
+        assert_eq!(expand_macro(":disapprove"), Some("ಠ_ಠ"));
+        assert_eq!(expand_macro(":shrug now"), None);
+        assert_eq!(expand_macro(":nope"), None);
+    }
+
+    #[test]
+    fn macro_texts_survive_framing() {
+        for (_, text) in MACROS {
+            let mut wire = Vec::new();
+            write_frame(&mut wire, text).unwrap();
+            let read = read_frame(&mut wire.as_slice()).unwrap();
+            assert_eq!(read.as_deref(), Some(text));
+        }
+    }
+
+    #[test]
+    fn validate_name_refuses_each_kind_of_bad_name() {
+        assert_eq!(validate_name("   "), Err(String::from("invalid name: the name is empty")));
+        assert!(validate_name(&"a".repeat(MAX_NAME_LEN + 1)).unwrap_err().contains("longer than"));
+        assert!(validate_name("ann smith").unwrap_err().contains("spaces"));
+        assert!(validate_name("ann\u{7}").unwrap_err().contains("control characters"));
+        assert!(validate_name("[ann").unwrap_err().contains("'['"));
+        assert!(validate_name("ann]::x").unwrap_err().contains("']::'"));
+        assert!(validate_name("guest-12").unwrap_err().contains("reserved"));
+    }
+
+    #[test]
+    fn validate_name_accepts_and_trims_a_good_name() {
+        assert_eq!(validate_name("  zoë_42 "), Ok("zoë_42"));
+        assert_eq!(validate_name("guest-star"), Ok("guest-star"));
+        assert_eq!(validate_name(&"a".repeat(MAX_NAME_LEN)).map(str::len), Ok(MAX_NAME_LEN));
+    }
+}
@@ -0,0 +1,234 @@
+// Sending small files to another user. The sending client cuts the file into
+// chunks and sends each one as a line
+//
+//   :file <user> <filename> <size> <index>/<count> <base64 data>
+//
+// where <user> is the recipient. The server checks the recipient and the
+// size, replaces <user> with the sender's name and passes the line on
+// without looking at the data; the recipient's client puts the file back
+// together.
+use std::collections::HashMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+// Largest file that can be sent, in bytes.
+pub const MAX_FILE_SIZE: usize = 64 * 1024;
+// Bytes per chunk. Encoded they take 2732 characters, so a chunk line stays
+// below the server's default message limit of 4096 bytes.
+pub const CHUNK_SIZE: usize = 2048;
+// Longest file name safe_filename returns, in bytes. Most file systems
+// refuse names longer than this.
+pub const MAX_FILENAME_LEN: usize = 255;
+
+// One `:file` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChunk<'a> {
+    // the recipient on the way to the server, the sender after it
+    pub peer: &'a str,
+    pub filename: &'a str,
+    // size of the whole file
+    pub size: usize,
+    pub index: usize,
+    pub count: usize,
+    pub data: &'a str,
+}
+
+impl<'a> FileChunk<'a> {
+    // None unless `line` is a well-formed `:file` line.
+    pub fn parse(line: &'a str) -> Option<FileChunk<'a>> {
+        let mut parts = line.strip_prefix(":file ")?.splitn(5, ' ');
+        let peer = parts.next()?;
+        let filename = parts.next()?;
+        let size = parts.next()?.parse().ok()?;
+        let (index, count) = parts.next()?.split_once('/')?;
+        let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+        let data = parts.next().unwrap_or("");
+        if peer.is_empty() || filename.is_empty() || index >= count {
+            return None;
+        }
+        Some(FileChunk { peer, filename, size, index, count, data })
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(":file {} {} {} {}/{} {}", self.peer, self.filename, self.size, self.index, self.count, self.data)
+    }
+}
+
+// The `:file` lines that send `data` to `recipient` under the name
+// `filename` (only its last path component is used, see safe_filename).
+pub fn chunk_file(recipient: &str, filename: &str, data: &[u8]) -> Result<Vec<String>, String> {
+    if data.len() > MAX_FILE_SIZE {
+        return Err(format!("files are limited to {} bytes, this one has {}", MAX_FILE_SIZE, data.len()));
+    }
+    let filename = safe_filename(filename);
+    // an empty file still needs one chunk to arrive
+    let pieces: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(CHUNK_SIZE).collect() };
+    let count = pieces.len();
+    let lines = pieces
+        .iter()
+        .enumerate()
+        .map(|(index, piece)| {
+            let encoded = STANDARD.encode(piece);
+            FileChunk { peer: recipient, filename: &filename, size: data.len(), index, count, data: &encoded }.to_text()
+        })
+        .collect();
+    Ok(lines)
+}
+
+// A file name that is safe to create in a download directory: the last path
+// component, with anything but letters, digits, '.', '-' and '_' replaced by
+// '_', without leading dots and cut to MAX_FILENAME_LEN bytes. Received
+// names go through it again, since another client may send anything.
+pub fn safe_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    let mut name = name.trim_start_matches('.');
+    if name.len() > MAX_FILENAME_LEN {
+        let mut end = MAX_FILENAME_LEN;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = &name[..end];
+    }
+    if name.is_empty() { String::from("file") } else { name.to_string() }
+}
+
+// Files being received, keyed by sender and file name. Chunks have to
+// arrive in order (they do, one connection carries them all); a new chunk 0
+// starts the file over.
+#[derive(Default)]
+pub struct Assembler {
+    partial: HashMap<(String, String), Partial>,
+}
+
+struct Partial {
+    size: usize,
+    count: usize,
+    next: usize,
+    data: Vec<u8>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    // Adds one chunk. Ok(Some(data)) once the file is complete, Ok(None)
+    // while more chunks are expected, Err if the file has to be given up.
+    pub fn add(&mut self, chunk: &FileChunk) -> Result<Option<Vec<u8>>, String> {
+        let key = (chunk.peer.to_string(), chunk.filename.to_string());
+        if chunk.index == 0 {
+            if chunk.size > MAX_FILE_SIZE {
+                return Err(format!("{} is larger than {} bytes", chunk.filename, MAX_FILE_SIZE));
+            }
+            self.partial.insert(key.clone(), Partial { size: chunk.size, count: chunk.count, next: 0, data: Vec::new() });
+        }
+        let Some(partial) = self.partial.get_mut(&key) else {
+            return Err(format!("{}: missed the start of the file", chunk.filename));
+        };
+
+        let decoded = STANDARD.decode(chunk.data);
+        let result = match decoded {
+            _ if chunk.index != partial.next || chunk.count != partial.count => Err(format!("{}: chunks out of order", chunk.filename)),
+            Err(_) => Err(format!("{}: damaged chunk", chunk.filename)),
+            Ok(bytes) if partial.data.len() + bytes.len() > partial.size => Err(format!("{}: more data than announced", chunk.filename)),
+            Ok(bytes) => {
+                partial.data.extend_from_slice(&bytes);
+                partial.next += 1;
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            self.partial.remove(&key);
+            return Err(e);
+        }
+
+        if partial.next < partial.count {
+            return Ok(None);
+        }
+        let Some(partial) = self.partial.remove(&key) else { return Ok(None); };
+        if partial.data.len() != partial.size {
+            return Err(format!("{}: less data than announced", chunk.filename));
+        }
+        Ok(Some(partial.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_filename_keeps_only_the_last_component() {
+        assert_eq!(safe_filename("../../etc/passwd"), "passwd");
+        assert_eq!(safe_filename("C:\\Users\\ann\\notes.txt"), "notes.txt");
+        assert_eq!(safe_filename("..hidden file?.txt"), "hidden_file_.txt");
+        assert_eq!(safe_filename("dir/"), "file");
+    }
+
+    #[test]
+    fn safe_filename_caps_the_length() {
+        assert_eq!(safe_filename(&"a".repeat(1000)).len(), MAX_FILENAME_LEN);
+    }
+
+    #[test]
+    fn safe_filename_cuts_between_characters() {
+        // 'é' takes two bytes, so byte 255 falls inside one
+        let name = safe_filename(&"é".repeat(200));
+        assert_eq!(name, "é".repeat(127));
+    }
+
+    #[test]
+    fn file_chunks_survive_a_round_trip() {
+        let chunk = FileChunk { peer: "ann", filename: "notes.txt", size: 5, index: 1, count: 3, data: "aGVsbG8=" };
+        assert_eq!(FileChunk::parse(&chunk.to_text()), Some(chunk));
+    }
+
+    #[test]
+    fn malformed_file_lines_are_rejected() {
+        assert_eq!(FileChunk::parse(":file ann notes.txt 5 3/3 aGVsbG8="), None);
+        assert_eq!(FileChunk::parse(":file ann notes.txt five 0/1 aGVsbG8="), None);
+        assert_eq!(FileChunk::parse(":file ann notes.txt 5"), None);
+        assert_eq!(FileChunk::parse("file ann notes.txt 5 0/1 aGVsbG8="), None);
+    }
+
+    #[test]
+    fn chunked_files_are_put_back_together() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let lines = chunk_file("bob", "data.bin", &data).unwrap();
+        assert_eq!(lines.len(), 3);
+
+        let mut assembler = Assembler::new();
+        let mut result = None;
+        for line in &lines {
+            result = assembler.add(&FileChunk::parse(line).unwrap()).unwrap();
+        }
+        assert_eq!(result, Some(data));
+    }
+
+    #[test]
+    fn an_empty_file_still_arrives() {
+        let lines = chunk_file("bob", "empty", &[]).unwrap();
+        assert_eq!(Assembler::new().add(&FileChunk::parse(&lines[0]).unwrap()), Ok(Some(Vec::new())));
+    }
+
+    #[test]
+    fn files_over_the_limit_are_refused() {
+        assert!(chunk_file("bob", "big", &vec![0; MAX_FILE_SIZE + 1]).is_err());
+        let chunk = FileChunk { peer: "ann", filename: "big", size: MAX_FILE_SIZE + 1, index: 0, count: 40, data: "" };
+        assert!(Assembler::new().add(&chunk).is_err());
+    }
+
+    #[test]
+    fn a_chunk_out_of_order_gives_the_file_up() {
+        let data = vec![7; CHUNK_SIZE * 3];
+        let lines = chunk_file("bob", "data.bin", &data).unwrap();
+        let mut assembler = Assembler::new();
+        assembler.add(&FileChunk::parse(&lines[0]).unwrap()).unwrap();
+        assert!(assembler.add(&FileChunk::parse(&lines[2]).unwrap()).is_err());
+        assert!(assembler.add(&FileChunk::parse(&lines[1]).unwrap()).is_err());
+    }
+}
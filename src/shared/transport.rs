@@ -0,0 +1,485 @@
+// Byte streams the chat runs over. Plain TCP, TLS, WebSocket (with the
+// `websocket` feature) and the in-memory pipe all implement Transport, so
+// the framing, the server's reader/writer threads and the client only ever
+// see Read and Write and do not care which one they got.
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection};
+#[cfg(feature = "websocket")]
+use tungstenite::protocol::{Role, WebSocket};
+#[cfg(feature = "websocket")]
+use tungstenite::Message;
+#[cfg(feature = "websocket")]
+use crate::shared::framing::encode_frame;
+
+// A connected, bidirectional stream. Both ends of the chat use one thread to
+// read and another to write, so a transport can be split into two halves
+// that work independently of each other.
+pub trait Transport: Read + Write + Send {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)>;
+}
+
+impl Transport for TcpStream {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        let writer = self.try_clone()?;
+        Ok((self, Box::new(writer)))
+    }
+}
+
+// Loads the certificate chain and private key (both PEM files) the server
+// presents to TLS clients.
+pub fn server_config(cert: &Path, key: &Path) -> io::Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", cert.display(), e)))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", key.display(), e)))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Arc::new(config))
+}
+
+// Trusts the certificates in the given PEM file, e.g. the server's own
+// self-signed certificate.
+pub fn client_config(ca: &Path) -> io::Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", ca.display(), e)))? {
+        let cert = cert.map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", ca.display(), e)))?;
+        roots.add(cert).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    }
+    let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+// A TCP stream wrapped in TLS. The rustls state is shared by the two halves
+// behind a mutex that is never held while waiting for the network, so the
+// reading half can block on the socket while the writing half sends.
+pub struct TlsTransport {
+    reader: TlsReader,
+    writer: TlsWriter,
+}
+
+impl TlsTransport {
+    // Server side: performs the handshake on an accepted stream.
+    pub fn accept(config: Arc<ServerConfig>, tcp: TcpStream) -> io::Result<TlsTransport> {
+        let conn = ServerConnection::new(config).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        TlsTransport::handshake(conn.into(), tcp)
+    }
+
+    // Client side: performs the handshake and checks that the server's
+    // certificate is valid for `host` (a DNS name or an IP address).
+    pub fn connect(config: Arc<ClientConfig>, host: &str, tcp: TcpStream) -> io::Result<TlsTransport> {
+        let name = ServerName::try_from(host.to_string()).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let conn = ClientConnection::new(config, name).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        TlsTransport::handshake(conn.into(), tcp)
+    }
+
+    fn handshake(mut conn: Connection, mut tcp: TcpStream) -> io::Result<TlsTransport> {
+        while conn.is_handshaking() {
+            if conn.complete_io(&mut tcp)? == (0, 0) {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+        let conn = Arc::new(Mutex::new(conn));
+        let reader = TlsReader { conn: conn.clone(), tcp: tcp.try_clone()?, pending: Vec::new() };
+        Ok(TlsTransport { reader, writer: TlsWriter { conn, tcp } })
+    }
+}
+
+impl Read for TlsTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for TlsTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        Ok((Box::new(self.reader), Box::new(self.writer)))
+    }
+}
+
+fn lock(conn: &Mutex<Connection>) -> io::Result<MutexGuard<'_, Connection>> {
+    conn.lock().map_err(|_| io::Error::other("tls state poisoned"))
+}
+
+// Sends whatever records rustls has queued (data, alerts, key updates).
+fn flush_tls(conn: &mut Connection, tcp: &mut TcpStream) -> io::Result<()> {
+    while conn.wants_write() {
+        conn.write_tls(tcp)?;
+    }
+    tcp.flush()
+}
+
+// Reading half of a TlsTransport.
+struct TlsReader {
+    conn: Arc<Mutex<Connection>>,
+    tcp: TcpStream,
+    // ciphertext read from the socket that rustls has not taken yet
+    pending: Vec<u8>,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut conn = lock(&self.conn)?;
+            // Plaintext that is already decrypted comes first; WouldBlock
+            // means rustls needs more ciphertext.
+            match conn.reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                other => return other,
+            }
+            if self.pending.is_empty() {
+                drop(conn);
+                let mut raw = [0u8; 4096];
+                let n = self.tcp.read(&mut raw)?;
+                if n == 0 { return Ok(0); }
+                self.pending.extend_from_slice(&raw[..n]);
+                continue;
+            }
+            let used = conn.read_tls(&mut self.pending.as_slice())?;
+            self.pending.drain(..used);
+            conn.process_new_packets().map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            flush_tls(&mut conn, &mut self.tcp)?;
+        }
+    }
+}
+
+// Writing half of a TlsTransport.
+struct TlsWriter {
+    conn: Arc<Mutex<Connection>>,
+    tcp: TcpStream,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = lock(&self.conn)?;
+        let n = conn.writer().write(buf)?;
+        flush_tls(&mut conn, &mut self.tcp)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = lock(&self.conn)?;
+        conn.writer().flush()?;
+        flush_tls(&mut conn, &mut self.tcp)
+    }
+}
+
+// One direction of an in-memory connection: bytes written by one end wait
+// here until the other end reads them.
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    // set once the writing end is gone; readers then see EOF
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.ready.notify_all();
+    }
+}
+
+// Reads block until the peer writes something or drops its end.
+struct PipeReader(Arc<Pipe>);
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pipe = &self.0;
+        let mut state = pipe.state.lock().map_err(|_| io::Error::other("pipe poisoned"))?;
+        while state.bytes.is_empty() && !state.closed && !buf.is_empty() {
+            state = pipe.ready.wait(state).map_err(|_| io::Error::other("pipe poisoned"))?;
+        }
+        let n = buf.len().min(state.bytes.len());
+        for (slot, byte) in buf.iter_mut().zip(state.bytes.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+struct PipeWriter(Arc<Pipe>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.state.lock().map_err(|_| io::Error::other("pipe poisoned"))?;
+        state.bytes.extend(buf);
+        self.0.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+// An in-memory connection, for driving the chat code without real sockets.
+// Dropping an end (or its writing half) is seen as EOF by the other end.
+pub struct MemoryTransport {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+// Two connected in-memory ends: what one writes, the other reads.
+pub fn memory_pair() -> (MemoryTransport, MemoryTransport) {
+    let (a_to_b, b_to_a) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+    let a = MemoryTransport { reader: PipeReader(b_to_a.clone()), writer: PipeWriter(a_to_b.clone()) };
+    let b = MemoryTransport { reader: PipeReader(a_to_b), writer: PipeWriter(b_to_a) };
+    (a, b)
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        Ok((Box::new(self.reader), Box::new(self.writer)))
+    }
+}
+
+// A WebSocket connection (browsers). The gateway speaks the text protocol
+// inside: every incoming text message becomes one length-prefixed frame for
+// the reader, and every frame the writer sends goes out as one text message,
+// so the rest of the server does not know it is talking to a browser.
+#[cfg(feature = "websocket")]
+pub struct WsTransport {
+    reader: WsReader,
+    writer: WsWriter,
+}
+
+#[cfg(feature = "websocket")]
+impl WsTransport {
+    // Server side: performs the HTTP upgrade on an accepted stream. After the
+    // handshake both halves share one WebSocket behind a mutex, like the
+    // rustls state of a TlsTransport, so data frames and the pongs and close
+    // replies tungstenite sends on its own never interleave on the socket.
+    pub fn accept(tcp: TcpStream) -> io::Result<WsTransport> {
+        tungstenite::accept(tcp.try_clone()?).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let stream = WsStream { tcp: tcp.try_clone()?, received: VecDeque::new() };
+        let ws = Arc::new(Mutex::new(WebSocket::from_raw_socket(stream, Role::Server, None)));
+        let reader = WsReader { ws: ws.clone(), tcp, pending: Vec::new() };
+        Ok(WsTransport { reader, writer: WsWriter { ws, buffer: Vec::new() } })
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Read for WsTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Write for WsTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Transport for WsTransport {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        Ok((Box::new(self.reader), Box::new(self.writer)))
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn ws_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::new(ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+// The socket under a WsTransport's WebSocket. Reads only hand out bytes the
+// reading half has already received and report WouldBlock otherwise, so the
+// mutex is never held while waiting for the network; writes go straight to
+// the socket.
+#[cfg(feature = "websocket")]
+struct WsStream {
+    tcp: TcpStream,
+    received: VecDeque<u8>,
+}
+
+#[cfg(feature = "websocket")]
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.received.is_empty() && !buf.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(self.received.len());
+        for (slot, byte) in buf.iter_mut().zip(self.received.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tcp.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tcp.flush()
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn lock_ws(ws: &Mutex<WebSocket<WsStream>>) -> io::Result<MutexGuard<'_, WebSocket<WsStream>>> {
+    ws.lock().map_err(|_| io::Error::other("websocket state poisoned"))
+}
+
+// Reading half of a WsTransport.
+#[cfg(feature = "websocket")]
+struct WsReader {
+    ws: Arc<Mutex<WebSocket<WsStream>>>,
+    tcp: TcpStream,
+    // the current message, already encoded as a frame
+    pending: Vec<u8>,
+}
+
+#[cfg(feature = "websocket")]
+impl Read for WsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            let mut ws = lock_ws(&self.ws)?;
+            match ws.read() {
+                Ok(Message::Text(text)) => self.pending = encode_frame(text.as_str())?,
+                // answer the close here, the writer may have nothing to send
+                Ok(Message::Close(_)) => {
+                    let _ = ws.flush();
+                    return Ok(0);
+                }
+                // pings are answered by tungstenite; binary messages have no
+                // meaning in the chat
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => return Ok(0),
+                // WouldBlock: the WebSocket needs more bytes, wait for them
+                // without holding the lock
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {
+                    drop(ws);
+                    let mut raw = [0u8; 4096];
+                    let n = self.tcp.read(&mut raw)?;
+                    if n == 0 { return Ok(0); }
+                    lock_ws(&self.ws)?.get_mut().received.extend(&raw[..n]);
+                }
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+// Writing half of a WsTransport.
+#[cfg(feature = "websocket")]
+struct WsWriter {
+    ws: Arc<Mutex<WebSocket<WsStream>>>,
+    // bytes of a frame that has not been written completely yet
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "websocket")]
+impl Write for WsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= 4 {
+            let len = u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) as usize;
+            if self.buffer.len() < 4 + len { break; }
+            let text = String::from_utf8_lossy(&self.buffer[4..4 + len]).into_owned();
+            self.buffer.drain(..4 + len);
+            lock_ws(&self.ws)?.send(Message::text(text)).map_err(ws_error)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock_ws(&self.ws)?.flush().map_err(ws_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::framing::{read_frame, write_frame};
+
+    #[test]
+    fn memory_ends_talk_both_ways() {
+        let (mut a, mut b) = memory_pair();
+        write_frame(&mut a, "ping").unwrap();
+        assert_eq!(read_frame(&mut b).unwrap().as_deref(), Some("ping"));
+        write_frame(&mut b, "pong").unwrap();
+        assert_eq!(read_frame(&mut a).unwrap().as_deref(), Some("pong"));
+    }
+
+    #[test]
+    fn split_halves_work_from_different_threads() {
+        let (a, b) = memory_pair();
+        let (mut a_reader, mut a_writer) = (Box::new(a) as Box<dyn Transport>).split().unwrap();
+        let echo = std::thread::spawn(move || {
+            let mut b = b;
+            while let Some(msg) = read_frame(&mut b).unwrap() {
+                write_frame(&mut b, &msg.to_uppercase()).unwrap();
+            }
+        });
+
+        write_frame(&mut a_writer, "hello").unwrap();
+        assert_eq!(read_frame(&mut a_reader).unwrap().as_deref(), Some("HELLO"));
+        drop(a_writer);
+        echo.join().unwrap();
+        assert_eq!(read_frame(&mut a_reader).unwrap(), None);
+    }
+}
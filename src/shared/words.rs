@@ -0,0 +1,175 @@
+// Built-in word list for hangman. Lets the server pick a secret word itself
+// so that nobody has to type it into the chat where everyone can read it.
+use rand::seq::SliceRandom;
+use std::fmt;
+use std::str::FromStr;
+
+// Difficulty buckets for random words, based on word length: longer words
+// have more distinct letters to find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    // Short common words, up to 5 letters.
+    Easy,
+    // Words of 6 or 7 letters.
+    Medium,
+    // Long words of 8 letters or more.
+    Hard,
+}
+
+impl Difficulty {
+    // Inclusive word length range for this difficulty.
+    pub fn length_range(self) -> (usize, usize) {
+        match self {
+            Difficulty::Easy => (1, 5),
+            Difficulty::Medium => (6, 7),
+            Difficulty::Hard => (8, usize::MAX),
+        }
+    }
+
+    // Incorrect guesses a round of this difficulty allows: more for easy
+    // rounds, fewer for hard ones. Medium keeps the full gallows (see
+    // hangman::HANGMAN_STRINGS).
+    pub fn lives(self) -> usize {
+        match self {
+            Difficulty::Easy => 12,
+            Difficulty::Medium => 9,
+            Difficulty::Hard => 6,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("unknown difficulty: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        };
+        f.write_str(name)
+    }
+}
+
+pub const WORDS: &[&str] = &[
+    "apple", "bread", "chair", "cloud", "dance", "eagle", "flame", "grape",
+    "heart", "house", "juice", "knife", "lemon", "money", "night", "ocean",
+    "piano", "queen", "river", "snake", "table", "tiger", "train", "water",
+    "whale", "zebra", "actor", "angel", "beach", "bench", "berry", "black",
+    "blade", "board", "brain", "brick", "brush", "candy", "chain", "chalk",
+    "charm", "cheek", "chess", "chest", "child", "coast", "coral", "crane",
+    "crown", "dream", "drink", "earth", "field", "fairy", "feast", "fence",
+    "flute", "frost", "ghost", "giant", "glass", "globe", "grass", "honey",
+    "horse", "hotel", "igloo", "jelly", "jewel", "kayak", "koala", "laser",
+    "latte", "light", "lunch", "magic", "maple", "medal", "metal", "mouse",
+    "music", "nurse", "olive", "onion", "opera", "otter", "paint", "paper",
+    "party", "peach", "pearl", "pilot", "plane", "plant", "pizza", "radio",
+    "robot", "rocket", "salad", "scarf", "shark", "sheep", "shell", "skate",
+    "smile", "spoon", "stone", "storm", "sugar", "sword", "teeth", "tower",
+    "truck", "uncle", "video", "voice", "wagon", "watch", "wheel", "witch",
+    "world", "yacht", "anchor", "animal", "autumn", "banana", "basket",
+    "bottle", "bridge", "butter", "camera", "candle", "carpet", "castle",
+    "cookie", "cotton", "desert", "doctor", "dragon", "engine", "falcon",
+    "flower", "forest", "friend", "garden", "ginger", "guitar", "hammer",
+    "harbor", "helmet", "island", "jacket", "jungle", "kitten", "ladder",
+    "lizard", "marble", "meadow", "mirror", "monkey", "needle", "orange",
+    "oyster", "palace", "parrot", "pencil", "pepper", "pirate", "planet",
+    "pocket", "potato", "puzzle", "rabbit", "saddle", "silver", "spider",
+    "spring", "squash", "statue", "summer", "tomato", "travel", "turtle",
+    "violin", "walnut", "window", "winter", "wizard", "yellow", "acrobat",
+    "balloon", "bicycle", "blanket", "cabbage", "captain", "cartoon",
+    "chimney", "compass", "concert", "cupcake", "diamond", "dolphin",
+    "emerald", "feather", "giraffe", "glacier", "harvest", "holiday",
+    "iceberg", "journey", "kitchen", "lantern", "library", "lobster",
+    "mammoth", "mansion", "monster", "morning", "mustard", "octopus",
+    "orchard", "pancake", "panther", "peacock", "penguin", "picture",
+    "pumpkin", "pyramid", "rainbow", "sandals", "sausage", "scholar",
+    "skeleton", "snowman", "soldier", "station", "sunrise", "teacher",
+    "thunder", "trumpet", "unicorn", "vampire", "village", "volcano",
+    "weather", "whistle", "alligator", "astronaut", "avalanche", "blueberry",
+    "butterfly", "carnival", "chocolate", "cinnamon", "crocodile", "dandelion",
+    "detective", "dinosaur", "elephant", "envelope", "firework", "flamingo",
+    "football", "gardening", "goldfish", "grasshopper", "hamburger",
+    "hedgehog", "horizon", "hurricane", "kangaroo", "labyrinth", "lighthouse",
+    "marathon", "mushroom", "notebook", "nightmare", "ostrich", "pineapple",
+    "porcupine", "raspberry", "reindeer", "sandwich", "scorpion", "seahorse",
+    "spaghetti", "squirrel", "strawberry", "submarine", "sunflower",
+    "telescope", "tornado", "treasure", "umbrella", "waterfall", "woodpecker",
+    "adventure", "archipelago", "basketball", "blacksmith", "boomerang",
+    "caterpillar", "chandelier", "chimpanzee", "constellation", "crossroads",
+    "dictionary", "encyclopedia", "expedition", "grandfather", "hippopotamus",
+    "hummingbird", "kaleidoscope", "lumberjack", "marshmallow", "mathematics",
+    "microscope", "moonlight", "motorcycle", "orchestra", "parachute",
+    "philosophy", "photograph", "playground", "rhinoceros", "saxophone",
+    "skyscraper", "snowflake", "stethoscope", "tambourine", "thermometer",
+    "trampoline", "typewriter", "wheelbarrow", "xylophone",
+];
+
+// Picks a random word from WORDS.
+pub fn random_word() -> &'static str {
+    WORDS.choose(&mut rand::thread_rng()).expect("word list is not empty")
+}
+
+// Picks a random word from WORDS whose length matches the difficulty.
+pub fn random_word_by_difficulty(level: Difficulty) -> &'static str {
+    let (min, max) = level.length_range();
+    let candidates: Vec<&'static str> = WORDS
+        .iter()
+        .copied()
+        .filter(|w| (min..=max).contains(&w.chars().count()))
+        .collect();
+    candidates.choose(&mut rand::thread_rng()).expect("every difficulty has words")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::hangman;
+
+    #[test]
+    fn random_words_are_plain_lowercase_words() {
+        for _ in 0..100 {
+            let word = random_word();
+            assert!(!word.is_empty());
+            assert!(word.chars().all(|c| c.is_ascii_lowercase()), "{}", word);
+        }
+    }
+
+    #[test]
+    fn the_word_list_only_has_playable_words() {
+        for word in WORDS {
+            assert!(hangman::create_hangman_match("server", word).is_ok(), "{}", word);
+            assert!(word.chars().all(|c| c.is_ascii_lowercase()), "{}", word);
+        }
+    }
+
+    #[test]
+    fn each_difficulty_picks_words_of_its_length() {
+        for level in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            let (min, max) = level.length_range();
+            for _ in 0..100 {
+                let len = random_word_by_difficulty(level).chars().count();
+                assert!(min <= len && len <= max, "{} word of {} letters", level, len);
+            }
+        }
+    }
+
+    #[test]
+    fn difficulties_parse_in_any_case() {
+        assert_eq!("EASY".parse(), Ok(Difficulty::Easy));
+        assert_eq!("medium".parse(), Ok(Difficulty::Medium));
+        assert_eq!("Hard".parse::<Difficulty>().map(|level| level.to_string()), Ok(String::from("hard")));
+        assert!("extreme".parse::<Difficulty>().is_err());
+    }
+}
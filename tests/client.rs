@@ -0,0 +1,115 @@
+// Drives the client binary against a real server.
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use common::*;
+
+// Starts the client binary with `args`. Its input is a pipe, so it only
+// does what the command line says until the test writes to it.
+fn run_client(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_client"))
+        .args(args)
+        .env_remove("CHAT_SERVER")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+// What the client prints, line by line.
+fn output(client: &mut Child) -> Receiver<String> {
+    let stdout = BufReader::new(client.stdout.take().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdout.lines() {
+            if tx.send(line.unwrap()).is_err() { break; }
+        }
+    });
+    rx
+}
+
+#[test]
+fn the_client_takes_the_server_and_the_name_from_its_arguments() {
+    let server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let bob = server.join("bob");
+
+    let mut ann = run_client(&["--no-color", "--server", &server.addr, "ann"]);
+
+    wait_for(&bob, |line| line == "ann joined");
+    ann.kill().unwrap();
+    ann.wait().unwrap();
+}
+
+#[test]
+fn the_client_reconnects_after_a_server_restart() {
+    let mut server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let bob = server.join("bob");
+    let mut ann = run_client(&["--no-color", "--reconnect", "--server", &server.addr, "ann"]);
+    wait_for(&bob, |line| line == "ann joined");
+
+    let addr = server.addr.clone();
+    server.kill();
+    let mut server = TestServer::start(&[("SERVER_ADDR", &addr), ("SERVER_TIMESTAMPS", "off")]);
+    server.addr = addr;
+    let bob = server.join("bob");
+
+    // the first retries back off for a few seconds in total
+    let deadline = Instant::now() + 3 * WAIT;
+    loop {
+        bob.send(":list").unwrap();
+        if wait_for(&bob, |line| line.starts_with("connected:")).lines().any(|name| name == "ann") {
+            break;
+        }
+        assert!(Instant::now() < deadline, "the client never came back");
+        thread::sleep(Duration::from_millis(100));
+    }
+    ann.kill().unwrap();
+    ann.wait().unwrap();
+}
+
+#[test]
+fn a_lurking_client_reads_but_does_not_send() {
+    let server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    // lurkers may still take a name; it is a command, not chat
+    let mut lurker = run_client(&["--no-color", "--lurk", "--server", &server.addr, "lurky"]);
+    let printed = output(&mut lurker);
+    wait_for(&ann, |line| line == "lurky joined");
+
+    writeln!(lurker.stdin.as_ref().unwrap(), "hello from the shadows").unwrap();
+    ann.send("anyone there?").unwrap();
+
+    while printed.recv_timeout(WAIT).expect("the lurker never printed the chat") != "ann: anyone there?" {}
+    assert!(drain(&ann, Duration::from_millis(300)).iter().all(|line| !line.contains("shadows")));
+    lurker.kill().unwrap();
+    lurker.wait().unwrap();
+}
+
+#[test]
+fn the_end_of_the_input_quits_the_client() {
+    let server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let bob = server.join("bob");
+
+    for (name, lurk) in [("ann", false), ("lurky", true)] {
+        let mut args = vec!["--no-color", "--server", &server.addr, name];
+        if lurk {
+            args.insert(1, "--lurk");
+        }
+        let mut client = run_client(&args);
+        wait_for(&bob, |line| line == format!("{} joined", name));
+
+        drop(client.stdin.take());
+
+        wait_for(&bob, |line| line == format!("{} left", name));
+        let deadline = Instant::now() + WAIT;
+        while client.try_wait().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "the client kept running after its input ended");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// Runs a server binary on a free local port for the length of a test.
+#![allow(dead_code)]
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use chatproject::shared::client::ChatClient;
+use rustls::ClientConfig;
+
+// How long a test waits for a line it expects.
+pub const WAIT: Duration = Duration::from_secs(5);
+
+// A server process, killed when dropped. It runs in a scratch directory of
+// its own so ban lists and saved games do not end up in the repository.
+pub struct TestServer {
+    pub addr: String,
+    pub dir: PathBuf,
+    child: Child,
+    // server_async sends no roster on :name
+    roster_on_join: bool,
+}
+
+impl TestServer {
+    // Starts the threaded server with `env` on top of SERVER_ADDR.
+    pub fn start(env: &[(&str, &str)]) -> TestServer {
+        TestServer::start_bin(env!("CARGO_BIN_EXE_server"), env)
+    }
+
+    pub fn start_async(env: &[(&str, &str)]) -> TestServer {
+        TestServer::start_bin(env!("CARGO_BIN_EXE_server_async"), env)
+    }
+
+    fn start_bin(bin: &str, env: &[(&str, &str)]) -> TestServer {
+        let addr = free_addr();
+        let dir = std::env::temp_dir().join(format!("chatproject-test-{}-{}", std::process::id(), addr.replace([':', '.'], "-")));
+        std::fs::create_dir_all(&dir).unwrap();
+        let child = Command::new(bin)
+            .current_dir(&dir)
+            .env("SERVER_ADDR", &addr)
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        TestServer { addr, dir, child, roster_on_join: bin == env!("CARGO_BIN_EXE_server") }
+    }
+
+    // Connects as a new client. The server may still be starting up, so
+    // refused connections are retried; probing it any other way would take
+    // up a client id.
+    pub fn connect(&self) -> ChatClient {
+        self.retry(|| ChatClient::connect(&self.addr))
+    }
+
+    // Connects over TLS, trusting `config`'s roots.
+    pub fn connect_tls(&self, config: Arc<ClientConfig>) -> ChatClient {
+        self.retry(|| ChatClient::connect_tls(&self.addr, config.clone()))
+    }
+
+    fn retry(&self, connect: impl Fn() -> io::Result<ChatClient>) -> ChatClient {
+        let deadline = Instant::now() + WAIT;
+        loop {
+            match connect() {
+                Ok(client) => return client,
+                Err(e) => assert!(Instant::now() < deadline, "could not connect to {}: {}", self.addr, e),
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    // Connects and takes the name `name`, returning once the server agreed.
+    pub fn join(&self, name: &str) -> ChatClient {
+        let client = self.connect();
+        client.send(&format!(":name {}", name)).unwrap();
+        if !self.roster_on_join {
+            client.send(":list").unwrap();
+        }
+        wait_for(&client, |line| line.starts_with("connected:"));
+        client
+    }
+
+    // Threads the server process is running right now.
+    #[cfg(target_os = "linux")]
+    pub fn threads(&self) -> usize {
+        std::fs::read_dir(format!("/proc/{}/task", self.child.id())).unwrap().count()
+    }
+
+    // Sends SIGINT, as Ctrl-C would, and waits for the server to exit.
+    pub fn interrupt(&mut self) {
+        let status = Command::new("kill").arg("-INT").arg(self.child.id().to_string()).status().unwrap();
+        assert!(status.success());
+        let deadline = Instant::now() + WAIT;
+        while self.child.try_wait().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "the server ignored SIGINT");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.kill();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// A file in tests/fixtures, e.g. the self-signed certificate for
+// 127.0.0.1 (cert.pem, key.pem).
+pub fn fixture(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name).to_string_lossy().into_owned()
+}
+
+// A local address nothing listens on right now.
+pub fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+// Skips lines until one matches and returns it; fails the test if none
+// arrives in time.
+pub fn wait_for(client: &ChatClient, matches: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + WAIT;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match client.recv_timeout(left) {
+            Some(line) if matches(&line) => return line,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    panic!("the expected line never arrived");
+}
+
+// Every line that arrives within `quiet` of the last one.
+pub fn drain(client: &ChatClient, quiet: Duration) -> Vec<String> {
+    let mut lines = Vec::new();
+    while let Some(line) = client.recv_timeout(quiet) {
+        lines.push(line);
+    }
+    lines
+}
@@ -0,0 +1,358 @@
+// The threaded server over real sockets, driven through ChatClient.
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use chatproject::shared::framing::{read_frame, write_frame};
+use chatproject::shared::protocol::Hello;
+use chatproject::shared::transport::client_config;
+use common::*;
+
+#[test]
+fn invalid_utf8_does_not_stop_the_server() {
+    let server = TestServer::start(&[]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+    let mut raw = TcpStream::connect(&server.addr).unwrap();
+    write_frame(&mut raw, &Hello::ours().to_text()).unwrap();
+    raw.write_all(&[0, 0, 0, 3, 0xff, 0xfe, 0xfd]).unwrap();
+    write_frame(&mut raw, ":list").unwrap();
+
+    // the sender itself is still served, after its bad frame
+    while !read_frame(&mut raw).unwrap().unwrap().starts_with("connected:") {}
+    ann.send("still working?").unwrap();
+
+    wait_for(&bob, |line| line.ends_with("ann: still working?"));
+}
+
+#[test]
+fn ctrl_c_says_goodbye_before_closing() {
+    let mut server = TestServer::start(&[]);
+    let ann = server.join("ann");
+
+    server.interrupt();
+
+    wait_for(&ann, |line| line == "server shutting down");
+    assert_eq!(ann.recv_timeout(WAIT), None);
+}
+
+#[test]
+fn messages_are_relayed_without_polling_delays() {
+    let server = TestServer::start(&[("SERVER_RATE", "1000"), ("SERVER_BURST", "1000")]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+    wait_for(&ann, |line| line == "bob joined");
+
+    // Twenty relays each way. The old loop slept 100ms between polls, so
+    // this took about two seconds.
+    let start = Instant::now();
+    for i in 0..20 {
+        ann.send(&format!("ping {}", i)).unwrap();
+        wait_for(&bob, |line| line.ends_with(&format!("ann: ping {}", i)));
+        bob.send(&format!("pong {}", i)).unwrap();
+        wait_for(&ann, |line| line.ends_with(&format!("bob: pong {}", i)));
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_millis(800), "40 relays took {:?}", elapsed);
+}
+
+#[test]
+fn a_client_that_never_reads_does_not_hold_up_the_others() {
+    let server = TestServer::start(&[("SERVER_RATE", "1000"), ("SERVER_BURST", "1000")]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+    let mut staller = TcpStream::connect(&server.addr).unwrap();
+    write_frame(&mut staller, &Hello::ours().to_text()).unwrap();
+    write_frame(&mut staller, ":name staller").unwrap();
+    wait_for(&bob, |line| line == "staller joined");
+
+    // more than the staller's queue holds; its socket takes the rest
+    let start = Instant::now();
+    for i in 0..400 {
+        ann.send(&format!("{} {}", i, "x".repeat(3900))).unwrap();
+        wait_for(&bob, |line| line.contains(&format!("ann: {} x", i)));
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_secs(3), "400 messages took {:?}", elapsed);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_removed_clients_threads_end() {
+    let server = TestServer::start(&[("SERVER_OP_PASSWORD", "hunter2")]);
+    let ann = server.join("ann");
+    ann.send(":op hunter2").unwrap();
+    wait_for(&ann, |line| line == "you are now an operator");
+    let before = server.threads();
+    let bob = server.join("bob");
+    assert_eq!(server.threads(), before + 2);
+
+    ann.send(":kick bob").unwrap();
+
+    wait_for(&bob, |line| line.starts_with("you were kicked"));
+    assert_eq!(bob.recv_timeout(WAIT), None);
+    let deadline = Instant::now() + WAIT;
+    while server.threads() > before {
+        assert!(Instant::now() < deadline, "the reader and writer threads are still running");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn an_over_limit_message_is_rejected_and_the_sender_stays() {
+    let server = TestServer::start(&[("SERVER_MAX_MSG", "100")]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+
+    ann.send(&"x".repeat(101)).unwrap();
+    wait_for(&ann, |line| line == "message rejected: longer than 100 bytes");
+    ann.send("short enough").unwrap();
+
+    let line = wait_for(&bob, |line| line.contains("ann: "));
+    assert!(line.ends_with("ann: short enough"), "{}", line);
+}
+
+#[test]
+fn the_first_client_is_listed_as_guest_1() {
+    let server = TestServer::start(&[]);
+    let client = server.connect();
+
+    client.send(":list").unwrap();
+
+    assert_eq!(wait_for(&client, |line| line.starts_with("connected:")), "connected:\nguest-1\n");
+}
+
+#[test]
+fn a_dropped_connection_is_announced_and_unlisted() {
+    let server = TestServer::start(&[]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+
+    bob.close();
+
+    wait_for(&ann, |line| line == "bob left");
+    ann.send(":list").unwrap();
+    assert_eq!(wait_for(&ann, |line| line.starts_with("connected:")), "connected:\nann\n");
+}
+
+#[test]
+fn the_chat_log_gets_every_broadcast() {
+    let mut server = TestServer::start(&[("SERVER_LOG_FILE", "chat.log"), ("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+    ann.send("hello bob").unwrap();
+    wait_for(&bob, |line| line == "ann: hello bob");
+
+    // stopping flushes the log
+    server.interrupt();
+
+    let log = std::fs::read_to_string(server.dir.join("chat.log")).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(lines.iter().any(|line| line.ends_with("] [lobby] ann: hello bob")), "{}", log);
+    assert!(lines.last().unwrap().ends_with("] [*] server shutting down"), "{}", log);
+}
+
+#[test]
+fn a_netcat_user_can_chat_in_lines() {
+    let server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    let mut nc = TcpStream::connect(&server.addr).unwrap();
+    nc.set_read_timeout(Some(WAIT)).unwrap();
+    let mut lines = BufReader::new(nc.try_clone().unwrap()).lines();
+
+    nc.write_all(b":name nc\r\nhi from netcat\n").unwrap();
+    wait_for(&ann, |line| line == "nc: hi from netcat");
+    ann.send("hi back").unwrap();
+
+    assert!(lines.by_ref().map(Result::unwrap).any(|line| line == "ann: hi back"));
+}
+
+#[test]
+fn clients_can_chat_over_tls() {
+    let server = TestServer::start(&[("SERVER_TLS_CERT", &fixture("cert.pem")), ("SERVER_TLS_KEY", &fixture("key.pem"))]);
+    let config = client_config(Path::new(&fixture("cert.pem"))).unwrap();
+    let ann = server.connect_tls(config.clone());
+    let bob = server.connect_tls(config);
+    ann.send(":name ann").unwrap();
+    wait_for(&ann, |line| line.starts_with("connected:"));
+
+    ann.send("secret hello").unwrap();
+
+    wait_for(&bob, |line| line.ends_with("ann: secret hello"));
+}
+
+#[test]
+fn a_tls_server_does_not_talk_plain_tcp() {
+    let server = TestServer::start(&[("SERVER_TLS_CERT", &fixture("cert.pem")), ("SERVER_TLS_KEY", &fixture("key.pem"))]);
+    let config = client_config(Path::new(&fixture("cert.pem"))).unwrap();
+    let ann = server.connect_tls(config);
+    ann.send(":name ann").unwrap();
+    wait_for(&ann, |line| line.starts_with("connected:"));
+
+    let plain = server.connect();
+    plain.send(":list").unwrap();
+
+    assert_eq!(plain.recv_timeout(WAIT), None);
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn a_browser_and_a_tcp_client_can_chat() {
+    use tungstenite::Message;
+
+    let ws_addr = free_addr();
+    let server = TestServer::start(&[("SERVER_WS_ADDR", &ws_addr), ("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    let tcp = TcpStream::connect(&ws_addr).unwrap();
+    tcp.set_read_timeout(Some(WAIT)).unwrap();
+    let (mut browser, _) = tungstenite::client(format!("ws://{}/", ws_addr), tcp).unwrap();
+
+    browser.send(Message::text(":name webby")).unwrap();
+    browser.send(Message::text("hi from the browser")).unwrap();
+    wait_for(&ann, |line| line == "webby: hi from the browser");
+    ann.send("hi browser").unwrap();
+
+    loop {
+        if let Message::Text(text) = browser.read().unwrap()
+            && text.as_str() == "ann: hi browser"
+        {
+            break;
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn a_browser_gets_its_pong_and_close_reply() {
+    use tungstenite::Message;
+
+    let ws_addr = free_addr();
+    let server = TestServer::start(&[("SERVER_WS_ADDR", &ws_addr), ("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    let tcp = TcpStream::connect(&ws_addr).unwrap();
+    tcp.set_read_timeout(Some(WAIT)).unwrap();
+    let (mut browser, _) = tungstenite::client(format!("ws://{}/", ws_addr), tcp).unwrap();
+    browser.send(Message::text(":name webby")).unwrap();
+    wait_for(&ann, |line| line == "webby joined");
+
+    browser.send(Message::Ping("are you there".into())).unwrap();
+    loop {
+        if let Message::Pong(payload) = browser.read().unwrap() {
+            assert_eq!(&payload[..], b"are you there");
+            break;
+        }
+    }
+
+    browser.close(None).unwrap();
+    loop {
+        match browser.read() {
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(e) => panic!("no close reply: {}", e),
+        }
+    }
+    wait_for(&ann, |line| line == "webby left");
+}
+
+#[test]
+fn the_server_listens_on_every_address_in_the_list() {
+    let (first, second) = (free_addr(), free_addr());
+    let mut server = TestServer::start(&[("SERVER_ADDR", &format!("{},{}", first, second)), ("SERVER_TIMESTAMPS", "off")]);
+    server.addr = first;
+    let ann = server.join("ann");
+    server.addr = second;
+    let bob = server.join("bob");
+
+    ann.send("hello across ports").unwrap();
+
+    wait_for(&bob, |line| line == "ann: hello across ports");
+}
+
+#[test]
+fn two_chat_clients_register_and_talk() {
+    let server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.connect();
+    let bob = server.connect();
+
+    ann.send(":name ann").unwrap();
+    wait_for(&ann, |line| line.starts_with("connected:"));
+    bob.send(":name bob").unwrap();
+    wait_for(&ann, |line| line == "bob joined");
+    bob.send("hi ann").unwrap();
+
+    wait_for(&ann, |line| line == "bob: hi ann");
+    ann.close();
+    wait_for(&bob, |line| line.starts_with("ann "));
+}
+
+#[test]
+fn a_client_with_a_newer_protocol_is_turned_away_politely() {
+    let server = TestServer::start(&[]);
+    let _ann = server.join("ann");
+    let mut raw = TcpStream::connect(&server.addr).unwrap();
+    raw.set_read_timeout(Some(WAIT)).unwrap();
+
+    write_frame(&mut raw, ":hello 99").unwrap();
+
+    let refusal = read_frame(&mut raw).unwrap().unwrap();
+    assert!(refusal.starts_with("protocol: version 99 is not supported"), "{}", refusal);
+    assert!(matches!(read_frame(&mut raw), Ok(None) | Err(_)));
+}
+
+#[test]
+fn a_client_with_our_protocol_gets_a_hello_back() {
+    let server = TestServer::start(&[]);
+    let _ann = server.join("ann");
+    let mut raw = TcpStream::connect(&server.addr).unwrap();
+    raw.set_read_timeout(Some(WAIT)).unwrap();
+
+    write_frame(&mut raw, &Hello::ours().to_text()).unwrap();
+
+    let hello = Hello::parse(&read_frame(&mut raw).unwrap().unwrap()).unwrap();
+    assert_eq!(hello.version, Hello::ours().version);
+}
+
+#[test]
+fn a_ping_token_comes_back_unchanged() {
+    let server = TestServer::start(&[]);
+    let ann = server.join("ann");
+
+    ann.send(":ping 1700000000000").unwrap();
+
+    wait_for(&ann, |line| line == ":pong 1700000000000");
+}
+
+#[test]
+fn a_client_past_the_limit_is_turned_away() {
+    let server = TestServer::start(&[("SERVER_MAX_CLIENTS", "2"), ("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    let _bob = server.join("bob");
+    drain(&ann, Duration::from_millis(200));
+
+    let carl = server.connect();
+
+    assert_eq!(carl.recv_timeout(WAIT).as_deref(), Some("server full: too many clients are connected, try again later"));
+    assert_eq!(carl.recv_timeout(WAIT), None);
+    ann.send(":list").unwrap();
+    assert_eq!(wait_for(&ann, |line| line.starts_with("connected:")), "connected:\nann\nbob\n");
+    assert!(drain(&ann, Duration::from_millis(200)).is_empty());
+}
+
+#[test]
+fn quitting_is_announced_right_away() {
+    let server = TestServer::start(&[("SERVER_TIMESTAMPS", "off")]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+    drain(&ann, Duration::from_millis(200));
+
+    let start = Instant::now();
+    ann.send(":quit").unwrap();
+
+    wait_for(&bob, |line| line == "ann left");
+    assert!(start.elapsed() < Duration::from_secs(1), "took {:?}", start.elapsed());
+    // the server closes the connection; the client did not
+    assert_eq!(ann.recv_timeout(WAIT), None);
+}
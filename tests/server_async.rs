@@ -0,0 +1,82 @@
+// The tokio server over real sockets, driven through ChatClient.
+mod common;
+
+use std::collections::HashSet;
+use std::thread;
+use common::*;
+
+#[test]
+fn a_hundred_clients_can_chat_at_once() {
+    let server = TestServer::start_async(&[]);
+    let watcher = server.join("watcher");
+
+    let clients: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..100)
+            .map(|i| {
+                let server = &server;
+                scope.spawn(move || {
+                    let client = server.connect();
+                    client.send(&format!(":name user{}", i)).unwrap();
+                    client.send(&format!("hello from user{}", i)).unwrap();
+                    client
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    let mut heard = HashSet::new();
+    while heard.len() < 100 {
+        let line = wait_for(&watcher, |line| line.contains(": hello from user"));
+        heard.insert(line);
+    }
+    watcher.send("everyone made it").unwrap();
+    for client in &clients {
+        wait_for(client, |line| line == "watcher: everyone made it");
+    }
+}
+
+#[test]
+fn a_client_that_hangs_up_is_announced_as_left() {
+    let server = TestServer::start_async(&[]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+    wait_for(&ann, |line| line == "bob joined");
+
+    bob.close();
+
+    wait_for(&ann, |line| line == "bob left");
+}
+
+#[test]
+fn a_bad_name_is_refused() {
+    let server = TestServer::start_async(&[]);
+    let _ann = server.join("ann");
+    let bob = server.connect();
+
+    bob.send(":name bo b").unwrap();
+    bob.send(&format!(":name {}", "b".repeat(500))).unwrap();
+    bob.send(":list").unwrap();
+
+    wait_for(&bob, |line| line.starts_with("invalid name: spaces"));
+    wait_for(&bob, |line| line.starts_with("invalid name: longer than"));
+    let list = wait_for(&bob, |line| line.starts_with("connected:"));
+    assert!(list.starts_with("connected:\nann\n") && !list.contains("bo") && list.lines().count() == 3, "{}", list);
+}
+
+#[test]
+fn an_over_limit_message_is_rejected_and_repeats_disconnect() {
+    let server = TestServer::start_async(&[("SERVER_MAX_MSG", "100")]);
+    let ann = server.join("ann");
+    let bob = server.join("bob");
+
+    ann.send(&"x".repeat(101)).unwrap();
+    wait_for(&ann, |line| line == "message rejected: longer than 100 bytes");
+    ann.send("short enough").unwrap();
+    wait_for(&bob, |line| line == "ann: short enough");
+
+    ann.send(&"x".repeat(101)).unwrap();
+    ann.send(&"x".repeat(101)).unwrap();
+    wait_for(&ann, |line| line == "too many oversized messages, disconnecting");
+    wait_for(&bob, |line| line == "ann left");
+}
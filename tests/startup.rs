@@ -0,0 +1,67 @@
+// The binaries report startup problems as errors instead of panicking.
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Runs `bin` with SERVER_ADDR set to `addr` in a scratch directory and
+// returns its output; a server still running after a few seconds is
+// killed, which fails the test.
+fn run_server(bin: &str, addr: &str) -> Output {
+    let name = Path::new(bin).file_name().unwrap().to_string_lossy();
+    let dir = std::env::temp_dir().join(format!("chatproject-startup-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut child = Command::new(bin)
+        .current_dir(&dir)
+        .env("SERVER_ADDR", addr)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            panic!("{} did not exit", bin);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn a_bad_server_addr_is_an_error_not_a_panic() {
+    let output = run_server(env!("CARGO_BIN_EXE_server"), "not an address");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Error: could not listen on not an address"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn a_bad_server_addr_stops_the_async_server_with_an_error() {
+    let output = run_server(env!("CARGO_BIN_EXE_server_async"), "nonsense");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Error: could not bind nonsense"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn a_port_in_use_is_reported_with_its_address() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = taken.local_addr().unwrap().to_string();
+
+    let output = run_server(env!("CARGO_BIN_EXE_server"), &addr);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains(&format!("Error: could not listen on {}: Address already in use", addr)), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+
+    let output = run_server(env!("CARGO_BIN_EXE_server_async"), &addr);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains(&format!("Error: could not bind {}: Address already in use", addr)), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}